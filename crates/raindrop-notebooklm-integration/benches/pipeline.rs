@@ -0,0 +1,64 @@
+//! Fetch throughput against a local server with 50ms of latency per page.
+//!
+//! ```text
+//! cargo bench -p raindrop-notebooklm-integration --bench pipeline
+//! ```
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+use std::time::{Duration, Instant};
+
+use raindrop_notebooklm_integration::content::ContentFetcher;
+use raindrop_notebooklm_integration::pipeline::{FetchLimits, Pipeline};
+use support::SlowServer;
+
+const URLS: usize = 400;
+const LATENCY: Duration = Duration::from_millis(50);
+
+#[tokio::main]
+async fn main() {
+    let server = SlowServer::start(LATENCY);
+    let runs = [
+        ("sequential", 1, 1),
+        ("4 at once", 4, 4),
+        ("16 at once", 16, 16),
+        ("64 at once", 64, 64),
+    ];
+    println!(
+        "{} pages from one host, {}ms latency each\n",
+        URLS,
+        LATENCY.as_millis()
+    );
+    println!(
+        "{:<12} {:>10} {:>12} {:>14}",
+        "limits", "elapsed", "pages/s", "peak in flight"
+    );
+    for (name, concurrency, per_host) in runs {
+        server.reset();
+        let limits = FetchLimits {
+            concurrency,
+            per_host,
+            host_delay: Duration::ZERO,
+        };
+        let started = Instant::now();
+        let mut results = Pipeline::new(ContentFetcher::new(), limits).run(
+            (0..URLS)
+                .map(|n| (n, server.url(&format!("/{}", n))))
+                .collect(),
+        );
+        let mut fetched = 0;
+        while let Some((_, result, _)) = results.recv().await {
+            fetched += usize::from(result.is_ok());
+        }
+        let elapsed = started.elapsed();
+        assert_eq!(fetched, URLS);
+        println!(
+            "{:<12} {:>9.2}s {:>12.1} {:>14}",
+            name,
+            elapsed.as_secs_f64(),
+            URLS as f64 / elapsed.as_secs_f64(),
+            server.peak()
+        );
+    }
+}
@@ -0,0 +1,723 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::credentials::{self, CredentialStore, Secret};
+use crate::error::{AppError, ConfigError, CredentialError, Result};
+use crate::redact::{self, REDACTED};
+use crate::{content, notebooklm, oauth, pipeline, raindrop, retry};
+
+pub const PROJECT_CONFIG_FILE: &str = "raindrop-notebooklm.toml";
+const USER_CONFIG_DIR: &str = "raindrop-notebooklm";
+const USER_CONFIG_FILE: &str = "config.toml";
+const DEFAULT_OUTPUT_DIR: &str = "./output";
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Where a resolved configuration value came from, in increasing precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    UserFile(PathBuf),
+    ProjectFile(PathBuf),
+    /// A `[profiles.<name>]` table inside one of the files above.
+    Profile {
+        file: PathBuf,
+        name: String,
+    },
+    Env(&'static str),
+    Cli,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::UserFile(path) => write!(f, "user file {}", path.display()),
+            ConfigSource::ProjectFile(path) => write!(f, "project file {}", path.display()),
+            ConfigSource::Profile { file, name } => {
+                write!(f, "profile '{}' in {}", name, file.display())
+            }
+            ConfigSource::Env(var) => write!(f, "env {}", var),
+            ConfigSource::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+/// One partial set of values as it appears in a file, the environment or on
+/// the command line. Every field is optional so layers can be stacked.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    pub raindrop_api_key: Option<Secret>,
+    pub raindrop_base_url: Option<String>,
+    pub raindrop_client_id: Option<String>,
+    pub raindrop_client_secret: Option<Secret>,
+    pub raindrop_oauth_url: Option<String>,
+    pub notebooklm_access_token: Option<Secret>,
+    pub notebooklm_token_command: Option<String>,
+    pub notebooklm_endpoint: Option<String>,
+    pub notebooklm_project: Option<String>,
+    pub notebooklm_location: Option<String>,
+    pub notebook_title: Option<String>,
+    pub notebook_max_sources: Option<usize>,
+    pub notebook_max_words: Option<usize>,
+    pub fetch_concurrency: Option<usize>,
+    pub fetch_per_host: Option<usize>,
+    pub fetch_host_delay_ms: Option<u64>,
+    pub fetch_max_bytes: Option<u64>,
+    pub retry_max_retries: Option<u32>,
+    pub retry_base_delay_ms: Option<u64>,
+    pub retry_max_delay_ms: Option<u64>,
+    pub retry_budget: Option<u32>,
+    pub max_urls: Option<usize>,
+    pub output_dir: Option<PathBuf>,
+    pub state_dir: Option<PathBuf>,
+    /// Named overrides selected with `--profile`; only valid at file top level.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ConfigLayer>,
+}
+
+impl ConfigLayer {
+    pub fn from_toml(text: &str) -> std::result::Result<ConfigLayer, ConfigError> {
+        let layer: ConfigLayer = toml::from_str(text).map_err(ConfigError::Syntax)?;
+        match layer
+            .profiles
+            .iter()
+            .find(|(_, profile)| !profile.profiles.is_empty())
+        {
+            Some((name, _)) => Err(ConfigError::Invalid(format!(
+                "profile '{}' cannot contain nested profiles",
+                name
+            ))),
+            None => Ok(layer),
+        }
+    }
+
+    fn merge(self, over: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            raindrop_api_key: over.raindrop_api_key.or(self.raindrop_api_key),
+            raindrop_base_url: over.raindrop_base_url.or(self.raindrop_base_url),
+            raindrop_client_id: over.raindrop_client_id.or(self.raindrop_client_id),
+            raindrop_client_secret: over.raindrop_client_secret.or(self.raindrop_client_secret),
+            raindrop_oauth_url: over.raindrop_oauth_url.or(self.raindrop_oauth_url),
+            notebooklm_access_token: over
+                .notebooklm_access_token
+                .or(self.notebooklm_access_token),
+            notebooklm_token_command: over
+                .notebooklm_token_command
+                .or(self.notebooklm_token_command),
+            notebooklm_endpoint: over.notebooklm_endpoint.or(self.notebooklm_endpoint),
+            notebooklm_project: over.notebooklm_project.or(self.notebooklm_project),
+            notebooklm_location: over.notebooklm_location.or(self.notebooklm_location),
+            notebook_title: over.notebook_title.or(self.notebook_title),
+            notebook_max_sources: over.notebook_max_sources.or(self.notebook_max_sources),
+            notebook_max_words: over.notebook_max_words.or(self.notebook_max_words),
+            fetch_concurrency: over.fetch_concurrency.or(self.fetch_concurrency),
+            fetch_per_host: over.fetch_per_host.or(self.fetch_per_host),
+            fetch_host_delay_ms: over.fetch_host_delay_ms.or(self.fetch_host_delay_ms),
+            fetch_max_bytes: over.fetch_max_bytes.or(self.fetch_max_bytes),
+            retry_max_retries: over.retry_max_retries.or(self.retry_max_retries),
+            retry_base_delay_ms: over.retry_base_delay_ms.or(self.retry_base_delay_ms),
+            retry_max_delay_ms: over.retry_max_delay_ms.or(self.retry_max_delay_ms),
+            retry_budget: over.retry_budget.or(self.retry_budget),
+            max_urls: over.max_urls.or(self.max_urls),
+            output_dir: over.output_dir.or(self.output_dir),
+            state_dir: over.state_dir.or(self.state_dir),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub profile: String,
+    pub raindrop_api_key: Option<Secret>,
+    pub raindrop_base_url: String,
+    /// The registered OAuth app; with it, Raindrop is accessed with the
+    /// tokens `auth login raindrop --oauth` stored instead of an API key.
+    pub raindrop_client_id: Option<String>,
+    pub raindrop_client_secret: Option<Secret>,
+    pub raindrop_oauth_url: String,
+    /// An OAuth access token for the Discovery Engine API, which accepts no
+    /// API keys. Such tokens expire after about an hour, so long syncs are
+    /// better served by a command that prints a fresh one, e.g.
+    /// `gcloud auth print-access-token`, which is run again when the token
+    /// is rejected.
+    pub notebooklm_access_token: Option<Secret>,
+    pub notebooklm_token_command: Option<String>,
+    pub notebooklm_endpoint: String,
+    pub notebooklm_project: Option<String>,
+    pub notebooklm_location: String,
+    pub notebook_title: Option<String>,
+    /// Limits of a single notebook; larger selections are split across
+    /// several notebooks.
+    pub notebook_max_sources: usize,
+    pub notebook_max_words: usize,
+    /// Pages fetched at once, in total and from any one host, and the
+    /// minimum time between two requests to the same host.
+    pub fetch_concurrency: usize,
+    pub fetch_per_host: usize,
+    pub fetch_host_delay_ms: u64,
+    /// Largest response body read for a page; larger pages fail as too large.
+    pub fetch_max_bytes: u64,
+    /// Retries per request and per run, and the bounds of the exponential
+    /// backoff between them; a server's `Retry-After` beyond the maximum
+    /// delay is not waited for.
+    pub retry_max_retries: u32,
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    pub retry_budget: u32,
+    pub max_urls: Option<usize>,
+    pub output_dir: PathBuf,
+    /// Per-profile directory for sync state; always ends in the profile name
+    /// so two profiles can never share state even if they share a base path.
+    pub state_dir: PathBuf,
+    sources: BTreeMap<&'static str, ConfigSource>,
+    credentials: Option<CredentialStore>,
+}
+
+/// A single line of `config show --resolved`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigEntry {
+    pub key: &'static str,
+    pub value: Option<String>,
+    pub source: Option<ConfigSource>,
+}
+
+impl Config {
+    pub fn source_of(&self, key: &str) -> Option<&ConfigSource> {
+        self.sources.get(key)
+    }
+
+    /// The store `keyring:` references were resolved from, which also
+    /// keeps the OAuth tokens.
+    pub fn credentials(&self) -> Option<&CredentialStore> {
+        self.credentials.as_ref()
+    }
+
+    pub fn require_raindrop_api_key(&self) -> Result<&str> {
+        require(&self.raindrop_api_key, "raindrop_api_key", "RAINDROP_TOKEN")
+    }
+
+    pub fn require_notebooklm_access_token(&self) -> Result<&str> {
+        require(
+            &self.notebooklm_access_token,
+            "notebooklm_access_token",
+            "NOTEBOOKLM_ACCESS_TOKEN",
+        )
+    }
+
+    pub fn require_notebooklm_project(&self) -> Result<&str> {
+        require(
+            &self.notebooklm_project,
+            "notebooklm_project",
+            "NOTEBOOKLM_PROJECT",
+        )
+    }
+
+    /// Every known key with its value and origin. Secrets are replaced by a
+    /// placeholder so the output can be pasted into bug reports.
+    pub fn entries(&self) -> Vec<ConfigEntry> {
+        let secret = |value: &Option<Secret>| value.as_ref().map(|_| REDACTED.to_string());
+        [
+            ("profile", Some(self.profile.clone())),
+            ("raindrop_api_key", secret(&self.raindrop_api_key)),
+            ("raindrop_base_url", Some(self.raindrop_base_url.clone())),
+            ("raindrop_client_id", self.raindrop_client_id.clone()),
+            ("raindrop_client_secret", secret(&self.raindrop_client_secret)),
+            ("raindrop_oauth_url", Some(self.raindrop_oauth_url.clone())),
+            (
+                "notebooklm_access_token",
+                secret(&self.notebooklm_access_token),
+            ),
+            (
+                "notebooklm_token_command",
+                self.notebooklm_token_command.clone(),
+            ),
+            (
+                "notebooklm_endpoint",
+                Some(self.notebooklm_endpoint.clone()),
+            ),
+            ("notebooklm_project", self.notebooklm_project.clone()),
+            (
+                "notebooklm_location",
+                Some(self.notebooklm_location.clone()),
+            ),
+            ("notebook_title", self.notebook_title.clone()),
+            (
+                "notebook_max_sources",
+                Some(self.notebook_max_sources.to_string()),
+            ),
+            (
+                "notebook_max_words",
+                Some(self.notebook_max_words.to_string()),
+            ),
+            (
+                "fetch_concurrency",
+                Some(self.fetch_concurrency.to_string()),
+            ),
+            ("fetch_per_host", Some(self.fetch_per_host.to_string())),
+            (
+                "fetch_host_delay_ms",
+                Some(self.fetch_host_delay_ms.to_string()),
+            ),
+            ("fetch_max_bytes", Some(self.fetch_max_bytes.to_string())),
+            (
+                "retry_max_retries",
+                Some(self.retry_max_retries.to_string()),
+            ),
+            (
+                "retry_base_delay_ms",
+                Some(self.retry_base_delay_ms.to_string()),
+            ),
+            (
+                "retry_max_delay_ms",
+                Some(self.retry_max_delay_ms.to_string()),
+            ),
+            ("retry_budget", Some(self.retry_budget.to_string())),
+            ("max_urls", self.max_urls.map(|max| max.to_string())),
+            ("output_dir", Some(self.output_dir.display().to_string())),
+            ("state_dir", Some(self.state_dir.display().to_string())),
+        ]
+        .into_iter()
+        .map(|(key, value)| ConfigEntry {
+            key,
+            value,
+            source: self.source_of(key).cloned(),
+        })
+        .collect()
+    }
+
+    fn validate(self) -> Result<Config> {
+        let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if self.profile.is_empty() || !self.profile.chars().all(valid_name) {
+            return Err(invalid(format!(
+                "profile name '{}' may only contain letters, digits, '-' and '_'",
+                self.profile
+            )));
+        }
+        if self.max_urls == Some(0) {
+            return Err(invalid("max_urls must be greater than zero".to_string()));
+        }
+        if self.notebook_max_sources == 0 || self.notebook_max_words == 0 {
+            return Err(invalid(
+                "notebook_max_sources and notebook_max_words must be greater than zero".to_string(),
+            ));
+        }
+        if self.retry_base_delay_ms > self.retry_max_delay_ms {
+            return Err(invalid(
+                "retry_base_delay_ms must not exceed retry_max_delay_ms".to_string(),
+            ));
+        }
+        if self.fetch_concurrency == 0 || self.fetch_per_host == 0 {
+            return Err(invalid(
+                "fetch_concurrency and fetch_per_host must be greater than zero".to_string(),
+            ));
+        }
+        if self.fetch_max_bytes == 0 {
+            return Err(invalid(
+                "fetch_max_bytes must be greater than zero".to_string(),
+            ));
+        }
+        [
+            ("raindrop_base_url", &self.raindrop_base_url),
+            ("raindrop_oauth_url", &self.raindrop_oauth_url),
+            ("notebooklm_endpoint", &self.notebooklm_endpoint),
+        ]
+        .into_iter()
+        .find(|(_, url)| !url.starts_with("http://") && !url.starts_with("https://"))
+        .map_or(Ok(()), |(key, url)| {
+            Err(invalid(format!("{} must be an http(s) URL, got '{}'", key, url)))
+        })?;
+        Ok(self)
+    }
+}
+
+/// Stacks configuration layers; later layers override earlier ones key by key.
+#[derive(Debug, Default, Clone)]
+pub struct ConfigBuilder {
+    layers: Vec<(ConfigSource, ConfigLayer)>,
+    /// Resolves `keyring:` references in the API keys.
+    credentials: Option<CredentialStore>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        ConfigBuilder::default().with_layer(
+            ConfigSource::Default,
+            ConfigLayer {
+                raindrop_base_url: Some(raindrop::DEFAULT_BASE_URL.to_string()),
+                raindrop_oauth_url: Some(oauth::DEFAULT_OAUTH_URL.to_string()),
+                notebooklm_endpoint: Some(notebooklm::DEFAULT_ENDPOINT.to_string()),
+                notebooklm_location: Some(notebooklm::DEFAULT_LOCATION.to_string()),
+                notebook_max_sources: Some(notebooklm::DEFAULT_MAX_SOURCES),
+                notebook_max_words: Some(notebooklm::DEFAULT_MAX_WORDS),
+                fetch_concurrency: Some(pipeline::DEFAULT_CONCURRENCY),
+                fetch_per_host: Some(pipeline::DEFAULT_PER_HOST),
+                fetch_host_delay_ms: Some(pipeline::DEFAULT_HOST_DELAY_MS),
+                fetch_max_bytes: Some(content::DEFAULT_MAX_BYTES),
+                retry_max_retries: Some(retry::DEFAULT_MAX_RETRIES),
+                retry_base_delay_ms: Some(retry::DEFAULT_BASE_DELAY_MS),
+                retry_max_delay_ms: Some(retry::DEFAULT_MAX_DELAY_MS),
+                retry_budget: Some(retry::DEFAULT_BUDGET),
+                output_dir: Some(PathBuf::from(DEFAULT_OUTPUT_DIR)),
+                ..ConfigLayer::default()
+            },
+        )
+    }
+
+    pub fn with_layer(mut self, source: ConfigSource, layer: ConfigLayer) -> Self {
+        self.layers.push((source, layer));
+        self
+    }
+
+    pub fn with_user_file(self) -> Result<Self> {
+        match user_config_path() {
+            Some(path) => self.with_optional_file(path, ConfigSource::UserFile),
+            None => Ok(self),
+        }
+    }
+
+    pub fn with_project_file(self, dir: &Path) -> Result<Self> {
+        self.with_optional_file(dir.join(PROJECT_CONFIG_FILE), ConfigSource::ProjectFile)
+    }
+
+    /// Reads a TOML layer if the file exists. A missing file is not an error;
+    /// an unreadable or malformed one is, so typos never go unnoticed.
+    fn with_optional_file(
+        self,
+        path: PathBuf,
+        source: impl FnOnce(PathBuf) -> ConfigSource,
+    ) -> Result<Self> {
+        if !path.exists() {
+            return Ok(self);
+        }
+        let text = std::fs::read_to_string(&path).map_err(|source| {
+            AppError::Config(ConfigError::Read {
+                path: path.clone(),
+                source,
+            })
+        })?;
+        let layer = ConfigLayer::from_toml(&text).map_err(|e| {
+            AppError::Config(ConfigError::File {
+                path: path.clone(),
+                source: Box::new(e),
+            })
+        })?;
+        Ok(self.with_layer(source(path), layer))
+    }
+
+    /// Each recognised variable becomes its own layer so that the reported
+    /// source names the exact variable that supplied the value.
+    pub fn with_env(self, vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let vars: BTreeMap<String, String> = vars.into_iter().collect();
+        ENV_VARS
+            .iter()
+            .filter_map(|&name| vars.get(name).map(|value| (name, value)))
+            .try_fold(self, |builder, (name, value)| {
+                let layer = env_layer(name, value).map_err(|reason| {
+                    AppError::Config(ConfigError::Env { var: name, reason })
+                })?;
+                Ok(builder.with_layer(ConfigSource::Env(name), layer))
+            })
+    }
+
+    pub fn with_cli(self, layer: ConfigLayer) -> Self {
+        self.with_layer(ConfigSource::Cli, layer)
+    }
+
+    pub fn with_credentials(self, credentials: CredentialStore) -> Self {
+        ConfigBuilder {
+            credentials: Some(credentials),
+            ..self
+        }
+    }
+
+    /// Every profile defined in any file, or just the default profile when
+    /// no file defines one.
+    pub fn profile_names(&self) -> Vec<String> {
+        let names: BTreeSet<String> = self
+            .layers
+            .iter()
+            .flat_map(|(_, layer)| layer.profiles.keys().cloned())
+            .collect();
+        if names.is_empty() {
+            vec![DEFAULT_PROFILE.to_string()]
+        } else {
+            names.into_iter().collect()
+        }
+    }
+
+    /// Resolves the configuration for one profile. Each file contributes its
+    /// top-level values followed by its `[profiles.<name>]` table, so a
+    /// profile in the user file still loses to top-level project settings.
+    pub fn build(self, profile: &str) -> Result<Config> {
+        let known = self.profile_names();
+        if profile != DEFAULT_PROFILE && !known.iter().any(|name| name == profile) {
+            return Err(invalid(format!(
+                "unknown profile '{}' (defined profiles: {})",
+                profile,
+                known.join(", ")
+            )));
+        }
+
+        let credentials = self.credentials;
+        let layers: Vec<(ConfigSource, ConfigLayer)> = self
+            .layers
+            .into_iter()
+            .flat_map(|(source, mut layer)| {
+                let section = layer.profiles.remove(profile).and_then(|section| {
+                    source.file().map(|file| {
+                        let source = ConfigSource::Profile {
+                            file: file.to_path_buf(),
+                            name: profile.to_string(),
+                        };
+                        (source, section)
+                    })
+                });
+                std::iter::once((source, layer)).chain(section)
+            })
+            .collect();
+
+        let sources = CONFIG_KEYS
+            .iter()
+            .filter_map(|&(key, is_set)| {
+                layers
+                    .iter()
+                    .rev()
+                    .find(|(_, layer)| is_set(layer))
+                    .map(|(source, _)| (key, source.clone()))
+            })
+            .collect();
+
+        let merged = layers
+            .into_iter()
+            .map(|(_, layer)| layer)
+            .fold(ConfigLayer::default(), ConfigLayer::merge);
+
+        let output_dir = merged
+            .output_dir
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIR));
+        let state_dir = merged
+            .state_dir
+            .unwrap_or_else(|| output_dir.join("state"))
+            .join(profile);
+        let raindrop_api_key = resolve(merged.raindrop_api_key, credentials.as_ref())?;
+        let notebooklm_access_token =
+            resolve(merged.notebooklm_access_token, credentials.as_ref())?;
+        let raindrop_client_secret =
+            resolve(merged.raindrop_client_secret, credentials.as_ref())?;
+        // Known from here on, the keys are masked in whatever they turn up.
+        [
+            &raindrop_api_key,
+            &notebooklm_access_token,
+            &raindrop_client_secret,
+        ]
+        .into_iter()
+        .flatten()
+        .for_each(|key| redact::register_secret(key));
+
+        Config {
+            profile: profile.to_string(),
+            raindrop_api_key,
+            raindrop_base_url: merged
+                .raindrop_base_url
+                .unwrap_or_else(|| raindrop::DEFAULT_BASE_URL.to_string()),
+            raindrop_client_id: merged.raindrop_client_id,
+            raindrop_client_secret,
+            raindrop_oauth_url: merged
+                .raindrop_oauth_url
+                .unwrap_or_else(|| oauth::DEFAULT_OAUTH_URL.to_string()),
+            notebooklm_access_token,
+            notebooklm_token_command: merged.notebooklm_token_command,
+            notebooklm_endpoint: merged
+                .notebooklm_endpoint
+                .unwrap_or_else(|| notebooklm::DEFAULT_ENDPOINT.to_string()),
+            notebooklm_project: merged.notebooklm_project,
+            notebooklm_location: merged
+                .notebooklm_location
+                .unwrap_or_else(|| notebooklm::DEFAULT_LOCATION.to_string()),
+            notebook_title: merged.notebook_title,
+            notebook_max_sources: merged
+                .notebook_max_sources
+                .unwrap_or(notebooklm::DEFAULT_MAX_SOURCES),
+            notebook_max_words: merged
+                .notebook_max_words
+                .unwrap_or(notebooklm::DEFAULT_MAX_WORDS),
+            fetch_concurrency: merged
+                .fetch_concurrency
+                .unwrap_or(pipeline::DEFAULT_CONCURRENCY),
+            fetch_per_host: merged.fetch_per_host.unwrap_or(pipeline::DEFAULT_PER_HOST),
+            fetch_host_delay_ms: merged
+                .fetch_host_delay_ms
+                .unwrap_or(pipeline::DEFAULT_HOST_DELAY_MS),
+            fetch_max_bytes: merged.fetch_max_bytes.unwrap_or(content::DEFAULT_MAX_BYTES),
+            retry_max_retries: merged
+                .retry_max_retries
+                .unwrap_or(retry::DEFAULT_MAX_RETRIES),
+            retry_base_delay_ms: merged
+                .retry_base_delay_ms
+                .unwrap_or(retry::DEFAULT_BASE_DELAY_MS),
+            retry_max_delay_ms: merged
+                .retry_max_delay_ms
+                .unwrap_or(retry::DEFAULT_MAX_DELAY_MS),
+            retry_budget: merged.retry_budget.unwrap_or(retry::DEFAULT_BUDGET),
+            max_urls: merged.max_urls,
+            output_dir,
+            state_dir,
+            sources,
+            credentials,
+        }
+        .validate()
+    }
+}
+
+impl ConfigSource {
+    fn file(&self) -> Option<&Path> {
+        match self {
+            ConfigSource::UserFile(path) | ConfigSource::ProjectFile(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+type IsSet = fn(&ConfigLayer) -> bool;
+
+const CONFIG_KEYS: &[(&str, IsSet)] = &[
+    ("raindrop_api_key", |l| l.raindrop_api_key.is_some()),
+    ("raindrop_base_url", |l| l.raindrop_base_url.is_some()),
+    ("raindrop_client_id", |l| l.raindrop_client_id.is_some()),
+    ("raindrop_client_secret", |l| l.raindrop_client_secret.is_some()),
+    ("raindrop_oauth_url", |l| l.raindrop_oauth_url.is_some()),
+    ("notebooklm_access_token", |l| {
+        l.notebooklm_access_token.is_some()
+    }),
+    ("notebooklm_token_command", |l| {
+        l.notebooklm_token_command.is_some()
+    }),
+    ("notebooklm_endpoint", |l| l.notebooklm_endpoint.is_some()),
+    ("notebooklm_project", |l| l.notebooklm_project.is_some()),
+    ("notebooklm_location", |l| l.notebooklm_location.is_some()),
+    ("notebook_title", |l| l.notebook_title.is_some()),
+    ("notebook_max_sources", |l| l.notebook_max_sources.is_some()),
+    ("notebook_max_words", |l| l.notebook_max_words.is_some()),
+    ("fetch_concurrency", |l| l.fetch_concurrency.is_some()),
+    ("fetch_per_host", |l| l.fetch_per_host.is_some()),
+    ("fetch_host_delay_ms", |l| l.fetch_host_delay_ms.is_some()),
+    ("fetch_max_bytes", |l| l.fetch_max_bytes.is_some()),
+    ("retry_max_retries", |l| l.retry_max_retries.is_some()),
+    ("retry_base_delay_ms", |l| l.retry_base_delay_ms.is_some()),
+    ("retry_max_delay_ms", |l| l.retry_max_delay_ms.is_some()),
+    ("retry_budget", |l| l.retry_budget.is_some()),
+    ("max_urls", |l| l.max_urls.is_some()),
+    ("output_dir", |l| l.output_dir.is_some()),
+    ("state_dir", |l| l.state_dir.is_some()),
+];
+
+const ENV_VARS: &[&str] = &[
+    "RAINDROP_TOKEN",
+    "RAINDROP_API_URL",
+    "RAINDROP_CLIENT_ID",
+    "RAINDROP_CLIENT_SECRET",
+    "NOTEBOOKLM_ACCESS_TOKEN",
+    "NOTEBOOKLM_TOKEN_COMMAND",
+    "NOTEBOOKLM_ENDPOINT",
+    "NOTEBOOKLM_PROJECT",
+    "NOTEBOOKLM_LOCATION",
+    "RAINDROP_NOTEBOOKLM_MAX_URLS",
+    "RAINDROP_NOTEBOOKLM_OUTPUT_DIR",
+];
+
+fn env_layer(name: &str, value: &str) -> std::result::Result<ConfigLayer, String> {
+    let layer = ConfigLayer::default();
+    Ok(match name {
+        "RAINDROP_TOKEN" => ConfigLayer {
+            raindrop_api_key: Some(Secret::from(value)),
+            ..layer
+        },
+        "RAINDROP_API_URL" => ConfigLayer {
+            raindrop_base_url: Some(value.to_string()),
+            ..layer
+        },
+        "RAINDROP_CLIENT_ID" => ConfigLayer {
+            raindrop_client_id: Some(value.to_string()),
+            ..layer
+        },
+        "RAINDROP_CLIENT_SECRET" => ConfigLayer {
+            raindrop_client_secret: Some(Secret::from(value)),
+            ..layer
+        },
+        "NOTEBOOKLM_ACCESS_TOKEN" => ConfigLayer {
+            notebooklm_access_token: Some(Secret::from(value)),
+            ..layer
+        },
+        "NOTEBOOKLM_TOKEN_COMMAND" => ConfigLayer {
+            notebooklm_token_command: Some(value.to_string()),
+            ..layer
+        },
+        "NOTEBOOKLM_ENDPOINT" => ConfigLayer {
+            notebooklm_endpoint: Some(value.to_string()),
+            ..layer
+        },
+        "NOTEBOOKLM_PROJECT" => ConfigLayer {
+            notebooklm_project: Some(value.to_string()),
+            ..layer
+        },
+        "NOTEBOOKLM_LOCATION" => ConfigLayer {
+            notebooklm_location: Some(value.to_string()),
+            ..layer
+        },
+        "RAINDROP_NOTEBOOKLM_MAX_URLS" => ConfigLayer {
+            max_urls: Some(
+                value
+                    .parse()
+                    .map_err(|_| "expected a positive integer".to_string())?,
+            ),
+            ..layer
+        },
+        "RAINDROP_NOTEBOOKLM_OUTPUT_DIR" => ConfigLayer {
+            output_dir: Some(PathBuf::from(value)),
+            ..layer
+        },
+        _ => layer,
+    })
+}
+
+fn require<'a, T: Deref<Target = str>>(
+    value: &'a Option<T>,
+    key: &'static str,
+    env: &'static str,
+) -> Result<&'a str> {
+    value
+        .as_deref()
+        .ok_or(AppError::Config(ConfigError::Missing { key, env }))
+}
+
+/// The stored key a `keyring:` reference names, or `value` as it is.
+fn resolve(value: Option<Secret>, credentials: Option<&CredentialStore>) -> Result<Option<Secret>> {
+    let Some(entry) = value.as_deref().and_then(credentials::parse_reference) else {
+        return Ok(value);
+    };
+    match credentials {
+        Some(store) => store.get(entry).map(Some),
+        None => Err(CredentialError::NotFound(entry.to_string())),
+    }
+    .map_err(CredentialError::entry(entry))
+}
+
+fn invalid(message: String) -> AppError {
+    AppError::Config(ConfigError::Invalid(message))
+}
+
+/// `$XDG_CONFIG_HOME/raindrop-notebooklm/config.toml`, falling back to
+/// `~/.config/...` as the XDG spec prescribes.
+pub fn user_config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join(USER_CONFIG_DIR).join(USER_CONFIG_FILE))
+}
@@ -0,0 +1,433 @@
+use std::sync::OnceLock;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use encoding_rs::{Encoding, UTF_8};
+use futures_util::StreamExt;
+use regex::bytes::Regex;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, ContentError, Result, ServiceError};
+use crate::http;
+use crate::retry::RetryPolicy;
+
+mod markdown;
+mod pdf;
+mod readability;
+
+use markdown::MarkdownRenderer;
+
+/// Media types handed to the HTML extractor. A missing `Content-Type` is
+/// treated as HTML because plenty of small sites never send one.
+const HTML_TYPES: &[&str] = &["text/html", "application/xhtml+xml"];
+
+const PDF_TYPE: &str = "application/pdf";
+
+/// Servers (S3 buckets in particular) often label PDFs as a generic binary,
+/// so those responses are sniffed for the PDF header instead.
+const BINARY_TYPE: &str = "application/octet-stream";
+
+/// How much of a page is searched for a `<meta>` charset declaration. The
+/// HTML standard has browsers look at the first 1024 bytes.
+const META_CHARSET_WINDOW: usize = 1024;
+
+/// Largest response body read for a page unless configured otherwise;
+/// book-length PDFs stay below it, endless or runaway downloads do not.
+pub const DEFAULT_MAX_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageContent {
+    /// The URL the content was read from, after redirects.
+    pub url: String,
+    pub title: String,
+    pub byline: Option<String>,
+    pub published: Option<DateTime<Utc>>,
+    /// BCP 47 tag as declared by the page, e.g. `en-US`.
+    pub language: Option<String>,
+    /// The main text with navigation, ads, footers and comments removed;
+    /// paragraphs are separated by blank lines.
+    pub text: String,
+    /// The same main content as Markdown, keeping headings, lists, tables,
+    /// quotes, links and code blocks. This is what gets uploaded.
+    pub markdown: String,
+    pub word_count: usize,
+}
+
+/// Cheap to clone; clones share the connection pool.
+#[derive(Clone)]
+pub struct ContentFetcher {
+    http: reqwest::Client,
+    retry: RetryPolicy,
+    max_bytes: u64,
+}
+
+/// A downloaded page whose text has yet to be extracted. Downloading waits
+/// on the network and extracting on the CPU, so the two are separate steps
+/// that can be scheduled differently.
+#[derive(Debug, Clone)]
+pub struct Download {
+    /// The URL the content was read from, after redirects.
+    pub url: String,
+    body: Body,
+}
+
+#[derive(Debug, Clone)]
+enum Body {
+    Html(String),
+    Pdf(Vec<u8>),
+}
+
+impl Download {
+    /// Bytes received for the body.
+    pub fn size(&self) -> u64 {
+        match &self.body {
+            Body::Html(html) => html.len() as u64,
+            Body::Pdf(bytes) => bytes.len() as u64,
+        }
+    }
+
+    pub fn extract(&self) -> Result<PageContent> {
+        match &self.body {
+            Body::Html(html) => Ok(extract(&self.url, html)),
+            Body::Pdf(bytes) => extract_pdf(&self.url, bytes),
+        }
+    }
+}
+
+impl Default for ContentFetcher {
+    fn default() -> Self {
+        ContentFetcher::new()
+    }
+}
+
+impl ContentFetcher {
+    pub fn new() -> Self {
+        ContentFetcher {
+            http: http::client(),
+            retry: RetryPolicy::default(),
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        ContentFetcher { retry, ..self }
+    }
+
+    /// Pages whose body is larger than `max_bytes` fail with
+    /// [`ContentError::TooLarge`] without being read any further.
+    pub fn with_max_bytes(self, max_bytes: u64) -> Self {
+        ContentFetcher { max_bytes, ..self }
+    }
+
+    /// Downloads `url` and extracts its main text, from HTML or from a
+    /// PDF's text layer.
+    pub async fn fetch_content(&self, url: &str) -> Result<PageContent> {
+        self.download(url).await?.extract()
+    }
+
+    /// Downloads `url`, failing for content types that cannot be extracted.
+    pub async fn download(&self, url: &str) -> Result<Download> {
+        let fetch_error = |source| {
+            AppError::Content(ContentError::Fetch {
+                url: url.to_string(),
+                source,
+            })
+        };
+        let response = http::send(self.http.get(url), &self.retry)
+            .await
+            .map_err(fetch_error)?;
+
+        let header = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let content_type = header.as_deref().map(media_type);
+        let final_url = response.url().to_string();
+        match content_type.as_deref() {
+            None => {}
+            Some(t) if HTML_TYPES.contains(&t) => {}
+            Some(t @ (PDF_TYPE | BINARY_TYPE)) => {
+                let bytes = self.read_body(url, response).await?;
+                if t == PDF_TYPE || bytes.starts_with(b"%PDF-") {
+                    return Ok(Download {
+                        url: final_url,
+                        body: Body::Pdf(bytes),
+                    });
+                }
+                return Err(unsupported_type(url, t));
+            }
+            Some(t) => return Err(unsupported_type(url, t)),
+        }
+
+        let bytes = self.read_body(url, response).await?;
+        Ok(Download {
+            url: final_url,
+            body: Body::Html(decode(&bytes, header.as_deref().and_then(charset))),
+        })
+    }
+
+    /// Reads the body chunk by chunk, giving up as soon as it grows past
+    /// the size limit instead of after buffering all of it.
+    async fn read_body(&self, url: &str, response: reqwest::Response) -> Result<Vec<u8>> {
+        let too_large = || {
+            AppError::Content(ContentError::TooLarge {
+                url: url.to_string(),
+                limit: self.max_bytes,
+            })
+        };
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_bytes)
+        {
+            return Err(too_large());
+        }
+        let mut body = Vec::new();
+        let mut chunks = response.bytes_stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| {
+                AppError::Content(ContentError::Fetch {
+                    url: url.to_string(),
+                    source: ServiceError::Decode(e),
+                })
+            })?;
+            if (body.len() + chunk.len()) as u64 > self.max_bytes {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+/// `text/html; charset="ISO-8859-1"` -> `ISO-8859-1`
+fn charset(header: &str) -> Option<&str> {
+    header.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Decodes HTML in the charset the `Content-Type` header declares, else in
+/// the one a `<meta>` tag near the top of the page declares, else as UTF-8.
+/// A byte order mark wins over all of them.
+fn decode(bytes: &[u8], charset: Option<&str>) -> String {
+    let encoding = charset
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .or_else(|| meta_charset(&bytes[..bytes.len().min(META_CHARSET_WINDOW)]))
+        .unwrap_or(UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
+
+/// The charset of `<meta charset="...">` or of `<meta http-equiv=
+/// "Content-Type" content="...; charset=...">`. A tag the page could be
+/// read by was written in an ASCII-compatible encoding, so a UTF-16 label
+/// there means UTF-8.
+fn meta_charset(head: &[u8]) -> Option<&'static Encoding> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let pattern = RE.get_or_init(|| {
+        Regex::new(r#"(?i)<meta\s[^>]*?charset\s*=\s*["']?\s*([^\s"'/>;]+)"#)
+            .expect("static regex is valid")
+    });
+    let label = pattern.captures(head)?.get(1)?;
+    Encoding::for_label(label.as_bytes()).map(Encoding::output_encoding)
+}
+
+fn unsupported_type(url: &str, content_type: &str) -> AppError {
+    AppError::Content(ContentError::UnsupportedType {
+        url: url.to_string(),
+        content_type: content_type.to_string(),
+    })
+}
+
+/// `text/html; charset=utf-8` -> `text/html`
+fn media_type(header: &str) -> String {
+    header
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Extracts metadata and the readable main text, as plain text and as
+/// Markdown, from an HTML document.
+pub fn extract(url: &str, html: &str) -> PageContent {
+    let document = Html::parse_document(html);
+    let main_content = readability::main_content(&document);
+    let text = readability::render_text(&main_content);
+    let base_url = Url::parse(url).ok();
+    let markdown = MarkdownRenderer::new(base_url.as_ref()).render(&main_content);
+    let word_count = text.split_whitespace().count();
+    PageContent {
+        url: url.to_string(),
+        title: title(&document).unwrap_or_else(|| url.to_string()),
+        byline: byline(&document),
+        published: published(&document),
+        language: language(&document),
+        text,
+        markdown,
+        word_count,
+    }
+}
+
+/// Extracts the text layer of a PDF page by page, with the title, author,
+/// creation date and language from the document metadata. Scanned PDFs
+/// without a text layer fail with [`ContentError::NoTextLayer`].
+pub fn extract_pdf(url: &str, bytes: &[u8]) -> Result<PageContent> {
+    pdf::extract(url, bytes)
+}
+
+/// Converts an HTML fragment to Markdown as is, without looking for the
+/// main content first.
+pub fn html_to_markdown(url: &str, html: &str) -> String {
+    let fragment = Html::parse_fragment(html);
+    let base_url = Url::parse(url).ok();
+    MarkdownRenderer::new(base_url.as_ref()).render_children(fragment.root_element())
+}
+
+fn select_first<'a>(document: &'a Html, selector: &str) -> Option<ElementRef<'a>> {
+    let selector = Selector::parse(selector).expect("selectors are static and valid");
+    let mut matches = document.select(&selector);
+    matches.next()
+}
+
+fn meta_content(document: &Html, selectors: &[&str]) -> Option<String> {
+    selectors.iter().find_map(|selector| {
+        select_first(document, selector)
+            .and_then(|meta| meta.value().attr("content"))
+            .map(collapse_whitespace)
+            .filter(|content| !content.is_empty())
+    })
+}
+
+fn element_text(element: ElementRef) -> String {
+    collapse_whitespace(&element.text().collect::<String>())
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Prefers the Open Graph title, which rarely carries the site name. The
+/// `<title>` element usually does ("Headline | Site"), so when the first
+/// heading is a prefix of it the heading is the cleaner choice.
+fn title(document: &Html) -> Option<String> {
+    if let Some(og_title) = meta_content(
+        document,
+        &[
+            r#"meta[property="og:title"]"#,
+            r#"meta[name="twitter:title"]"#,
+        ],
+    ) {
+        return Some(og_title);
+    }
+    let heading = select_first(document, "h1")
+        .map(element_text)
+        .filter(|text| !text.is_empty());
+    let title = select_first(document, "title")
+        .map(element_text)
+        .filter(|text| !text.is_empty());
+    match (title, heading) {
+        (Some(title), Some(heading)) if title.starts_with(&heading) => Some(heading),
+        (Some(title), _) => Some(title),
+        (None, heading) => heading,
+    }
+}
+
+fn byline(document: &Html) -> Option<String> {
+    // `article:author` is often a profile URL rather than a name.
+    let from_meta = meta_content(
+        document,
+        &[
+            r#"meta[name="author"]"#,
+            r#"meta[property="article:author"]"#,
+        ],
+    )
+    .filter(|author| !author.starts_with("http"));
+    from_meta
+        .or_else(|| {
+            [
+                r#"[itemprop="author"]"#,
+                r#"[rel="author"]"#,
+                ".byline",
+                ".author",
+            ]
+            .iter()
+            .find_map(|selector| {
+                select_first(document, selector)
+                    .map(element_text)
+                    .filter(|text| !text.is_empty())
+            })
+        })
+        .map(|author| {
+            let trimmed = author
+                .strip_prefix("By ")
+                .or_else(|| author.strip_prefix("by "))
+                .unwrap_or(&author);
+            trimmed.to_string()
+        })
+}
+
+fn published(document: &Html) -> Option<DateTime<Utc>> {
+    meta_content(
+        document,
+        &[
+            r#"meta[property="article:published_time"]"#,
+            r#"meta[itemprop="datePublished"]"#,
+            r#"meta[name="date"]"#,
+            r#"meta[name="pubdate"]"#,
+            r#"meta[name="DC.date.issued"]"#,
+        ],
+    )
+    .or_else(|| {
+        select_first(document, "time[datetime]")
+            .and_then(|time| time.value().attr("datetime"))
+            .map(str::to_string)
+    })
+    .and_then(|raw| parse_date(&raw))
+}
+
+/// Pages publish dates in whatever format their CMS emits; these cover
+/// nearly everything seen in practice. Date-only values become midnight UTC.
+fn parse_date(raw: &str) -> Option<DateTime<Utc>> {
+    let raw = raw.trim();
+    DateTime::parse_from_rfc3339(raw)
+        .or_else(|_| DateTime::parse_from_rfc2822(raw))
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S")
+                .ok()
+                .map(|date| date.and_utc())
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|date| date.and_utc())
+        })
+}
+
+fn language(document: &Html) -> Option<String> {
+    select_first(document, "html[lang]")
+        .and_then(|html| html.value().attr("lang"))
+        .map(str::trim)
+        .filter(|lang| !lang.is_empty())
+        .map(str::to_string)
+        .or_else(|| {
+            meta_content(
+                document,
+                &[
+                    r#"meta[http-equiv="content-language" i]"#,
+                    r#"meta[property="og:locale"]"#,
+                ],
+            )
+        })
+        // og:locale uses `en_US`; BCP 47 wants `en-US`.
+        .map(|lang| lang.replace('_', "-"))
+}
@@ -0,0 +1,456 @@
+//! Renders extracted HTML as CommonMark with GitHub table syntax, keeping
+//! the structure NotebookLM grounds on: headings, lists, tables, quotes,
+//! links and fenced code with its language.
+
+use reqwest::Url;
+use scraper::ElementRef;
+
+use super::readability::{is_boilerplate, is_link_list, BLOCK_TAGS};
+
+/// Cells spanning more columns than this are treated as malformed markup.
+const MAX_COLSPAN: usize = 100;
+
+pub struct MarkdownRenderer<'a> {
+    base_url: Option<&'a Url>,
+}
+
+impl<'a> MarkdownRenderer<'a> {
+    /// Relative links and image sources are resolved against `base_url`.
+    pub fn new(base_url: Option<&'a Url>) -> Self {
+        MarkdownRenderer { base_url }
+    }
+
+    /// Renders the elements themselves (not just their children) as
+    /// consecutive blocks.
+    pub fn render(&self, elements: &[ElementRef]) -> String {
+        elements
+            .iter()
+            .flat_map(|&element| self.block(element))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Renders the children of `element`; runs of inline content between
+    /// block elements become paragraphs.
+    pub fn render_children(&self, element: ElementRef) -> String {
+        self.blocks(element).join("\n\n")
+    }
+
+    fn blocks(&self, element: ElementRef) -> Vec<String> {
+        let mut blocks = Vec::new();
+        let mut inline = String::new();
+        for child in element.children() {
+            if let Some(text) = child.value().as_text() {
+                inline.push_str(&escape(&collapse_spaces(text)));
+            } else if let Some(child) = ElementRef::wrap(child) {
+                if is_boilerplate(child) || is_link_list(child) {
+                    continue;
+                }
+                if BLOCK_TAGS.contains(&child.value().name()) {
+                    flush_paragraph(&mut blocks, &mut inline);
+                    blocks.extend(self.block(child));
+                } else {
+                    inline.push_str(&self.inline_element(child));
+                }
+            }
+        }
+        flush_paragraph(&mut blocks, &mut inline);
+        blocks
+    }
+
+    fn block(&self, element: ElementRef) -> Vec<String> {
+        let tag = element.value().name();
+        let rendered = match tag {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => self.heading(element, &tag[1..]),
+            "ul" => self.list(element, false),
+            "ol" => self.list(element, true),
+            "pre" => code_block(element),
+            "blockquote" => self.quote(element),
+            "table" => self.table(element),
+            "hr" => Some("---".to_string()),
+            _ => return self.blocks(element),
+        };
+        rendered.into_iter().collect()
+    }
+
+    fn heading(&self, element: ElementRef, level: &str) -> Option<String> {
+        let level = level.parse().unwrap_or(1);
+        let text = single_line(&tidy(&self.inline_children(element)));
+        (!text.is_empty()).then(|| format!("{} {}", "#".repeat(level), text))
+    }
+
+    /// Nested lists are indented to the content column of their item, which
+    /// is what CommonMark needs to keep them nested. A list placed directly
+    /// inside another list (invalid, but common) is attached to the
+    /// preceding item.
+    fn list(&self, element: ElementRef, ordered: bool) -> Option<String> {
+        let mut items: Vec<Vec<String>> = Vec::new();
+        for child in element.children().filter_map(ElementRef::wrap) {
+            match child.value().name() {
+                "li" => items.push(self.blocks(child)),
+                "ul" | "ol" => match items.last_mut() {
+                    Some(item) => item.extend(self.block(child)),
+                    None => items.push(self.block(child)),
+                },
+                _ => {}
+            }
+        }
+        if items.is_empty() {
+            return None;
+        }
+
+        let start = if ordered {
+            element
+                .value()
+                .attr("start")
+                .and_then(|start| start.trim().parse().ok())
+                .unwrap_or(1)
+        } else {
+            0
+        };
+        let rendered = items
+            .iter()
+            .enumerate()
+            .map(|(index, blocks)| {
+                let marker = if ordered {
+                    format!("{}. ", start + index)
+                } else {
+                    "- ".to_string()
+                };
+                list_item(&marker, blocks)
+            })
+            .collect::<Vec<_>>();
+        Some(rendered.join("\n"))
+    }
+
+    fn quote(&self, element: ElementRef) -> Option<String> {
+        let inner = self.render_children(element);
+        if inner.is_empty() {
+            return None;
+        }
+        let quoted = inner
+            .lines()
+            .map(|line| {
+                if line.is_empty() {
+                    ">".to_string()
+                } else {
+                    format!("> {}", line)
+                }
+            })
+            .collect::<Vec<_>>();
+        Some(quoted.join("\n"))
+    }
+
+    /// GitHub tables have no spans, so a cell spanning several columns or
+    /// rows keeps its text in the first slot and leaves the rest empty; the
+    /// grid stays aligned with the header. The first row is the header, as
+    /// the syntax requires one.
+    fn table(&self, element: ElementRef) -> Option<String> {
+        let mut grid: Vec<Vec<String>> = Vec::new();
+        // Remaining rows each column is still covered by a rowspan above.
+        let mut covered: Vec<usize> = Vec::new();
+
+        for row in table_rows(element) {
+            let mut cells = row
+                .children()
+                .filter_map(ElementRef::wrap)
+                .filter(|cell| matches!(cell.value().name(), "td" | "th"));
+            let mut rendered = Vec::new();
+            loop {
+                while let Some(rows) = covered.get_mut(rendered.len()).filter(|rows| **rows > 0) {
+                    *rows -= 1;
+                    rendered.push(String::new());
+                }
+                let Some(cell) = cells.next() else {
+                    break;
+                };
+                let span = |name: &str, max: usize| {
+                    cell.value()
+                        .attr(name)
+                        .and_then(|value| value.trim().parse::<usize>().ok())
+                        .unwrap_or(1)
+                        .clamp(1, max)
+                };
+                let colspan = span("colspan", MAX_COLSPAN);
+                let rowspan = span("rowspan", usize::MAX);
+                let text = self.cell_text(cell);
+                for offset in 0..colspan {
+                    let column = rendered.len();
+                    if covered.len() <= column {
+                        covered.resize(column + 1, 0);
+                    }
+                    covered[column] = rowspan - 1;
+                    rendered.push(if offset == 0 {
+                        text.clone()
+                    } else {
+                        String::new()
+                    });
+                }
+            }
+            grid.push(rendered);
+        }
+
+        let width = grid.iter().map(Vec::len).max().unwrap_or(0);
+        if width == 0 {
+            return None;
+        }
+        let line = |cells: &[String]| {
+            let padded = (0..width)
+                .map(|column| cells.get(column).map(String::as_str).unwrap_or_default())
+                .collect::<Vec<_>>();
+            format!("| {} |", padded.join(" | "))
+        };
+        let separator = vec!["---".to_string(); width];
+        let lines = std::iter::once(line(&grid[0]))
+            .chain(std::iter::once(line(&separator)))
+            .chain(grid[1..].iter().map(|row| line(row)))
+            .collect::<Vec<_>>();
+        Some(lines.join("\n"))
+    }
+
+    /// Table cells must stay on one line, and a bare `|` would end the cell.
+    fn cell_text(&self, cell: ElementRef) -> String {
+        single_line(&self.blocks(cell).join(" ")).replace('|', "\\|")
+    }
+
+    fn inline_children(&self, element: ElementRef) -> String {
+        let mut inline = String::new();
+        for child in element.children() {
+            if let Some(text) = child.value().as_text() {
+                inline.push_str(&escape(&collapse_spaces(text)));
+            } else if let Some(child) = ElementRef::wrap(child) {
+                if !is_boilerplate(child) {
+                    inline.push_str(&self.inline_element(child));
+                }
+            }
+        }
+        inline
+    }
+
+    fn inline_element(&self, element: ElementRef) -> String {
+        match element.value().name() {
+            "br" => "\n".to_string(),
+            "strong" | "b" => emphasize(&self.inline_children(element), "**"),
+            "em" | "i" => emphasize(&self.inline_children(element), "*"),
+            "del" | "s" | "strike" => emphasize(&self.inline_children(element), "~~"),
+            "code" | "kbd" | "samp" => inline_code(&element.text().collect::<String>()),
+            "a" => self.link(element),
+            "img" => self.image(element),
+            _ => self.inline_children(element),
+        }
+    }
+
+    fn link(&self, element: ElementRef) -> String {
+        let inner = self.inline_children(element);
+        let text = tidy(&inner);
+        match element.value().attr("href").map(str::trim) {
+            Some(href) if !text.is_empty() && !href.starts_with("javascript:") => {
+                let link = format!("[{}]({})", single_line(&text), self.resolve(href));
+                keep_surrounding_space(&inner, link)
+            }
+            _ => inner,
+        }
+    }
+
+    fn image(&self, element: ElementRef) -> String {
+        let value = element.value();
+        match value
+            .attr("src")
+            .map(str::trim)
+            .filter(|src| !src.is_empty())
+        {
+            Some(src) => format!(
+                "![{}]({})",
+                escape(&collapse_spaces(value.attr("alt").unwrap_or_default())).trim(),
+                self.resolve(src)
+            ),
+            None => String::new(),
+        }
+    }
+
+    fn resolve(&self, href: &str) -> String {
+        self.base_url
+            .and_then(|base| base.join(href).ok())
+            .map(String::from)
+            .unwrap_or_else(|| href.to_string())
+    }
+}
+
+/// Renders one list item. Continuation lines are indented by the marker's
+/// width; a nested list follows its item directly so the list stays tight.
+fn list_item(marker: &str, blocks: &[String]) -> String {
+    let mut body = String::new();
+    for block in blocks {
+        if !body.is_empty() {
+            body.push_str(if is_list(block) { "\n" } else { "\n\n" });
+        }
+        body.push_str(block);
+    }
+    if body.is_empty() {
+        return marker.trim_end().to_string();
+    }
+
+    let indent = " ".repeat(marker.len());
+    body.lines()
+        .enumerate()
+        .map(|(index, line)| match index {
+            0 => format!("{}{}", marker, line),
+            _ if line.is_empty() => String::new(),
+            _ => format!("{}{}", indent, line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn is_list(block: &str) -> bool {
+    let digits = block.chars().take_while(char::is_ascii_digit).count();
+    block.starts_with("- ") || (digits > 0 && block[digits..].starts_with(". "))
+}
+
+/// Fenced code block. The fence is made longer than any backtick run in
+/// the code so that code containing fences survives intact.
+fn code_block(pre: ElementRef) -> Option<String> {
+    let code = pre.text().collect::<String>();
+    let code = code.trim_matches('\n').trim_end();
+    if code.is_empty() {
+        return None;
+    }
+    let fence = "`".repeat(longest_backtick_run(code).max(2) + 1);
+    let language = code_language(pre).unwrap_or_default();
+    Some(format!("{}{}\n{}\n{}", fence, language, code, fence))
+}
+
+/// Highlighters put the language on the `<code>` (`language-rust`,
+/// `lang-rust`), on the `<pre>` itself, or on a wrapper such as GitHub's
+/// `highlight-source-rust`.
+fn code_language(pre: ElementRef) -> Option<String> {
+    let code = pre
+        .children()
+        .filter_map(ElementRef::wrap)
+        .find(|child| child.value().name() == "code");
+    let parent = pre.parent().and_then(ElementRef::wrap);
+    [code, Some(pre), parent]
+        .into_iter()
+        .flatten()
+        .find_map(|element| {
+            let value = element.value();
+            value
+                .classes()
+                .find_map(|class| {
+                    ["language-", "lang-", "highlight-source-"]
+                        .iter()
+                        .find_map(|prefix| class.strip_prefix(prefix))
+                })
+                .or_else(|| value.attr("data-lang"))
+                .map(str::trim)
+                .filter(|language| !language.is_empty())
+                .map(str::to_string)
+        })
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+fn inline_code(text: &str) -> String {
+    let code = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if code.is_empty() {
+        return String::new();
+    }
+    let fence = "`".repeat(longest_backtick_run(&code) + 1);
+    if code.contains('`') {
+        format!("{} {} {}", fence, code, fence)
+    } else {
+        format!("{}{}{}", fence, code, fence)
+    }
+}
+
+/// Wraps the trimmed text in `marker`, keeping surrounding whitespace
+/// outside: `**word** ` is emphasis, `**word **` is not.
+fn emphasize(inner: &str, marker: &str) -> String {
+    let text = inner.trim();
+    if text.is_empty() {
+        return inner.to_string();
+    }
+    keep_surrounding_space(inner, format!("{}{}{}", marker, text, marker))
+}
+
+fn keep_surrounding_space(original: &str, rendered: String) -> String {
+    let leading = if original.starts_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    let trailing = if original.ends_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    format!("{}{}{}", leading, rendered, trailing)
+}
+
+fn flush_paragraph(blocks: &mut Vec<String>, inline: &mut String) {
+    let paragraph = tidy(inline);
+    if !paragraph.is_empty() {
+        blocks.push(paragraph);
+    }
+    inline.clear();
+}
+
+/// Collapses whitespace within each line; the only line breaks left are
+/// the `<br>`s, which become CommonMark hard breaks.
+fn tidy(inline: &str) -> String {
+    inline
+        .split('\n')
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("  \n")
+}
+
+fn single_line(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn collapse_spaces(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !c.is_whitespace() {
+            collapsed.push(c);
+        } else if !collapsed.ends_with(' ') {
+            collapsed.push(' ');
+        }
+    }
+    collapsed
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn table_rows(table: ElementRef) -> Vec<ElementRef> {
+    table
+        .children()
+        .filter_map(ElementRef::wrap)
+        .flat_map(|child| match child.value().name() {
+            "tr" => vec![child],
+            "thead" | "tbody" | "tfoot" => child
+                .children()
+                .filter_map(ElementRef::wrap)
+                .filter(|row| row.value().name() == "tr")
+                .collect(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
@@ -0,0 +1,160 @@
+//! Text extraction for PDF bookmarks (papers, whitepapers). Text is read
+//! page by page from the text layer; nothing is OCR'd.
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use log::debug;
+use lopdf::{Dictionary, Document, Object};
+
+use super::PageContent;
+use crate::error::{AppError, ContentError, Result};
+
+/// Pages averaging fewer visible characters than this are treated as
+/// having no text layer; scans often carry nothing but a page number.
+const MIN_CHARS_PER_PAGE: usize = 20;
+
+pub fn extract(url: &str, bytes: &[u8]) -> Result<PageContent> {
+    let document = Document::load_mem(bytes).map_err(|source| {
+        AppError::Content(ContentError::Pdf {
+            url: url.to_string(),
+            source,
+        })
+    })?;
+
+    let pages = document
+        .get_pages()
+        .keys()
+        .map(|&number| page_text(&document, number))
+        .collect::<Vec<_>>();
+    let visible_chars = pages
+        .iter()
+        .flat_map(|page| page.chars())
+        .filter(|c| !c.is_whitespace())
+        .count();
+    if visible_chars < pages.len().max(1) * MIN_CHARS_PER_PAGE {
+        return Err(AppError::Content(ContentError::NoTextLayer {
+            url: url.to_string(),
+            pages: pages.len(),
+        }));
+    }
+
+    let info = info_dictionary(&document);
+    let title = info
+        .and_then(|info| text_string(info, b"Title"))
+        .or_else(|| {
+            pages
+                .iter()
+                .find_map(|page| page.lines().next())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| url.to_string());
+    let text = pages
+        .iter()
+        .filter(|page| !page.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    // Page headings let NotebookLM cite "page 4" the way a reader would.
+    let markdown = pages
+        .iter()
+        .enumerate()
+        .filter(|(_, page)| !page.is_empty())
+        .map(|(index, page)| format!("## Page {}\n\n{}", index + 1, page))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let word_count = text.split_whitespace().count();
+
+    Ok(PageContent {
+        url: url.to_string(),
+        title,
+        byline: info.and_then(|info| text_string(info, b"Author")),
+        published: info
+            .and_then(|info| text_string(info, b"CreationDate"))
+            .and_then(|date| parse_pdf_date(&date)),
+        language: document
+            .catalog()
+            .ok()
+            .and_then(|catalog| text_string(catalog, b"Lang")),
+        text,
+        markdown,
+        word_count,
+    })
+}
+
+/// A page whose fonts cannot be decoded counts as empty rather than failing
+/// the whole document.
+fn page_text(document: &Document, number: u32) -> String {
+    match document.extract_text(&[number]) {
+        Ok(text) => text
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(e) => {
+            debug!("Could not extract text from page {}: {}", number, e);
+            String::new()
+        }
+    }
+}
+
+fn info_dictionary(document: &Document) -> Option<&Dictionary> {
+    match document.trailer.get(b"Info").ok()? {
+        Object::Reference(id) => document.get_dictionary(*id).ok(),
+        Object::Dictionary(info) => Some(info),
+        _ => None,
+    }
+}
+
+/// PDF text strings are UTF-16BE when they start with a byte order mark and
+/// PDFDocEncoding otherwise, which matches Latin-1 for printable text.
+fn text_string(dictionary: &Dictionary, key: &[u8]) -> Option<String> {
+    let bytes = dictionary.get(key).ok()?.as_str().ok()?;
+    let text = match bytes {
+        [0xFE, 0xFF, utf16 @ ..] => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        [0xEF, 0xBB, 0xBF, utf8 @ ..] => String::from_utf8_lossy(utf8).into_owned(),
+        latin1 => latin1.iter().map(|&byte| char::from(byte)).collect(),
+    };
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// Parses `D:YYYYMMDDHHmmSS+HH'mm'`, where everything after the year is
+/// optional.
+fn parse_pdf_date(raw: &str) -> Option<DateTime<Utc>> {
+    let raw = raw.trim().trim_start_matches("D:");
+    let digits = raw.chars().take_while(char::is_ascii_digit).count();
+    let field = |start: usize, len: usize, default: u32| {
+        raw.get(start..start + len)
+            .filter(|_| start + len <= digits)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    let year = raw.get(0..4).filter(|_| digits >= 4)?.parse().ok()?;
+    let local = NaiveDate::from_ymd_opt(year, field(4, 2, 1), field(6, 2, 1))?.and_hms_opt(
+        field(8, 2, 0),
+        field(10, 2, 0),
+        field(12, 2, 0),
+    )?;
+
+    let zone = &raw[digits..];
+    let offset = match zone.chars().next() {
+        Some(sign @ ('+' | '-')) => {
+            let mut parts = zone[1..]
+                .split('\'')
+                .filter_map(|part| part.parse::<i64>().ok());
+            let minutes = parts.next().unwrap_or(0) * 60 + parts.next().unwrap_or(0);
+            if sign == '-' {
+                -minutes
+            } else {
+                minutes
+            }
+        }
+        _ => 0,
+    };
+    Some((local - Duration::minutes(offset)).and_utc())
+}
@@ -0,0 +1,388 @@
+//! Main-text detection modelled on Mozilla's Readability: every paragraph
+//! votes for its parent and grandparent, the best-scoring container wins, and
+//! siblings that look like part of the same article are pulled back in.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use ego_tree::NodeId;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+
+/// Elements that never hold article text, whatever their score.
+const STRIPPED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "svg", "canvas", "iframe", "object", "embed", "nav",
+    "footer", "aside", "form", "button", "input", "select", "textarea", "dialog", "menu",
+];
+
+const STRIPPED_ROLES: &[&str] = &[
+    "navigation",
+    "banner",
+    "complementary",
+    "contentinfo",
+    "dialog",
+    "alertdialog",
+    "menu",
+    "menubar",
+];
+
+/// Elements whose text is scored as a paragraph.
+const PARAGRAPH_TAGS: &[&str] = &["p", "pre", "td", "blockquote"];
+
+/// Elements that start a new paragraph in the rendered text.
+pub const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "blockquote",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// Containers that are dropped from the result when they are mostly links,
+/// which catches "related articles" lists and share bars inside the article.
+const LINK_LIST_TAGS: &[&str] = &["div", "section", "ul", "ol", "table"];
+
+/// Paragraphs shorter than this are captions, buttons or stray labels.
+const MIN_PARAGRAPH_LEN: usize = 25;
+
+fn unlikely_candidates() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"(?i)-ad-|\bads?\b|advert|banner|breadcrumbs?|combx|comment|community|cookie|disqus|extra|footer|gdpr|header|legends|menu|newsletter|related|remark|replies|rss|shoutbox|sidebar|skyscraper|social|sponsor|subscribe|supplemental|pagination|pager|popup|promo",
+        )
+        .expect("static regex is valid")
+    })
+}
+
+fn maybe_candidate() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?i)and|article|body|column|content|main|shadow")
+            .expect("static regex is valid")
+    })
+}
+
+fn positive_hint() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?i)article|body|content|entry|hentry|h-entry|main|page|post|text|blog|story")
+            .expect("static regex is valid")
+    })
+}
+
+fn negative_hint() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"(?i)-ad-|\bads?\b|advert|hidden|banner|combx|comment|com-|contact|foot|footnote|gdpr|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget",
+        )
+        .expect("static regex is valid")
+    })
+}
+
+/// Returns the elements that make up the article, in document order. Falls
+/// back to `<body>` when no paragraph scores at all, e.g. on a bare page.
+pub fn main_content(document: &Html) -> Vec<ElementRef<'_>> {
+    let scores = score_candidates(document);
+    let best = scores
+        .iter()
+        .filter_map(|(&id, &score)| {
+            let element = element_by_id(document, id)?;
+            Some((element, score * (1.0 - link_density(element))))
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    match best {
+        Some((top, top_score)) => with_related_siblings(top, top_score, &scores),
+        None => {
+            let body = Selector::parse("body").expect("selectors are static and valid");
+            let mut matches = document.select(&body);
+            matches.next().into_iter().collect()
+        }
+    }
+}
+
+/// Renders the elements as plain text, one paragraph per block element,
+/// skipping boilerplate nested inside them.
+pub fn render_text(elements: &[ElementRef]) -> String {
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    elements
+        .iter()
+        .for_each(|&element| collect_text(element, &mut paragraphs, &mut current));
+    flush(&mut paragraphs, &mut current);
+    paragraphs.join("\n\n")
+}
+
+/// Whether an element is navigation, an ad, a footer, a comment thread or
+/// another part of the page chrome that should not reach the output.
+pub fn is_boilerplate(element: ElementRef) -> bool {
+    let value = element.value();
+    let tag = value.name();
+    if STRIPPED_TAGS.contains(&tag)
+        || value.attr("hidden").is_some()
+        || value.attr("aria-hidden") == Some("true")
+        || value
+            .attr("role")
+            .is_some_and(|role| STRIPPED_ROLES.contains(&role))
+    {
+        return true;
+    }
+    if matches!(tag, "html" | "body" | "article" | "main" | "a") {
+        return false;
+    }
+    let hints = class_and_id(element);
+    unlikely_candidates().is_match(&hints) && !maybe_candidate().is_match(&hints)
+}
+
+fn score_candidates(document: &Html) -> HashMap<NodeId, f64> {
+    let mut scores: HashMap<NodeId, f64> = HashMap::new();
+    let paragraphs = document
+        .root_element()
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter(|&element| is_paragraph(element))
+        .filter(|&element| !within_boilerplate(element));
+
+    for paragraph in paragraphs {
+        let text = normalized_text(paragraph);
+        let length = text.chars().count();
+        if length < MIN_PARAGRAPH_LEN {
+            continue;
+        }
+        let commas = text.matches([',', '、', '，']).count();
+        let score = 1.0 + commas as f64 + (length as f64 / 100.0).min(3.0);
+
+        // Parent gets the full score, grandparent half and great-grandparent a
+        // sixth, so an outer wrapper cannot outscore the article it wraps.
+        paragraph
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .take(3)
+            .enumerate()
+            .for_each(|(level, ancestor)| {
+                let divider = match level {
+                    0 => 1.0,
+                    1 => 2.0,
+                    _ => level as f64 * 3.0,
+                };
+                *scores
+                    .entry(ancestor.id())
+                    .or_insert_with(|| initial_score(ancestor)) += score / divider;
+            });
+    }
+    scores
+}
+
+/// Paragraph-like elements, including `<div>`s used as paragraphs, i.e.
+/// ones with no block-level children of their own.
+fn is_paragraph(element: ElementRef) -> bool {
+    let tag = element.value().name();
+    PARAGRAPH_TAGS.contains(&tag)
+        || (tag == "div"
+            && element
+                .children()
+                .filter_map(ElementRef::wrap)
+                .all(|child| !BLOCK_TAGS.contains(&child.value().name())))
+}
+
+fn within_boilerplate(element: ElementRef) -> bool {
+    is_boilerplate(element)
+        || element
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .any(is_boilerplate)
+}
+
+fn initial_score(element: ElementRef) -> f64 {
+    let tag_score = match element.value().name() {
+        "div" | "article" | "main" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    tag_score + class_weight(element)
+}
+
+fn class_weight(element: ElementRef) -> f64 {
+    let value = element.value();
+    let class = value.classes().collect::<Vec<_>>().join(" ");
+    [Some(class.as_str()), value.id()]
+        .into_iter()
+        .flatten()
+        .filter(|hint| !hint.is_empty())
+        .map(|hint| {
+            let negative = if negative_hint().is_match(hint) {
+                -25.0
+            } else {
+                0.0
+            };
+            let positive = if positive_hint().is_match(hint) {
+                25.0
+            } else {
+                0.0
+            };
+            negative + positive
+        })
+        .sum()
+}
+
+/// Pulls in siblings of the winning container that scored well on their own
+/// or read like prose, since articles are often split across several
+/// wrappers (lead, body, continued body).
+fn with_related_siblings<'a>(
+    top: ElementRef<'a>,
+    top_score: f64,
+    scores: &HashMap<NodeId, f64>,
+) -> Vec<ElementRef<'a>> {
+    let Some(parent) = top
+        .parent()
+        .filter(|parent| ElementRef::wrap(*parent).is_some())
+    else {
+        return vec![top];
+    };
+    let threshold = (top_score * 0.2).max(10.0);
+    let top_class = top.value().attr("class");
+
+    parent
+        .children()
+        .filter_map(ElementRef::wrap)
+        .filter(|&sibling| {
+            if sibling.id() == top.id() {
+                return true;
+            }
+            if is_boilerplate(sibling) {
+                return false;
+            }
+            let bonus = match (top_class, sibling.value().attr("class")) {
+                (Some(a), Some(b)) if !a.is_empty() && a == b => top_score * 0.2,
+                _ => 0.0,
+            };
+            let score = scores.get(&sibling.id()).copied().unwrap_or(0.0);
+            if score + bonus >= threshold {
+                return true;
+            }
+            if sibling.value().name() != "p" {
+                return false;
+            }
+            let text = normalized_text(sibling);
+            let length = text.chars().count();
+            let density = link_density(sibling);
+            (length > 80 && density < 0.25)
+                || (length > 0 && density == 0.0 && (text.ends_with('.') || text.contains(". ")))
+        })
+        .collect()
+}
+
+fn collect_text(element: ElementRef, paragraphs: &mut Vec<String>, current: &mut String) {
+    let tag = element.value().name();
+    match tag {
+        "br" => {
+            current.push('\n');
+            return;
+        }
+        "pre" => {
+            // Keep the code's own line breaks and indentation.
+            flush(paragraphs, current);
+            let code = element.text().collect::<String>();
+            let code = code.trim_matches('\n').trim_end();
+            if !code.is_empty() {
+                paragraphs.push(code.to_string());
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let is_block = BLOCK_TAGS.contains(&tag);
+    if is_block {
+        flush(paragraphs, current);
+    }
+    for child in element.children() {
+        if let Some(text) = child.value().as_text() {
+            current.push_str(text);
+        } else if let Some(child) = ElementRef::wrap(child) {
+            if !is_boilerplate(child) && !is_link_list(child) {
+                collect_text(child, paragraphs, current);
+            }
+        }
+    }
+    if is_block {
+        flush(paragraphs, current);
+    }
+}
+
+fn flush(paragraphs: &mut Vec<String>, current: &mut String) {
+    let lines = current
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    if !lines.is_empty() {
+        paragraphs.push(lines.join("\n"));
+    }
+    current.clear();
+}
+
+pub fn is_link_list(element: ElementRef) -> bool {
+    LINK_LIST_TAGS.contains(&element.value().name())
+        && (class_weight(element) < 0.0 || link_density(element) > 0.5)
+}
+
+/// Share of an element's text that sits inside links.
+fn link_density(element: ElementRef) -> f64 {
+    let total = element.text().map(|text| text.trim().len()).sum::<usize>();
+    if total == 0 {
+        return 0.0;
+    }
+    let anchor = Selector::parse("a").expect("selectors are static and valid");
+    let linked = element
+        .select(&anchor)
+        .flat_map(|link| link.text())
+        .map(|text| text.trim().len())
+        .sum::<usize>();
+    linked as f64 / total as f64
+}
+
+fn normalized_text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn class_and_id(element: ElementRef) -> String {
+    let value = element.value();
+    let class = value.attr("class").unwrap_or_default();
+    let id = value.attr("id").unwrap_or_default();
+    format!("{} {}", class, id)
+}
+
+fn element_by_id(document: &Html, id: NodeId) -> Option<ElementRef<'_>> {
+    document.tree.get(id).and_then(ElementRef::wrap)
+}
@@ -0,0 +1,475 @@
+//! API keys kept out of configuration files. `auth login` stores a key in
+//! the system keyring (the Secret Service on Linux) or, where there is
+//! none, in a file encrypted with a passphrase; configuration then names
+//! it as `keyring:<profile>/<service>`.
+//!
+//! Keys live in [`Secret`]s, which wipe their memory when dropped.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
+
+use crate::config;
+use crate::error::CredentialError;
+
+/// Marks a configuration value as the name of a stored credential.
+pub const KEYRING_PREFIX: &str = "keyring:";
+
+/// The service name entries are filed under in the system keyring.
+pub const KEYRING_SERVICE: &str = "raindrop-notebooklm";
+
+/// The encrypted fallback, next to the user configuration file.
+pub const CREDENTIALS_FILE: &str = "credentials.enc";
+
+/// Holds the passphrase of the encrypted file for unattended runs.
+pub const PASSPHRASE_ENV: &str = "RAINDROP_NOTEBOOKLM_PASSPHRASE";
+
+/// Bumped whenever the encrypted file format changes incompatibly.
+const FILE_VERSION: u32 = 1;
+
+const SALT_LEN: usize = 16;
+
+/// A string that is overwritten with zeros when dropped and never shows in
+/// `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(Zeroizing::new(value.into()))
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Secret::new(value)
+    }
+}
+
+impl Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", crate::redact::REDACTED)
+    }
+}
+
+/// Only for stores that protect what they hold, such as the keyring.
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret::new)
+    }
+}
+
+/// The services `auth login` stores a key for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CredentialKind {
+    Raindrop,
+    #[value(name = "notebooklm")]
+    NotebookLM,
+}
+
+impl CredentialKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CredentialKind::Raindrop => "raindrop",
+            CredentialKind::NotebookLM => "notebooklm",
+        }
+    }
+
+    /// The configuration key the stored credential is meant for.
+    pub fn config_key(self) -> &'static str {
+        match self {
+            CredentialKind::Raindrop => "raindrop_api_key",
+            CredentialKind::NotebookLM => "notebooklm_access_token",
+        }
+    }
+
+    /// What the stored credential is, for prompts.
+    pub fn describe(self) -> &'static str {
+        match self {
+            CredentialKind::Raindrop => "API key",
+            CredentialKind::NotebookLM => "access token",
+        }
+    }
+
+    /// `<profile>/<service>`, the name the key is stored under.
+    pub fn entry(self, profile: &str) -> String {
+        format!("{}/{}", profile, self.as_str())
+    }
+
+    /// `keyring:<profile>/<service>`, for the configuration file.
+    pub fn reference(self, profile: &str) -> String {
+        format!("{}{}", KEYRING_PREFIX, self.entry(profile))
+    }
+}
+
+/// Where a credential is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// The system keyring
+    Keyring,
+    /// A file encrypted with a passphrase
+    File,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Keyring => write!(f, "the system keyring"),
+            Backend::File => write!(f, "the encrypted credential file"),
+        }
+    }
+}
+
+/// Reads and stores credentials in the system keyring, falling back to the
+/// encrypted file when no keyring is reachable or it lacks the entry.
+#[derive(Debug, Clone)]
+pub struct CredentialStore {
+    keyring: bool,
+    file: Option<PathBuf>,
+    /// Asked for once and then kept for the rest of the run.
+    passphrase: Arc<Mutex<Option<Secret>>>,
+    prompt: bool,
+}
+
+impl Default for CredentialStore {
+    fn default() -> Self {
+        CredentialStore::new()
+    }
+}
+
+impl CredentialStore {
+    /// The system keyring and the encrypted file next to the user
+    /// configuration.
+    pub fn new() -> Self {
+        CredentialStore {
+            keyring: true,
+            file: config::user_config_path()
+                .as_deref()
+                .and_then(Path::parent)
+                .map(|dir| dir.join(CREDENTIALS_FILE)),
+            passphrase: Arc::new(Mutex::new(None)),
+            prompt: false,
+        }
+    }
+
+    /// Only the encrypted file at `path`, as for tests and machines
+    /// without a keyring.
+    pub fn file_only(path: impl Into<PathBuf>) -> Self {
+        CredentialStore {
+            keyring: false,
+            file: Some(path.into()),
+            ..CredentialStore::new()
+        }
+    }
+
+    pub fn with_passphrase(self, passphrase: Secret) -> Self {
+        *self.passphrase.lock().expect("passphrase lock poisoned") = Some(passphrase);
+        self
+    }
+
+    /// Asks for the passphrase on the terminal if none was given.
+    pub fn with_prompt(self) -> Self {
+        CredentialStore {
+            prompt: true,
+            ..self
+        }
+    }
+
+    /// The key stored as `entry`, from the keyring if it has it, otherwise
+    /// from the encrypted file.
+    pub fn get(&self, entry: &str) -> Result<Secret, CredentialError> {
+        if self.keyring {
+            match keyring_entry(entry)?.get_password() {
+                Ok(secret) => return Ok(Secret::new(secret)),
+                Err(keyring::Error::NoEntry) => {}
+                Err(e) if keyring_unavailable(&e) => {
+                    log::debug!("System keyring unavailable: {}", e);
+                }
+                Err(e) => return Err(CredentialError::Keyring(e)),
+            }
+        }
+        match &self.file {
+            Some(path) if path.exists() => self
+                .read_file(path)?
+                .remove(entry)
+                .ok_or_else(|| CredentialError::NotFound(entry.to_string())),
+            _ => Err(CredentialError::NotFound(entry.to_string())),
+        }
+    }
+
+    /// Stores `secret` as `entry` in `backend`, or in the keyring if one is
+    /// reachable and the encrypted file otherwise. Returns where it went.
+    pub fn set(
+        &self,
+        entry: &str,
+        secret: &Secret,
+        backend: Option<Backend>,
+    ) -> Result<Backend, CredentialError> {
+        let use_keyring = match backend {
+            Some(Backend::Keyring) => true,
+            Some(Backend::File) => false,
+            None => self.keyring,
+        };
+        if use_keyring {
+            match keyring_entry(entry)?.set_password(secret) {
+                Ok(()) => return Ok(Backend::Keyring),
+                Err(e) if backend.is_none() && keyring_unavailable(&e) => {
+                    log::warn!(
+                        "⚠️ System keyring unavailable, using the encrypted file: {}",
+                        e
+                    );
+                }
+                Err(e) => return Err(CredentialError::Keyring(e)),
+            }
+        }
+        let path = self.file.as_deref().ok_or(CredentialError::NoFile)?;
+        let mut entries = if path.exists() {
+            self.read_file(path)?
+        } else {
+            BTreeMap::new()
+        };
+        entries.insert(entry.to_string(), secret.clone());
+        self.write_file(path, &entries)?;
+        Ok(Backend::File)
+    }
+
+    fn passphrase(&self, path: &Path) -> Result<Secret, CredentialError> {
+        let mut cached = self.passphrase.lock().expect("passphrase lock poisoned");
+        if let Some(passphrase) = cached.as_ref() {
+            return Ok(passphrase.clone());
+        }
+        let passphrase = std::env::var(PASSPHRASE_ENV)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(Secret::new);
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase,
+            None if self.prompt => {
+                let prompt = |label: &str| {
+                    rpassword::prompt_password(format!("{} for {}: ", label, path.display()))
+                        .map(Secret::new)
+                        .map_err(|source| CredentialError::Io {
+                            path: path.to_path_buf(),
+                            source,
+                        })
+                };
+                let passphrase = prompt("Passphrase")?;
+                // A typo in the passphrase of a new file would lock its
+                // keys away for good.
+                if !path.exists() && prompt("Repeat the passphrase")? != passphrase {
+                    return Err(CredentialError::PassphraseMismatch {
+                        path: path.to_path_buf(),
+                    });
+                }
+                passphrase
+            }
+            None => {
+                return Err(CredentialError::NoPassphrase {
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+        *cached = Some(passphrase.clone());
+        Ok(passphrase)
+    }
+
+    fn read_file(&self, path: &Path) -> Result<BTreeMap<String, Secret>, CredentialError> {
+        let text = std::fs::read_to_string(path).map_err(|source| CredentialError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let malformed = |source| CredentialError::Malformed {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file: EncryptedFile = serde_json::from_str(&text).map_err(malformed)?;
+        let decrypt_error = || CredentialError::Decrypt {
+            path: path.to_path_buf(),
+        };
+        if file.version != FILE_VERSION {
+            return Err(decrypt_error());
+        }
+        let salt = BASE64.decode(&file.salt).map_err(|_| decrypt_error())?;
+        let nonce = BASE64.decode(&file.nonce).map_err(|_| decrypt_error())?;
+        let ciphertext = BASE64
+            .decode(&file.ciphertext)
+            .map_err(|_| decrypt_error())?;
+        if nonce.len() != 12 {
+            return Err(decrypt_error());
+        }
+        let cipher = cipher(&self.passphrase(path)?, &salt)?;
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+                .map_err(|_| decrypt_error())?,
+        );
+        serde_json::from_slice(&plaintext).map_err(malformed)
+    }
+
+    /// Encrypts `entries` afresh, with a new salt and nonce, and replaces
+    /// the file. Only its owner may read it.
+    fn write_file(
+        &self,
+        path: &Path,
+        entries: &BTreeMap<String, Secret>,
+    ) -> Result<(), CredentialError> {
+        let io = |source| CredentialError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plain: BTreeMap<&str, &str> = entries
+            .iter()
+            .map(|(name, secret)| (name.as_str(), &**secret))
+            .collect();
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(&plain).expect("credential entries are plain strings"),
+        );
+        let ciphertext = cipher(&self.passphrase(path)?, &salt)?
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| CredentialError::Decrypt {
+                path: path.to_path_buf(),
+            })?;
+        let file = EncryptedFile {
+            version: FILE_VERSION,
+            kdf: "argon2id".to_string(),
+            salt: BASE64.encode(salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(io)?;
+        }
+        let json = serde_json::to_string_pretty(&file).expect("the file contains only strings");
+        write_private(path, json.as_bytes()).map_err(io)
+    }
+}
+
+/// `keyring:<entry>` names the stored credential `<entry>`.
+pub fn parse_reference(value: &str) -> Option<&str> {
+    value
+        .strip_prefix(KEYRING_PREFIX)
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    kdf: String,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Derives the file's key from the passphrase with Argon2id.
+fn cipher(passphrase: &Secret, salt: &[u8]) -> Result<ChaCha20Poly1305, CredentialError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| CredentialError::Kdf(e.to_string()))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(key.as_ref())))
+}
+
+fn keyring_entry(entry: &str) -> Result<keyring::Entry, CredentialError> {
+    keyring::Entry::new(KEYRING_SERVICE, entry).map_err(CredentialError::Keyring)
+}
+
+/// No keyring daemon, or one that is locked: the file can stand in.
+fn keyring_unavailable(error: &keyring::Error) -> bool {
+    matches!(
+        error,
+        keyring::Error::PlatformFailure(_) | keyring::Error::NoStorageAccess(_)
+    )
+}
+
+/// Writes `contents` to a new file next to `path` that only its owner may
+/// read, flushes it to disk and renames it over `path`, so that a crash
+/// leaves either the old file or the new one, never half of either.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let file_name = path.file_name().unwrap_or(CREDENTIALS_FILE.as_ref());
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = path.with_file_name(temp_name);
+    let _ = std::fs::remove_file(&temp);
+    let written = create_private(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written?;
+    sync_parent(path)
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+/// Makes the rename itself survive a crash.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => std::fs::File::open(dir)?.sync_all(),
+        None => std::fs::File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
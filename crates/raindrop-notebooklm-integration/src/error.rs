@@ -0,0 +1,301 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, AppError>;
+
+/// Process exit codes, one per failure class, so wrapper scripts can tell
+/// "bad credentials" apart from "some pages could not be fetched".
+///
+/// | Code | Meaning                                         |
+/// |------|-------------------------------------------------|
+/// | 0    | success                                         |
+/// | 2    | invalid command line (reported by clap)         |
+/// | 3    | configuration missing or invalid                |
+/// | 10   | Raindrop unreachable                            |
+/// | 11   | Raindrop rejected the credentials               |
+/// | 12   | Raindrop returned an unexpected response        |
+/// | 20   | NotebookLM unreachable                          |
+/// | 21   | NotebookLM rejected the credentials             |
+/// | 22   | NotebookLM returned an unexpected response      |
+/// | 30   | some pages could not be fetched or uploaded     |
+/// | 40   | local files could not be read or written        |
+pub mod exit_code {
+    pub const OK: u8 = 0;
+    pub const USAGE: u8 = 2;
+    pub const CONFIG: u8 = 3;
+    pub const RAINDROP_UNREACHABLE: u8 = 10;
+    pub const RAINDROP_UNAUTHORIZED: u8 = 11;
+    pub const RAINDROP_API_ERROR: u8 = 12;
+    pub const NOTEBOOKLM_UNREACHABLE: u8 = 20;
+    pub const NOTEBOOKLM_UNAUTHORIZED: u8 = 21;
+    pub const NOTEBOOKLM_API_ERROR: u8 = 22;
+    pub const CONTENT: u8 = 30;
+    pub const STORAGE: u8 = 40;
+}
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("Raindrop API error")]
+    Raindrop(#[source] ServiceError),
+
+    #[error("Content fetch error")]
+    Content(#[source] ContentError),
+
+    #[error("NotebookLM API error")]
+    NotebookLM(#[source] ServiceError),
+
+    #[error("Configuration error")]
+    Config(#[source] ConfigError),
+
+    #[error("Storage error")]
+    Storage(#[source] StorageError),
+}
+
+impl AppError {
+    pub fn exit_code(&self) -> u8 {
+        use exit_code::*;
+        match self {
+            AppError::Raindrop(e) => e.exit_code(
+                RAINDROP_UNREACHABLE,
+                RAINDROP_UNAUTHORIZED,
+                RAINDROP_API_ERROR,
+            ),
+            AppError::NotebookLM(e) => e.exit_code(
+                NOTEBOOKLM_UNREACHABLE,
+                NOTEBOOKLM_UNAUTHORIZED,
+                NOTEBOOKLM_API_ERROR,
+            ),
+            AppError::Content(_) => CONTENT,
+            AppError::Config(_) => CONFIG,
+            AppError::Storage(_) => STORAGE,
+        }
+    }
+
+    /// The message followed by every underlying cause, `: `-separated,
+    /// with secrets redacted.
+    pub fn chain(&self) -> String {
+        let chain = std::iter::successors(Some(self as &(dyn std::error::Error + 'static)), |e| {
+            e.source()
+        })
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ");
+        crate::redact::redact(&chain)
+    }
+}
+
+/// Failures talking to a remote HTTP API, shared by the Raindrop and
+/// NotebookLM clients; `AppError` records which service it was.
+#[derive(Debug, Error)]
+pub enum ServiceError {
+    #[error("service unreachable")]
+    Unreachable(#[source] reqwest::Error),
+
+    #[error("credentials were rejected (HTTP {status})")]
+    Unauthorized { status: u16 },
+
+    #[error("unexpected response (HTTP {status}): {body}")]
+    Status { status: u16, body: String },
+
+    #[error("malformed response")]
+    Decode(#[source] reqwest::Error),
+
+    #[error("malformed response")]
+    Json(#[source] serde_json::Error),
+
+    #[error("unexpected response: {0}")]
+    Unexpected(String),
+
+    #[error("operation {name} failed (code {code}): {message}")]
+    OperationFailed {
+        name: String,
+        code: i32,
+        message: String,
+    },
+
+    #[error("operation {name} did not finish within {seconds}s")]
+    OperationTimeout { name: String, seconds: u64 },
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("the OAuth redirect could not be received")]
+    Callback(#[source] std::io::Error),
+
+    #[error("access token command `{command}` failed: {reason}")]
+    TokenCommand { command: String, reason: String },
+}
+
+impl ServiceError {
+    fn exit_code(&self, unreachable: u8, unauthorized: u8, other: u8) -> u8 {
+        match self {
+            ServiceError::Unreachable(_) => unreachable,
+            ServiceError::Unauthorized { .. } | ServiceError::TokenCommand { .. } => unauthorized,
+            _ => other,
+        }
+    }
+
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            ServiceError::Unauthorized { status } | ServiceError::Status { status, .. } => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ContentError {
+    #[error("failed to fetch {url}")]
+    Fetch {
+        url: String,
+        #[source]
+        source: ServiceError,
+    },
+
+    #[error("failed to read PDF {url}")]
+    Pdf {
+        url: String,
+        #[source]
+        source: lopdf::Error,
+    },
+
+    #[error("failed to extract the content of {url}")]
+    Extract {
+        url: String,
+        #[source]
+        source: tokio::task::JoinError,
+    },
+
+    #[error("{url} is a scanned PDF with no text layer ({pages} pages)")]
+    NoTextLayer { url: String, pages: usize },
+
+    #[error("unsupported content type {content_type} at {url}")]
+    UnsupportedType { url: String, content_type: String },
+
+    #[error("{url} is larger than the {limit}-byte limit (fetch_max_bytes)")]
+    TooLarge { url: String, limit: u64 },
+
+    #[error("{failed} of {total} pages could not be fetched or uploaded")]
+    PartialFailure { failed: usize, total: usize },
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read {}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("in {}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: Box<ConfigError>,
+    },
+
+    #[error("invalid TOML")]
+    Syntax(#[source] toml::de::Error),
+
+    #[error("invalid value in {var}: {reason}")]
+    Env { var: &'static str, reason: String },
+
+    #[error("{key} is not configured (set it in a config file or via {env})")]
+    Missing {
+        key: &'static str,
+        env: &'static str,
+    },
+
+    #[error("{0}")]
+    Invalid(String),
+
+    #[error("credential {entry}")]
+    Credential {
+        entry: String,
+        #[source]
+        source: CredentialError,
+    },
+}
+
+/// Failures reading or storing a key in the system keyring or the
+/// encrypted credential file.
+#[derive(Debug, Error)]
+pub enum CredentialError {
+    #[error("not in the system keyring or the credential file (run `auth login`)")]
+    NotFound(String),
+
+    #[error("system keyring failed")]
+    Keyring(#[source] keyring::Error),
+
+    #[error("no passphrase for {} (set {})", path.display(), crate::credentials::PASSPHRASE_ENV)]
+    NoPassphrase { path: PathBuf },
+
+    #[error("the passphrases for {} do not match", path.display())]
+    PassphraseMismatch { path: PathBuf },
+
+    #[error("cannot decrypt {}: wrong passphrase or damaged file", path.display())]
+    Decrypt { path: PathBuf },
+
+    #[error("{} is not a credential file", path.display())]
+    Malformed {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("cannot derive the file key: {0}")]
+    Kdf(String),
+
+    #[error("no keyring is reachable and no credential file is configured")]
+    NoFile,
+
+    #[error("failed to access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl CredentialError {
+    pub fn entry(entry: impl Into<String>) -> impl FnOnce(CredentialError) -> AppError {
+        let entry = entry.into();
+        move |source| AppError::Config(ConfigError::Credential { entry, source })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("failed to access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("execution plan cannot be used: {0}")]
+    Plan(String),
+
+    #[error("sync state database {} failed", path.display())]
+    Database {
+        path: PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+}
+
+impl StorageError {
+    pub fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> AppError {
+        let path = path.into();
+        move |source| AppError::Storage(StorageError::Io { path, source })
+    }
+
+    pub fn database(path: impl Into<PathBuf>) -> impl FnOnce(rusqlite::Error) -> AppError {
+        let path = path.into();
+        move |source| AppError::Storage(StorageError::Database { path, source })
+    }
+}
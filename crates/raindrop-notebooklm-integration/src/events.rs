@@ -0,0 +1,145 @@
+//! Structured progress events, so that dashboards and wrapper scripts can
+//! follow a run without parsing log lines. A run sends its events down a
+//! channel; the CLI prints each as one JSON line for `--output ndjson` and
+//! draws its progress display from them.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::ledger::FailureClass;
+use crate::raindrop::BookmarkId;
+use crate::status::ServiceStatus;
+
+/// Something that happened during a run, stamped with when and for which
+/// profile.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub at: DateTime<Utc>,
+    pub profile: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    RunStarted {
+        command: String,
+        tool_version: String,
+        dry_run: bool,
+    },
+    /// `total` is unset when it is not known up front, as for listing.
+    StageStarted { stage: Stage, total: Option<usize> },
+    /// The total of a stage that started without one, once it is known.
+    StageTotal { stage: Stage, total: usize },
+    StageFinished { stage: Stage },
+    /// One page of a Raindrop search; `total` is the search's match count.
+    ListingPage {
+        search: String,
+        page: usize,
+        items: usize,
+        total: usize,
+    },
+    /// Raindrop returned the bookmark for the selection.
+    BookmarkListed {
+        bookmark_id: BookmarkId,
+        link: String,
+        title: String,
+    },
+    FetchSucceeded {
+        bookmark_id: BookmarkId,
+        link: String,
+        title: String,
+        word_count: usize,
+        fetch_ms: u64,
+        bytes: u64,
+    },
+    FetchFailed {
+        bookmark_id: BookmarkId,
+        link: String,
+        class: FailureClass,
+        http_status: Option<u16>,
+        error: String,
+        fetch_ms: u64,
+    },
+    SourceUploaded {
+        bookmark_id: BookmarkId,
+        title: String,
+        notebook_id: String,
+        source_id: String,
+        bytes: u64,
+    },
+    /// One service checked by `status`.
+    ServiceChecked {
+        #[serde(flatten)]
+        status: ServiceStatus,
+    },
+    /// `exit_code` is what the process exits with for this profile alone.
+    RunFinished {
+        exit_code: u8,
+        error: Option<String>,
+    },
+}
+
+/// The parts of a sync that take long enough to follow. They overlap:
+/// pages are fetched while the listing goes on, and uploaded as they come.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Listing,
+    Fetching,
+    Uploading,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Listing => write!(f, "Listing"),
+            Stage::Fetching => write!(f, "Fetching"),
+            Stage::Uploading => write!(f, "Uploading"),
+        }
+    }
+}
+
+/// Where a run reports its events. The default drops them, for callers
+/// that do not follow the run.
+#[derive(Debug, Clone, Default)]
+pub struct Events {
+    sender: Option<UnboundedSender<Event>>,
+    profile: String,
+}
+
+impl Events {
+    /// A handle that sends its events to the returned receiver.
+    pub fn channel() -> (Events, UnboundedReceiver<Event>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let events = Events {
+            sender: Some(sender),
+            profile: String::new(),
+        };
+        (events, receiver)
+    }
+
+    /// The same stream, with events attributed to `profile`.
+    pub fn with_profile(self, profile: &str) -> Self {
+        Events {
+            profile: profile.to_string(),
+            ..self
+        }
+    }
+
+    pub fn emit(&self, kind: EventKind) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        // Nobody listening any more is no reason to stop the run.
+        let _ = sender.send(Event {
+            at: Utc::now(),
+            profile: self.profile.clone(),
+            kind,
+        });
+    }
+}
@@ -0,0 +1,474 @@
+//! Offline export: writes the fetched bookmarks as a bundle of Markdown
+//! files that can be dragged into NotebookLM by hand when API access is not
+//! available.
+//!
+//! ```text
+//! export/<profile>/
+//!   index.md                  what is in the bundle
+//!   digest-01.md, ...         all bookmarks combined, split to fit one source
+//!   bookmarks/001-<slug>.md   one file per bookmark, with front matter
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{info, warn};
+
+use tokio::sync::mpsc;
+
+use crate::content::{ContentFetcher, PageContent};
+use crate::error::{AppError, ContentError, Result, ServiceError, StorageError};
+use crate::pipeline::{FetchLimits, Pipeline};
+use crate::raindrop::{Bookmark, CollectionId, RaindropClient, TagMatch};
+use crate::sync::{self, Selection, Selector, CHANNEL_CAPACITY};
+
+pub const EXPORT_DIR: &str = "export";
+pub const INDEX_FILE: &str = "index.md";
+const BOOKMARKS_DIR: &str = "bookmarks";
+
+/// NotebookLM accepts up to 500,000 words per source.
+pub const DEFAULT_DIGEST_MAX_WORDS: usize = 500_000;
+
+const SECTION_SEPARATOR: &str = "\n\n---\n\n";
+const MAX_SLUG_LEN: usize = 60;
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub tags: Vec<String>,
+    pub tag_match: TagMatch,
+    pub collection: CollectionId,
+    pub max_urls: Option<usize>,
+    /// Replaced on every run, so the bundle never mixes in stale files.
+    pub dir: PathBuf,
+    pub digest_max_words: usize,
+    pub fetch_limits: FetchLimits,
+}
+
+/// A bookmark together with the content fetched for it.
+#[derive(Debug, Clone)]
+pub struct ExportedPage {
+    pub bookmark: Bookmark,
+    pub page: PageContent,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// The files written by one export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bundle {
+    pub index: PathBuf,
+    pub digests: Vec<PathBuf>,
+    pub pages: Vec<PathBuf>,
+}
+
+pub async fn run(
+    raindrop: &RaindropClient,
+    fetcher: &ContentFetcher,
+    options: &ExportOptions,
+) -> Result<Bundle> {
+    info!("🔗 Connecting to Raindrop API...");
+    let session = raindrop.authenticate().await?;
+    info!("👤 Authenticated as {}", session.user.full_name);
+
+    let tag_list = sync::describe_tags(&options.tags, options.tag_match);
+    info!("📄 Fetching page contents as bookmarks are listed...");
+    let (found, listing) = mpsc::channel(CHANNEL_CAPACITY);
+    let (queue, queued) = mpsc::channel(CHANNEL_CAPACITY);
+
+    let listing_stage = async {
+        raindrop
+            .send_bookmarks_by_tags(options.collection, &options.tags, options.tag_match, &found)
+            .await?;
+        drop(found);
+        Ok(())
+    };
+
+    let mut listed = 0;
+    let mut selected = 0;
+    let selecting = async {
+        let mut listing = listing;
+        let mut selector = Selector::new(options.max_urls);
+        while let Some(bookmark) = listing.recv().await {
+            listed += 1;
+            if !matches!(selector.select(&bookmark), Selection::Selected) {
+                continue;
+            }
+            let link = bookmark.link.clone();
+            if queue.send(((selected, bookmark), link)).await.is_err() {
+                break;
+            }
+            selected += 1;
+        }
+        drop(queue);
+        Ok(())
+    };
+
+    // Each page goes into the bundle as soon as it is fetched. The previous
+    // bundle is only replaced once there is something to replace it with.
+    let mut writer = None;
+    let mut failed = 0;
+    let writing = async {
+        let mut results = Pipeline::new(fetcher.clone(), options.fetch_limits).run_queued(queued);
+        while let Some(((index, bookmark), result, _)) = results.recv().await {
+            match result {
+                Ok(page) => {
+                    info!("  ✓ {} ({} words)", page.title, page.word_count);
+                    let writer = match &mut writer {
+                        Some(writer) => writer,
+                        None => writer.insert(BundleWriter::create(&options.dir)?),
+                    };
+                    let exported = ExportedPage {
+                        bookmark,
+                        page,
+                        fetched_at: Utc::now(),
+                    };
+                    writer.add(index, &exported)?;
+                }
+                Err(e) => {
+                    warn!("  ✗ Skipping {}: {}", bookmark.link, e.chain());
+                    failed += 1;
+                }
+            }
+        }
+        Ok(())
+    };
+    tokio::try_join!(listing_stage, selecting, writing)?;
+
+    if listed == 0 {
+        return Err(AppError::Raindrop(ServiceError::NotFound(format!(
+            "no bookmarks found for {}",
+            tag_list
+        ))));
+    }
+    info!("🔖 Exported {} bookmarks for {}", selected, tag_list);
+    let writer = match writer {
+        Some(writer) => writer,
+        None => BundleWriter::create(&options.dir)?,
+    };
+    let bundle = writer.finish(&tag_list, options.digest_max_words)?;
+    info!(
+        "📦 Wrote {} bookmarks and {} digest files to {}",
+        bundle.pages.len(),
+        bundle.digests.len(),
+        options.dir.display()
+    );
+
+    if failed > 0 {
+        return Err(AppError::Content(ContentError::PartialFailure {
+            failed,
+            total: selected,
+        }));
+    }
+    Ok(bundle)
+}
+
+/// Replaces `dir` with a bundle for `pages`, numbered in their order.
+/// `heading` describes the selection in the index, e.g. `#ai AND #rust`.
+pub fn write_bundle(
+    dir: &Path,
+    heading: &str,
+    pages: &[ExportedPage],
+    digest_max_words: usize,
+) -> Result<Bundle> {
+    let mut writer = BundleWriter::create(dir)?;
+    for (index, exported) in pages.iter().enumerate() {
+        writer.add(index, exported)?;
+    }
+    writer.finish(heading, digest_max_words)
+}
+
+/// Writes a bundle a page at a time, as the pages come in, so that only
+/// the page at hand is held in memory. The pages are numbered and the
+/// digests put together from their files once all of them are in.
+pub struct BundleWriter {
+    dir: PathBuf,
+    pages: Vec<WrittenPage>,
+}
+
+/// What the index and the digests need of a page already in its file.
+struct WrittenPage {
+    /// Its place in the selection.
+    index: usize,
+    title: String,
+    link: String,
+    word_count: usize,
+    slug: String,
+    path: PathBuf,
+    /// Where the Markdown starts in the file, after the front matter.
+    body_start: usize,
+}
+
+impl BundleWriter {
+    /// Replaces `dir` with an empty bundle.
+    pub fn create(dir: &Path) -> Result<BundleWriter> {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir).map_err(StorageError::io(dir))?;
+        }
+        let bookmarks_dir = dir.join(BOOKMARKS_DIR);
+        std::fs::create_dir_all(&bookmarks_dir).map_err(StorageError::io(&bookmarks_dir))?;
+        Ok(BundleWriter {
+            dir: dir.to_path_buf(),
+            pages: Vec::new(),
+        })
+    }
+
+    /// Writes the file of the page at `index` in the selection, under a
+    /// name of its own until [`BundleWriter::finish`] numbers it.
+    pub fn add(&mut self, index: usize, exported: &ExportedPage) -> Result<()> {
+        let path = self.dir.join(BOOKMARKS_DIR).join(format!(".{}.md", index));
+        let front_matter = front_matter(exported);
+        let text = format!("{}{}\n", front_matter, exported.page.markdown);
+        write(&path, &text)?;
+        self.pages.push(WrittenPage {
+            index,
+            title: exported.page.title.clone(),
+            link: exported.bookmark.link.clone(),
+            word_count: exported.page.word_count,
+            slug: slug(exported),
+            path,
+            body_start: front_matter.len(),
+        });
+        Ok(())
+    }
+
+    /// Numbers the pages in selection order and writes the digests and the
+    /// index. `heading` describes the selection, e.g. `#ai AND #rust`.
+    pub fn finish(mut self, heading: &str, digest_max_words: usize) -> Result<Bundle> {
+        self.pages.sort_by_key(|page| page.index);
+        for (number, page) in self.pages.iter_mut().enumerate() {
+            let name = format!("{:03}-{}.md", number + 1, page.slug);
+            let path = self.dir.join(BOOKMARKS_DIR).join(name);
+            std::fs::rename(&page.path, &path).map_err(StorageError::io(&page.path))?;
+            page.path = path;
+        }
+
+        // Each part is set aside as soon as it is full; its heading needs
+        // the number of parts, which is only known at the end.
+        let mut parts = Vec::new();
+        let sections = self.pages.iter().map(|page| {
+            let text = std::fs::read_to_string(&page.path).map_err(StorageError::io(&page.path))?;
+            let markdown = &text[page.body_start..];
+            let markdown = markdown.strip_suffix('\n').unwrap_or(markdown);
+            Ok(digest_section(&page.title, &page.link, markdown))
+        });
+        split_digest(sections, digest_max_words, |part| {
+            let path = self.dir.join(format!(".digest-{}.md", parts.len() + 1));
+            write(&path, &part)?;
+            parts.push((path, word_count(&part)));
+            Ok(())
+        })?;
+        let mut digests = Vec::new();
+        for (index, (part_path, words)) in parts.iter().enumerate() {
+            let part = std::fs::read_to_string(part_path).map_err(StorageError::io(part_path))?;
+            let path = self.dir.join(format!("digest-{:02}.md", index + 1));
+            let text = format!(
+                "# Raindrop digest: {} ({}/{})\n\n{}\n",
+                heading,
+                index + 1,
+                parts.len(),
+                part
+            );
+            write(&path, &text)?;
+            std::fs::remove_file(part_path).map_err(StorageError::io(part_path))?;
+            digests.push((path, *words));
+        }
+
+        let index = self.dir.join(INDEX_FILE);
+        write(
+            &index,
+            &render_index(&self.dir, heading, &self.pages, &digests),
+        )?;
+
+        Ok(Bundle {
+            index,
+            digests: digests.into_iter().map(|(path, _)| path).collect(),
+            pages: self.pages.into_iter().map(|page| page.path).collect(),
+        })
+    }
+}
+
+/// YAML front matter. Strings are written as JSON strings, which YAML reads
+/// as double-quoted scalars, so titles with colons or quotes stay valid.
+pub fn front_matter(exported: &ExportedPage) -> String {
+    let quote = |value: &str| serde_json::to_string(value).expect("strings always serialize");
+    let bookmark = &exported.bookmark;
+    let page = &exported.page;
+    let mut lines = vec![
+        "---".to_string(),
+        format!("title: {}", quote(&page.title)),
+        format!("source_url: {}", quote(&bookmark.link)),
+        format!(
+            "fetched_at: {}",
+            exported
+                .fetched_at
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+        ),
+        format!(
+            "tags: [{}]",
+            bookmark
+                .tags
+                .iter()
+                .map(|tag| quote(tag))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        format!("raindrop_id: {}", bookmark.id.0),
+        format!("word_count: {}", page.word_count),
+    ];
+    lines.extend(
+        page.byline
+            .as_deref()
+            .map(|by| format!("byline: {}", quote(by))),
+    );
+    lines.extend(page.published.map(|published| {
+        format!(
+            "published: {}",
+            published.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )
+    }));
+    lines.extend(
+        page.language
+            .as_deref()
+            .map(|language| format!("language: {}", quote(language))),
+    );
+    lines.push("---".to_string());
+    lines.join("\n") + "\n\n"
+}
+
+fn digest_section(title: &str, link: &str, markdown: &str) -> String {
+    format!("## {}\n\nSource: <{}>\n\n{}", title, link, markdown)
+}
+
+/// Packs sections into as few parts as possible without any part exceeding
+/// `max_words`, handing each part to `part` once it is full. A section that
+/// is too large on its own is split between paragraphs; a single paragraph
+/// is never cut.
+fn split_digest(
+    sections: impl Iterator<Item = Result<String>>,
+    max_words: usize,
+    mut part: impl FnMut(String) -> Result<()>,
+) -> Result<()> {
+    let mut current: Vec<String> = Vec::new();
+    let mut words = 0;
+    for section in sections {
+        for piece in split_section(section?, max_words) {
+            let piece_words = word_count(&piece);
+            if !current.is_empty() && words + piece_words > max_words {
+                part(current.join(SECTION_SEPARATOR))?;
+                current.clear();
+                words = 0;
+            }
+            words += piece_words;
+            current.push(piece);
+        }
+    }
+    if !current.is_empty() {
+        part(current.join(SECTION_SEPARATOR))?;
+    }
+    Ok(())
+}
+
+fn split_section(section: String, max_words: usize) -> Vec<String> {
+    if word_count(&section) <= max_words {
+        return vec![section];
+    }
+    let mut chunks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut words = 0;
+    for paragraph in section.split("\n\n") {
+        let paragraph_words = word_count(paragraph);
+        if !current.is_empty() && words + paragraph_words > max_words {
+            chunks.push(current.join("\n\n"));
+            current.clear();
+            words = 0;
+        }
+        words += paragraph_words;
+        current.push(paragraph);
+    }
+    if !current.is_empty() {
+        chunks.push(current.join("\n\n"));
+    }
+    chunks
+}
+
+fn word_count(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+fn render_index(
+    dir: &Path,
+    heading: &str,
+    pages: &[WrittenPage],
+    digests: &[(PathBuf, usize)],
+) -> String {
+    let relative = |path: &Path| {
+        path.strip_prefix(dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    };
+    let cell = |text: &str| text.replace('|', "\\|");
+
+    let mut lines = vec![
+        format!("# Raindrop export: {}", heading),
+        String::new(),
+        format!(
+            "{} bookmarks, {} words. Upload the digest files for one source per file, or the files under `{}/` for one source per bookmark.",
+            pages.len(),
+            pages.iter().map(|page| page.word_count).sum::<usize>(),
+            BOOKMARKS_DIR
+        ),
+        String::new(),
+        "## Digest".to_string(),
+        String::new(),
+    ];
+    lines.extend(digests.iter().map(|(path, words)| {
+        let path = relative(path);
+        format!("- [{}]({}) ({} words)", path, path, words)
+    }));
+    lines.extend([
+        String::new(),
+        "## Bookmarks".to_string(),
+        String::new(),
+        "| # | Title | Source | Words | File |".to_string(),
+        "| --- | --- | --- | --- | --- |".to_string(),
+    ]);
+    lines.extend(pages.iter().enumerate().map(|(index, page)| {
+        let path = relative(&page.path);
+        format!(
+            "| {} | {} | <{}> | {} | [{}]({}) |",
+            index + 1,
+            cell(&page.title),
+            page.link,
+            page.word_count,
+            path,
+            path
+        )
+    }));
+    lines.join("\n") + "\n"
+}
+
+/// Lowercase ASCII words from the title joined by dashes, falling back to
+/// the Raindrop id for titles without any.
+fn slug(exported: &ExportedPage) -> String {
+    let slug = exported
+        .page
+        .title
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug = match slug.char_indices().nth(MAX_SLUG_LEN) {
+        Some((end, _)) => slug[..end].trim_end_matches('-').to_string(),
+        None => slug,
+    };
+    if slug.is_empty() {
+        format!("bookmark-{}", exported.bookmark.id.0)
+    } else {
+        slug
+    }
+}
+
+fn write(path: &Path, text: &str) -> Result<()> {
+    std::fs::write(path, text).map_err(StorageError::io(path))
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use log::warn;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::error::ServiceError;
+use crate::retry::{self, RetryPolicy};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Error bodies are kept for diagnostics, but an HTML error page can be
+/// hundreds of kilobytes and would drown the log line.
+const MAX_ERROR_BODY: usize = 500;
+
+/// Shared HTTP client settings. Without a timeout a stalled server would hang
+/// a sync or status check indefinitely.
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .expect("HTTP client configuration is static and valid")
+}
+
+/// Sends the request, retrying as `retry` allows, and turns transport
+/// failures and non-2xx responses into the matching [`ServiceError`].
+///
+/// Refused connections are always retried since nothing was sent. Timeouts
+/// and server errors are retried only for methods that are safe to repeat,
+/// because the server may have acted on a request it did not answer
+/// properly; other methods are only retried on statuses that say the
+/// request was not processed, see [`retry::is_unprocessed`].
+pub async fn send(request: RequestBuilder, retry: &RetryPolicy) -> Result<Response, ServiceError> {
+    let (client, request) = request.build_split();
+    let request = request.map_err(ServiceError::Unreachable)?;
+    let repeatable = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    );
+    let mut retries = 0;
+    loop {
+        // Streaming bodies cannot be sent twice; none are used today.
+        let Some(attempt) = request.try_clone() else {
+            return check(client.execute(request).await).await;
+        };
+        let outcome = client.execute(attempt).await;
+        let (reason, requested) = match &outcome {
+            Ok(response)
+                if retry::is_retryable(response.status())
+                    && (repeatable || retry::is_unprocessed(response.status())) =>
+            {
+                (
+                    format!("HTTP {}", response.status().as_u16()),
+                    retry::requested_wait(response.headers(), Utc::now()),
+                )
+            }
+            Err(e) if e.is_connect() || (e.is_timeout() && repeatable) => (e.to_string(), None),
+            _ => return check(outcome).await,
+        };
+        let Some(delay) = retry.next_delay(retries, requested) else {
+            if retries > 0 || retry.limits().max_retries > 0 {
+                warn!(
+                    "Giving up on {} {} after {} retries ({})",
+                    request.method(),
+                    request.url(),
+                    retries,
+                    reason
+                );
+            }
+            return check(outcome).await;
+        };
+        retries += 1;
+        warn!(
+            "Retrying {} {} in {:.1}s ({}, retry {} of {})",
+            request.method(),
+            request.url(),
+            delay.as_secs_f64(),
+            reason,
+            retries,
+            retry.limits().max_retries
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn check(outcome: reqwest::Result<Response>) -> Result<Response, ServiceError> {
+    let response = outcome.map_err(ServiceError::Unreachable)?;
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(ServiceError::Unauthorized {
+            status: status.as_u16(),
+        });
+    }
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(ServiceError::Status {
+            status: status.as_u16(),
+            body: body.chars().take(MAX_ERROR_BODY).collect(),
+        });
+    }
+    Ok(response)
+}
+
+pub async fn send_json<T: DeserializeOwned>(
+    request: RequestBuilder,
+    retry: &RetryPolicy,
+) -> Result<T, ServiceError> {
+    send(request, retry)
+        .await?
+        .json::<T>()
+        .await
+        .map_err(ServiceError::Decode)
+}
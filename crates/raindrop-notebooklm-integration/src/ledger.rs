@@ -0,0 +1,123 @@
+//! Bookmarks whose page could not be fetched. Each failure is kept in the
+//! profile's sync state until a later sync or `retry-failed` fetches the
+//! page, so a bad night on the network does not lose anything.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, ContentError, ServiceError};
+use crate::raindrop::BookmarkId;
+
+/// What kind of failure kept a page from being fetched, for deciding what
+/// is worth retrying.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum FailureClass {
+    /// The site could not be reached: DNS, refused connection, TLS.
+    Network,
+    Timeout,
+    /// HTTP 429.
+    RateLimited,
+    /// HTTP 4xx other than 429: gone, forbidden, paywalled.
+    ClientError,
+    /// HTTP 5xx.
+    ServerError,
+    /// A content type that cannot be extracted, e.g. an image.
+    Unsupported,
+    /// A scanned PDF without a text layer.
+    NoText,
+    /// A body larger than `fetch_max_bytes`.
+    TooLarge,
+    /// A response that could not be read or parsed.
+    Malformed,
+}
+
+impl FailureClass {
+    pub const ALL: &'static [FailureClass] = &[
+        FailureClass::Network,
+        FailureClass::Timeout,
+        FailureClass::RateLimited,
+        FailureClass::ClientError,
+        FailureClass::ServerError,
+        FailureClass::Unsupported,
+        FailureClass::NoText,
+        FailureClass::TooLarge,
+        FailureClass::Malformed,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureClass::Network => "network",
+            FailureClass::Timeout => "timeout",
+            FailureClass::RateLimited => "rate_limited",
+            FailureClass::ClientError => "client_error",
+            FailureClass::ServerError => "server_error",
+            FailureClass::Unsupported => "unsupported",
+            FailureClass::NoText => "no_text",
+            FailureClass::TooLarge => "too_large",
+            FailureClass::Malformed => "malformed",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<FailureClass> {
+        FailureClass::ALL
+            .iter()
+            .copied()
+            .find(|class| class.as_str() == name)
+    }
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The class and, for HTTP errors, the status of a failed fetch.
+pub fn classify(error: &AppError) -> (FailureClass, Option<u16>) {
+    let service = |error: &ServiceError| match error {
+        ServiceError::Unreachable(e) if e.is_timeout() => FailureClass::Timeout,
+        ServiceError::Unreachable(_) => FailureClass::Network,
+        ServiceError::Unauthorized { .. } => FailureClass::ClientError,
+        ServiceError::Status { status: 429, .. } => FailureClass::RateLimited,
+        ServiceError::Status { status, .. } if *status >= 500 => FailureClass::ServerError,
+        ServiceError::Status { .. } => FailureClass::ClientError,
+        _ => FailureClass::Malformed,
+    };
+    match error {
+        AppError::Content(ContentError::Fetch { source, .. }) => {
+            (service(source), source.http_status())
+        }
+        AppError::Content(ContentError::UnsupportedType { .. }) => {
+            (FailureClass::Unsupported, None)
+        }
+        AppError::Content(ContentError::NoTextLayer { .. }) => (FailureClass::NoText, None),
+        AppError::Content(ContentError::TooLarge { .. }) => (FailureClass::TooLarge, None),
+        AppError::Raindrop(e) | AppError::NotebookLM(e) => (service(e), e.http_status()),
+        _ => (FailureClass::Malformed, None),
+    }
+}
+
+/// One bookmark in the ledger, within the scope it was synced for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedFetch {
+    pub bookmark_id: BookmarkId,
+    pub link: String,
+    pub title: String,
+    /// Raindrop's `lastUpdate` when the fetch failed.
+    pub last_update: DateTime<Utc>,
+    /// The selection in words, e.g. `#ai AND #rust`.
+    pub selection: String,
+    pub class: FailureClass,
+    pub http_status: Option<u16>,
+    pub error: String,
+    /// Runs in which fetching the page failed.
+    pub attempts: u32,
+    pub first_failed_at: DateTime<Utc>,
+    pub last_failed_at: DateTime<Utc>,
+}
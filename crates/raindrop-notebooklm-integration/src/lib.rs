@@ -0,0 +1,23 @@
+pub mod config;
+pub mod content;
+pub mod credentials;
+pub mod error;
+pub mod events;
+pub mod export;
+mod http;
+pub mod ledger;
+pub mod logging;
+pub mod notebooklm;
+pub mod oauth;
+pub mod pipeline;
+pub mod plan;
+pub mod progress;
+pub mod raindrop;
+pub mod redact;
+pub mod report;
+pub mod retry;
+pub mod shard;
+pub mod snapshot;
+pub mod state;
+pub mod status;
+pub mod sync;
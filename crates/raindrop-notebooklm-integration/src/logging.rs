@@ -0,0 +1,230 @@
+//! Log output: to stderr, and to a size-rotated file if asked (requirement
+//! 5.3), as readable lines or JSON. `RUST_LOG`, when set, decides what is
+//! logged; the command line only picks the default level.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use env_logger::filter::{self, Filter};
+use indicatif::MultiProgress;
+use log::LevelFilter;
+use serde_json::json;
+
+use crate::error::{Result, StorageError};
+use crate::redact;
+
+/// A log file is moved aside once it would grow past this size.
+pub const LOG_FILE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Rotated log files kept besides the current one.
+pub const LOG_FILE_KEEP: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// `[time LEVEL target] message`
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// Used unless `RUST_LOG` is set.
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    pub file: Option<PathBuf>,
+    /// Off for terminals and log sinks that mangle emoji.
+    pub emoji: bool,
+}
+
+/// Installs the logger. Log lines are written with the progress bars in
+/// `multi` out of the way; otherwise the two tear each other.
+pub fn init(options: &LogOptions, multi: MultiProgress) -> Result<()> {
+    let rust_log = std::env::var("RUST_LOG").ok();
+    let filter = level_filter(options.level, rust_log.as_deref());
+    let file = options
+        .file
+        .as_deref()
+        .map(|path| RotatingFile::open(path, LOG_FILE_MAX_BYTES, LOG_FILE_KEEP))
+        .transpose()?
+        .map(Mutex::new);
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(Logger {
+        filter,
+        format: options.format,
+        emoji: options.emoji,
+        file,
+        multi,
+    }))
+    .expect("the logger is set once at startup");
+    Ok(())
+}
+
+/// `rust_log` if it is set, otherwise everything at `level` or above.
+pub fn level_filter(level: LevelFilter, rust_log: Option<&str>) -> Filter {
+    let mut builder = filter::Builder::new();
+    match rust_log.filter(|spec| !spec.trim().is_empty()) {
+        Some(spec) => builder.parse(spec),
+        None => builder.filter_level(level),
+    };
+    builder.build()
+}
+
+/// One log line, without the trailing newline.
+pub fn render(record: &log::Record, format: LogFormat, emoji: bool, at: DateTime<Utc>) -> String {
+    let message = redact::redact(&record.args().to_string());
+    let message = if emoji {
+        message
+    } else {
+        strip_emoji(&message)
+    };
+    let timestamp = at.format("%Y-%m-%dT%H:%M:%SZ");
+    match format {
+        LogFormat::Pretty => format!(
+            "[{} {:<5} {}] {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            message
+        ),
+        LogFormat::Json => json!({
+            "timestamp": timestamp.to_string(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": message,
+        })
+        .to_string(),
+    }
+}
+
+/// Removes emoji and the space after each, so that `📄 Fetching` becomes
+/// `Fetching`.
+pub fn strip_emoji(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !is_emoji(c) {
+            stripped.push(c);
+            continue;
+        }
+        while chars.peek().copied().is_some_and(is_emoji) {
+            chars.next();
+        }
+        if chars.peek() == Some(&' ') {
+            chars.next();
+        }
+    }
+    stripped
+}
+
+/// The pictographs, symbols and dingbats this tool logs, with the joiner
+/// and variation selector that combine them.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        u32::from(c),
+        0x1F000..=0x1FAFF | 0x2300..=0x23FF | 0x2600..=0x27BF | 0x200D | 0xFE0F
+    )
+}
+
+/// A file that is moved aside as `<path>.1` once it grows past a size.
+/// Earlier ones shift to `.2`, `.3` and so on; the oldest beyond `keep`
+/// is dropped.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Appends to `path`, creating it and its directory if needed.
+    pub fn open(path: &Path, max_bytes: u64, keep: usize) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(StorageError::io(dir))?;
+        }
+        let file = append(path).map_err(StorageError::io(path))?;
+        let size = file.metadata().map_err(StorageError::io(path))?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep: keep.max(1),
+            file,
+            size,
+        })
+    }
+
+    /// Rotates first if the line would take the file past its size; a
+    /// line longer than that still gets a file of its own.
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for n in (1..self.keep).rev() {
+            let older = numbered(&self.path, n);
+            if older.exists() {
+                std::fs::rename(&older, numbered(&self.path, n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, numbered(&self.path, 1))?;
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `sync.log` becomes `sync.log.1`.
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+struct Logger {
+    filter: Filter,
+    format: LogFormat,
+    emoji: bool,
+    file: Option<Mutex<RotatingFile>>,
+    multi: MultiProgress,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let line = render(record, self.format, self.emoji, Utc::now());
+        self.multi.suspend(|| eprintln!("{}", line));
+        if let Some(file) = &self.file {
+            // A log line that cannot be written is no reason to stop a run.
+            let _ = file
+                .lock()
+                .expect("log file lock poisoned")
+                .write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().expect("log file lock poisoned").file.flush();
+        }
+    }
+}
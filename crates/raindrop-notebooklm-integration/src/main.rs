@@ -1,73 +1,948 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
 use clap::Parser;
-use log::{info, warn, error};
+use indicatif::MultiProgress;
+use log::{error, info, warn, LevelFilter};
+use zeroize::Zeroizing;
+
+use raindrop_notebooklm_integration::config::{self, Config, ConfigBuilder, ConfigLayer};
+use raindrop_notebooklm_integration::content::ContentFetcher;
+use raindrop_notebooklm_integration::credentials::{
+    Backend, CredentialKind, CredentialStore, Secret,
+};
+use raindrop_notebooklm_integration::error::{
+    exit_code, AppError, ConfigError, CredentialError, Result, StorageError,
+};
+use raindrop_notebooklm_integration::events::{EventKind, Events};
+use raindrop_notebooklm_integration::export::{self, ExportOptions};
+use raindrop_notebooklm_integration::ledger::FailureClass;
+use raindrop_notebooklm_integration::logging::{self, LogFormat, LogOptions};
+use raindrop_notebooklm_integration::notebooklm::NotebookLMClient;
+use raindrop_notebooklm_integration::oauth::{self, Loopback, OAuthClient};
+use raindrop_notebooklm_integration::pipeline::FetchLimits;
+use raindrop_notebooklm_integration::plan::ExecutionPlan;
+use raindrop_notebooklm_integration::progress::{self, ProgressTracker};
+use raindrop_notebooklm_integration::raindrop::{CollectionId, RaindropClient, TagMatch};
+use raindrop_notebooklm_integration::redact;
+use raindrop_notebooklm_integration::retry::{RetryLimits, RetryPolicy};
+use raindrop_notebooklm_integration::shard::ShardLimits;
+use raindrop_notebooklm_integration::status::{self, CheckOutcome, ProfileStatus, Service};
+use raindrop_notebooklm_integration::sync::{self, ApplyOptions, RetryOptions, SyncOptions};
 
 #[derive(Parser)]
 #[command(name = "raindrop-notebooklm-integration")]
 #[command(about = "A CLI tool to integrate Raindrop bookmarks with NotebookLM")]
 #[command(version)]
 struct Cli {
-    /// Enable verbose logging
-    #[arg(short, long)]
+    /// Enable verbose logging (RUST_LOG, if set, takes precedence)
+    #[arg(short, long, global = true)]
     verbose: bool,
+
+    /// Only log warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Format of log lines, on stderr and in the log file
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+
+    /// Also write log lines to FILE, rotating it once it passes 10 MiB
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Leave emoji out of log lines
+    #[arg(long, global = true)]
+    no_emoji: bool,
+
+    /// Configuration profile to use
+    #[arg(long, global = true, default_value = config::DEFAULT_PROFILE)]
+    profile: String,
+
+    /// How to report results on stdout; ndjson streams events from sync and status
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    output: OutputFormat,
     
     /// The command to execute
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// Log lines and readable plans
+    Human,
+    /// One JSON document with the result
+    Json,
+    /// One JSON event per line as the run progresses
+    Ndjson,
+}
+
 #[derive(clap::Subcommand)]
 enum Commands {
     /// Sync bookmarks from Raindrop to NotebookLM
     Sync {
-        /// Dry run - don't actually perform sync
+        /// Dry run - change nothing in NotebookLM or the sync state (the snapshot and summary are still written)
         #[arg(long)]
         dry_run: bool,
+
+        /// Raindrop tag to sync (repeat for several tags)
+        #[arg(long = "tag", required_unless_present = "apply_plan")]
+        tags: Vec<String>,
+
+        /// Whether bookmarks must carry all of the tags or any of them
+        #[arg(long = "match", value_enum, default_value_t = TagMatch::All)]
+        tag_match: TagMatch,
+
+        /// Maximum number of URLs to sync, newest first
+        #[arg(long)]
+        max_urls: Option<usize>,
+
+        /// Directory that receives every artifact of the run [default: ./output]
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Raindrop collection to search (0 searches all collections)
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        collection: i64,
+
+        /// Run once for every profile defined in the configuration
+        #[arg(long)]
+        all_profiles: bool,
+
+        /// Forget bookmarks that were synced before but no longer match
+        #[arg(long)]
+        prune: bool,
+
+        /// Print the dry-run plan as JSON
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+
+        /// Also print the Markdown summary written to the output directory (human output only)
+        #[arg(long)]
+        summary: bool,
+
+        /// Save the plan to FILE for review and a later --apply-plan
+        #[arg(long, value_name = "FILE", conflicts_with = "all_profiles")]
+        plan_out: Option<PathBuf>,
+
+        /// Execute a plan saved with --plan-out instead of planning anew
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["dry_run", "all_profiles", "plan_out", "summary"]
+        )]
+        apply_plan: Option<PathBuf>,
+    },
+    /// Fetch the bookmarks that failed in earlier syncs again
+    RetryFailed {
+        /// Only retry failures of this class (repeat for several classes)
+        #[arg(long = "class", value_enum)]
+        classes: Vec<FailureClass>,
+
+        /// Dry run - fetch again, but don't upload anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Print the dry-run plans as JSON
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+
+        /// Directory that holds the sync state [default: ./output]
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Run once for every profile defined in the configuration
+        #[arg(long)]
+        all_profiles: bool,
+    },
+    /// Write a Markdown bundle for uploading to NotebookLM by hand
+    Export {
+        /// Raindrop tag to export (repeat for several tags)
+        #[arg(long = "tag", required = true)]
+        tags: Vec<String>,
+
+        /// Whether bookmarks must carry all of the tags or any of them
+        #[arg(long = "match", value_enum, default_value_t = TagMatch::All)]
+        tag_match: TagMatch,
+
+        /// Maximum number of URLs to export, newest first
+        #[arg(long)]
+        max_urls: Option<usize>,
+
+        /// Directory that receives the bundle under export/<profile> [default: ./output]
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+
+        /// Raindrop collection to search (0 searches all collections)
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        collection: i64,
+
+        /// Run once for every profile defined in the configuration
+        #[arg(long)]
+        all_profiles: bool,
+
+        /// Maximum number of words per digest file
+        #[arg(long, default_value_t = export::DEFAULT_DIGEST_MAX_WORDS)]
+        digest_max_words: usize,
     },
     /// Check connection to both services
-    Status,
+    Status {
+        /// Check every profile defined in the configuration
+        #[arg(long)]
+        all_profiles: bool,
+
+        /// Print the result as JSON for monitoring
+        #[arg(long)]
+        json: bool,
+    },
+    /// Inspect the layered configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Store credentials outside of configuration files
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommands,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ConfigCommands {
+    /// Show configuration files and values
+    Show {
+        /// Print the merged values and the source of each, with secrets redacted
+        #[arg(long)]
+        resolved: bool,
+    },
 }
 
-fn main() {
+#[derive(clap::Subcommand)]
+enum AuthCommands {
+    /// Store an API key or access token for the selected profile in the system keyring
+    Login {
+        /// Service the key is for
+        #[arg(value_enum)]
+        service: CredentialKind,
+
+        /// Where to keep the key [default: the system keyring, or the encrypted file without one]
+        #[arg(long, value_enum)]
+        store: Option<Backend>,
+
+        /// Read the key from stdin instead of prompting for it
+        #[arg(long)]
+        stdin: bool,
+
+        /// Sign in to Raindrop in the browser with the configured OAuth app
+        #[arg(long, conflicts_with = "stdin")]
+        oauth: bool,
+
+        /// Local port the browser is sent back to (0 picks a free one)
+        #[arg(long, default_value_t = 0, requires = "oauth")]
+        port: u16,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
     let cli = Cli::parse();
     
     // Initialize logger
-    let log_level = if cli.verbose { "debug" } else { "info" };
-    std::env::set_var("RUST_LOG", log_level);
-    env_logger::init();
+    let log_options = LogOptions {
+        level: if cli.verbose {
+            LevelFilter::Debug
+        } else if cli.quiet {
+            LevelFilter::Warn
+        } else {
+            LevelFilter::Info
+        },
+        format: cli.log_format,
+        file: cli.log_file.clone(),
+        emoji: !cli.no_emoji,
+    };
+    let multi = MultiProgress::new();
+    if let Err(e) = logging::init(&log_options, multi.clone()) {
+        eprintln!("{}", e.chain());
+        return ExitCode::from(e.exit_code());
+    }
 
     info!("🚀 Starting raindrop-notebooklm-integration CLI");
     info!("Version: {}", env!("CARGO_PKG_VERSION"));
-    
+
+    match run(cli, multi).await {
+        Ok(code) => {
+            if code == ExitCode::SUCCESS {
+                info!("🎉 Application finished successfully");
+            }
+            code
+        }
+        Err(e) => {
+            error!("❌ {}", e.chain());
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Runs the selected command. Errors map to exit codes through
+/// `AppError::exit_code`; `status` reports unhealthy services through its
+/// own exit code because the check itself did not fail.
+async fn run(cli: Cli, multi: MultiProgress) -> Result<ExitCode> {
     match cli.command {
-        Some(Commands::Sync { dry_run }) => {
+        Some(Commands::Sync {
+            dry_run,
+            tags,
+            tag_match,
+            collection,
+            max_urls,
+            output_dir,
+            all_profiles,
+            prune,
+            json,
+            summary,
+            plan_out,
+            apply_plan,
+        }) => {
             info!("📋 Executing sync command");
-            if dry_run {
-                warn!("🔍 Running in dry-run mode - no actual changes will be made");
+
+            let cli_layer = ConfigLayer {
+                max_urls,
+                output_dir,
+                ..ConfigLayer::default()
+            };
+            let json = json || cli.output == OutputFormat::Json;
+            let human = cli.output == OutputFormat::Human;
+            if let Some(path) = apply_plan {
+                let plan = ExecutionPlan::load(&path)?;
+                let config = config_builder(cli_layer)?.build(&cli.profile)?;
+                if plan.profile != config.profile {
+                    return Err(AppError::Config(ConfigError::Invalid(format!(
+                        "{} was planned for profile '{}', not '{}'",
+                        path.display(),
+                        plan.profile,
+                        config.profile
+                    ))));
+                }
+                info!("📜 Applying {} from {}", plan.selection, path.display());
+                let tracker = human.then(|| ProgressTracker::for_stdout(multi.clone()));
+                let (events, follower) = follow_events(cli.output, tracker);
+                let events = events.with_profile(&config.profile);
+                events.emit(run_started("sync", false));
+                let options = ApplyOptions {
+                    output_dir: config.output_dir.clone(),
+                    state_dir: config.state_dir.clone(),
+                    config: config
+                        .entries()
+                        .into_iter()
+                        .map(|entry| (entry.key.to_string(), entry.value))
+                        .collect(),
+                    events: events.clone(),
+                };
+                let result = async {
+                    let notebooklm = notebooklm_client(&config, &retry_policy(&config))?;
+                    let run = sync::apply_plan(plan, &notebooklm, &options).await?;
+                    if json {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&run.plan)
+                                .expect("plans contain only plain data")
+                        );
+                    }
+                    if summary && human {
+                        print!("{}", run.summary);
+                    }
+                    run.plan.check_fetched()
+                }
+                .await;
+                events.emit(EventKind::RunFinished {
+                    exit_code: result
+                        .as_ref()
+                        .err()
+                        .map_or(exit_code::OK, AppError::exit_code),
+                    error: result.as_ref().err().map(AppError::chain),
+                });
+                drop(options);
+                drop(events);
+                let _ = follower.await;
+                result?;
+                info!("✅ Sync completed successfully");
+                return Ok(ExitCode::SUCCESS);
+            }
+            let configs = load_profiles(cli_layer, &cli.profile, all_profiles)?;
+            let tracker = human.then(|| ProgressTracker::for_stdout(multi.clone()));
+            let (events, follower) = follow_events(cli.output, tracker);
+
+            let mut failures = Vec::new();
+            for config in &configs {
+                info!("👥 Profile: {}", config.profile);
+                let events = events.clone().with_profile(&config.profile);
+                events.emit(run_started("sync", dry_run));
+                // Each profile is a run of its own with a fresh retry budget.
+                let retry = retry_policy(config);
+                let fetcher = content_fetcher(config, &retry);
+                let options = SyncOptions {
+                    profile: config.profile.clone(),
+                    tags: tags.clone(),
+                    tag_match,
+                    collection: CollectionId(collection),
+                    max_urls: config.max_urls,
+                    output_dir: config.output_dir.clone(),
+                    state_dir: config.state_dir.clone(),
+                    dry_run,
+                    prune,
+                    notebook_title: config.notebook_title.clone(),
+                    shard_limits: ShardLimits {
+                        max_sources: config.notebook_max_sources,
+                        max_words: config.notebook_max_words,
+                    },
+                    fetch_limits: fetch_limits(config),
+                    config: config
+                        .entries()
+                        .into_iter()
+                        .map(|entry| (entry.key.to_string(), entry.value))
+                        .collect(),
+                    events: events.clone(),
+                };
+                let result = async {
+                    let client = RaindropClient::from_config(config)?
+                        .with_retry(retry.clone())
+                        .with_events(events.clone());
+                    // A dry run only reads from NotebookLM, and only if it
+                    // is configured.
+                    let notebooklm = if dry_run {
+                        notebooklm_client(config, &retry).ok()
+                    } else {
+                        Some(notebooklm_client(config, &retry)?)
+                    };
+                    let run = sync::run(&client, &fetcher, notebooklm.as_ref(), &options).await?;
+                    let plan = run.plan;
+                    if json {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&plan)
+                                .expect("plans contain only plain data")
+                        );
+                    } else if dry_run && human {
+                        println!("{}", plan);
+                    }
+                    if summary && human {
+                        print!("{}", run.summary);
+                    }
+                    if let Some(path) = &plan_out {
+                        plan.save(path)?;
+                        info!("💾 Plan saved to {}", path.display());
+                    }
+                    plan.check_fetched()
+                }
+                .await;
+                events.emit(EventKind::RunFinished {
+                    exit_code: result.as_ref().err().map_or(exit_code::OK, AppError::exit_code),
+                    error: result.as_ref().err().map(AppError::chain),
+                });
+                if let Err(e) = result {
+                    error!("❌ Sync failed for profile {}: {}", config.profile, e.chain());
+                    failures.push(e);
+                }
             }
-            
-            info!("🔗 Connecting to Raindrop API...");
-            info!("📚 Connecting to NotebookLM API...");
-            info!("⚡ Synchronization process would start here");
-            
+            drop(events);
+            let _ = follower.await;
+            // With several profiles the first failure decides the exit code;
+            // the others have already been logged above.
+            if let Some(first) = failures.into_iter().next() {
+                return Err(first);
+            }
+
             if dry_run {
                 info!("✅ Dry run completed successfully");
             } else {
                 info!("✅ Sync completed successfully");
             }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Commands::RetryFailed {
+            classes,
+            dry_run,
+            json,
+            output_dir,
+            all_profiles,
+        }) => {
+            info!("📋 Executing retry-failed command");
+
+            let cli_layer = ConfigLayer {
+                output_dir,
+                ..ConfigLayer::default()
+            };
+            let configs = load_profiles(cli_layer, &cli.profile, all_profiles)?;
+
+            let mut failures = Vec::new();
+            for config in &configs {
+                info!("👥 Profile: {}", config.profile);
+                let retry = retry_policy(config);
+                let fetcher = content_fetcher(config, &retry);
+                let options = RetryOptions {
+                    profile: config.profile.clone(),
+                    classes: classes.clone(),
+                    state_dir: config.state_dir.clone(),
+                    dry_run,
+                    notebook_title: config.notebook_title.clone(),
+                    shard_limits: ShardLimits {
+                        max_sources: config.notebook_max_sources,
+                        max_words: config.notebook_max_words,
+                    },
+                    fetch_limits: fetch_limits(config),
+                };
+                let result = async {
+                    let notebooklm = if dry_run {
+                        None
+                    } else {
+                        Some(notebooklm_client(config, &retry)?)
+                    };
+                    let plans = sync::retry_failed(&fetcher, notebooklm.as_ref(), &options).await?;
+                    if json || cli.output == OutputFormat::Json {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&plans)
+                                .expect("plans contain only plain data")
+                        );
+                    } else if dry_run {
+                        plans.iter().for_each(|plan| println!("{}", plan));
+                    }
+                    plans.iter().try_for_each(ExecutionPlan::check_fetched)
+                }
+                .await;
+                if let Err(e) = result {
+                    error!(
+                        "❌ Retry failed for profile {}: {}",
+                        config.profile,
+                        e.chain()
+                    );
+                    failures.push(e);
+                }
+            }
+            if let Some(first) = failures.into_iter().next() {
+                return Err(first);
+            }
+
+            info!("✅ Retry completed successfully");
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Commands::Export {
+            tags,
+            tag_match,
+            collection,
+            max_urls,
+            output_dir,
+            all_profiles,
+            digest_max_words,
+        }) => {
+            info!("📋 Executing export command");
+
+            let cli_layer = ConfigLayer {
+                max_urls,
+                output_dir,
+                ..ConfigLayer::default()
+            };
+            let configs = load_profiles(cli_layer, &cli.profile, all_profiles)?;
+
+            let mut failures = Vec::new();
+            for config in &configs {
+                info!("👥 Profile: {}", config.profile);
+                // Each profile is a run of its own with a fresh retry budget.
+                let retry = retry_policy(config);
+                let fetcher = content_fetcher(config, &retry);
+                let options = ExportOptions {
+                    tags: tags.clone(),
+                    tag_match,
+                    collection: CollectionId(collection),
+                    max_urls: config.max_urls,
+                    dir: config
+                        .output_dir
+                        .join(export::EXPORT_DIR)
+                        .join(&config.profile),
+                    digest_max_words,
+                    fetch_limits: fetch_limits(config),
+                };
+                let result = async {
+                    let client = RaindropClient::from_config(config)?.with_retry(retry.clone());
+                    export::run(&client, &fetcher, &options).await
+                }
+                .await;
+                match result {
+                    Ok(bundle) => info!("📑 Index: {}", bundle.index.display()),
+                    Err(e) => {
+                        error!(
+                            "❌ Export failed for profile {}: {}",
+                            config.profile,
+                            e.chain()
+                        );
+                        failures.push(e);
+                    }
+                }
+            }
+            if let Some(first) = failures.into_iter().next() {
+                return Err(first);
+            }
+
+            info!("✅ Export completed successfully");
+            Ok(ExitCode::SUCCESS)
         }
-        Some(Commands::Status) => {
+        Some(Commands::Status { all_profiles, json }) => {
             info!("🔍 Checking service status");
-            info!("🔗 Raindrop API: Connection would be checked here");
-            info!("📚 NotebookLM API: Connection would be checked here");
+            let configs = load_profiles(ConfigLayer::default(), &cli.profile, all_profiles)?;
+            let (events, follower) = follow_events(cli.output, None);
+
+            let mut reports = Vec::new();
+            for config in &configs {
+                let events = events.clone().with_profile(&config.profile);
+                events.emit(run_started("status", false));
+                let report = status::check(config).await;
+                report.services.iter().for_each(|service| {
+                    events.emit(EventKind::ServiceChecked {
+                        status: service.clone(),
+                    })
+                });
+                events.emit(EventKind::RunFinished {
+                    exit_code: report.exit_code(),
+                    error: None,
+                });
+                reports.push(report);
+            }
+            drop(events);
+            let _ = follower.await;
+
+            if json || cli.output == OutputFormat::Json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&reports)
+                        .expect("status reports contain only strings and numbers")
+                );
+            } else if cli.output == OutputFormat::Human {
+                reports.iter().for_each(log_status);
+            }
+
+            let code = reports
+                .iter()
+                .map(ProfileStatus::exit_code)
+                .find(|&code| code != exit_code::OK)
+                .unwrap_or(exit_code::OK);
+            if code != exit_code::OK {
+                error!("❌ Status check failed");
+                return Ok(ExitCode::from(code));
+            }
             info!("✅ Status check completed");
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Commands::Config {
+            command: ConfigCommands::Show { resolved },
+        }) => {
+            show_config(&cli.profile, resolved)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Commands::Auth {
+            command:
+                AuthCommands::Login {
+                    service,
+                    store,
+                    stdin,
+                    oauth,
+                    port,
+                },
+        }) => {
+            if oauth {
+                if service != CredentialKind::Raindrop {
+                    return Err(AppError::Config(ConfigError::Invalid(
+                        "--oauth is only available for raindrop".to_string(),
+                    )));
+                }
+                login_oauth(&cli.profile, port).await?;
+            } else {
+                login(&cli.profile, service, store, stdin)?;
+            }
+            Ok(ExitCode::SUCCESS)
         }
         None => {
             info!("📖 No command specified. Use --help for available commands");
             info!("Available commands:");
             info!("  - sync: Synchronize bookmarks from Raindrop to NotebookLM");
+            info!("  - retry-failed: Fetch the bookmarks that failed in earlier syncs again");
+            info!("  - export: Write a Markdown bundle for manual upload");
             info!("  - status: Check connection to both services");
+            info!("  - config: Inspect the layered configuration");
+            info!("  - auth: Store credentials or sign in outside of configuration files");
+            Ok(ExitCode::SUCCESS)
         }
     }
-    
-    info!("🎉 Application finished successfully");
+}
+
+/// Follows the events of a command while it runs: prints each as a JSON
+/// line for ndjson output and keeps `tracker` up to date. With neither to
+/// do, the events are dropped as they are emitted.
+fn follow_events(
+    output: OutputFormat,
+    mut tracker: Option<ProgressTracker>,
+) -> (Events, tokio::task::JoinHandle<()>) {
+    let ndjson = output == OutputFormat::Ndjson;
+    if !ndjson && tracker.is_none() {
+        return (Events::default(), tokio::spawn(async {}));
+    }
+    let (events, mut receiver) = Events::channel();
+    let follower = tokio::spawn(async move {
+        let interval = tracker.as_ref().map_or(progress::LOG_INTERVAL, ProgressTracker::interval);
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        loop {
+            tokio::select! {
+                event = receiver.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    if ndjson {
+                        let mut line =
+                            serde_json::to_value(&event).expect("events contain only plain data");
+                        redact::redact_json(&mut line);
+                        println!("{}", line);
+                    }
+                    tracker.iter_mut().for_each(|tracker| tracker.update(&event));
+                }
+                _ = ticks.tick() => tracker.iter_mut().for_each(ProgressTracker::tick),
+            }
+        }
+        tracker.iter_mut().for_each(ProgressTracker::finish);
+    });
+    (events, follower)
+}
+
+fn run_started(command: &str, dry_run: bool) -> EventKind {
+    EventKind::RunStarted {
+        command: command.to_string(),
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        dry_run,
+    }
+}
+
+fn content_fetcher(config: &Config, retry: &RetryPolicy) -> ContentFetcher {
+    ContentFetcher::new()
+        .with_retry(retry.clone())
+        .with_max_bytes(config.fetch_max_bytes)
+}
+
+fn fetch_limits(config: &Config) -> FetchLimits {
+    FetchLimits {
+        concurrency: config.fetch_concurrency,
+        per_host: config.fetch_per_host,
+        host_delay: Duration::from_millis(config.fetch_host_delay_ms),
+    }
+}
+
+fn retry_policy(config: &Config) -> RetryPolicy {
+    RetryPolicy::new(RetryLimits {
+        max_retries: config.retry_max_retries,
+        base_delay: Duration::from_millis(config.retry_base_delay_ms),
+        max_delay: Duration::from_millis(config.retry_max_delay_ms),
+        budget: config.retry_budget,
+    })
+}
+
+fn notebooklm_client(config: &Config, retry: &RetryPolicy) -> Result<NotebookLMClient> {
+    Ok(NotebookLMClient::from_config(config)?.with_retry(retry.clone()))
+}
+
+fn log_status(report: &ProfileStatus) {
+    info!("👥 Profile: {}", report.profile);
+    report.services.iter().for_each(|service| {
+        let icon = match service.service {
+            Service::Raindrop => "🔗",
+            Service::NotebookLM => "📚",
+        };
+        let latency = service
+            .latency_ms
+            .map(|ms| format!(" in {} ms", ms))
+            .unwrap_or_default();
+        match &service.outcome {
+            CheckOutcome::Ok {
+                account,
+                rate_limit,
+            } => {
+                let headroom = rate_limit
+                    .as_ref()
+                    .and_then(|limit| limit.remaining.zip(limit.limit))
+                    .map(|(remaining, limit)| format!(", {}/{} requests left", remaining, limit))
+                    .unwrap_or_default();
+                info!(
+                    "{} {}: ✅ reachable, authenticated as {}{}{}",
+                    icon, service.service, account, latency, headroom
+                );
+            }
+            CheckOutcome::NotConfigured { reason } => {
+                warn!("{} {}: ⚙️ not configured: {}", icon, service.service, reason)
+            }
+            CheckOutcome::Unreachable { reason } => {
+                error!("{} {}: ❌ unreachable: {}", icon, service.service, reason)
+            }
+            CheckOutcome::Unauthorized { http_status } => error!(
+                "{} {}: 🔒 reachable{}, but credentials were rejected (HTTP {})",
+                icon, service.service, latency, http_status
+            ),
+            CheckOutcome::ApiError { reason, .. } => {
+                error!("{} {}: ❌ unexpected response{}: {}", icon, service.service, latency, reason)
+            }
+        }
+    });
+}
+
+/// Layers defaults, the user file, the project file in the working
+/// directory, environment variables and finally command-line flags.
+fn config_builder(cli_layer: ConfigLayer) -> Result<ConfigBuilder> {
+    Ok(ConfigBuilder::new()
+        .with_user_file()?
+        .with_project_file(&current_dir()?)?
+        .with_env(std::env::vars())?
+        .with_cli(cli_layer)
+        .with_credentials(credential_store()))
+}
+
+fn load_profiles(
+    cli_layer: ConfigLayer,
+    profile: &str,
+    all_profiles: bool,
+) -> Result<Vec<Config>> {
+    let builder = config_builder(cli_layer)?;
+    let names = if all_profiles {
+        builder.profile_names()
+    } else {
+        vec![profile.to_string()]
+    };
+    names
+        .iter()
+        .map(|name| builder.clone().build(name))
+        .collect()
+}
+
+fn show_config(profile: &str, resolved: bool) -> Result<()> {
+    let user_file = config::user_config_path();
+    let project_file = current_dir()?.join(config::PROJECT_CONFIG_FILE);
+    if !resolved {
+        let describe = |path: &std::path::Path| {
+            let state = if path.exists() { "found" } else { "not found" };
+            println!("{} ({})", path.display(), state);
+        };
+        println!("Configuration files, lowest precedence first:");
+        user_file.as_deref().into_iter().for_each(describe);
+        describe(&project_file);
+        println!("Run `config show --resolved` to see merged values.");
+        return Ok(());
+    }
+
+    config_builder(ConfigLayer::default())?
+        .build(profile)?
+        .entries()
+        .iter()
+        .for_each(|entry| {
+            let value = entry.value.as_deref().unwrap_or("<unset>");
+            match &entry.source {
+                Some(source) => println!("{} = {}  # from {}", entry.key, value, source),
+                None => println!("{} = {}", entry.key, value),
+            }
+        });
+    Ok(())
+}
+
+/// The system keyring with the encrypted file as fallback; its passphrase
+/// is asked for on the terminal unless the environment holds it.
+fn credential_store() -> CredentialStore {
+    let store = CredentialStore::new();
+    if std::io::stdin().is_terminal() {
+        store.with_prompt()
+    } else {
+        store
+    }
+}
+
+fn login(
+    profile: &str,
+    service: CredentialKind,
+    store: Option<Backend>,
+    stdin: bool,
+) -> Result<()> {
+    let entry = service.entry(profile);
+    let read_error = CredentialError::entry(&entry);
+    let secret = if stdin {
+        let mut line = Zeroizing::new(String::new());
+        std::io::stdin().read_line(&mut line).map_err(|source| {
+            read_error(CredentialError::Io {
+                path: PathBuf::from("<stdin>"),
+                source,
+            })
+        })?;
+        Secret::new(line.trim())
+    } else {
+        rpassword::prompt_password(format!(
+            "{} {} for profile {}: ",
+            service.as_str(),
+            service.describe(),
+            profile
+        ))
+        .map(Secret::new)
+        .map_err(|source| {
+            read_error(CredentialError::Io {
+                path: PathBuf::from("/dev/tty"),
+                source,
+            })
+        })?
+    };
+    if secret.is_empty() {
+        return Err(AppError::Config(ConfigError::Invalid(format!(
+            "no {} {} was entered",
+            service.as_str(),
+            service.describe()
+        ))));
+    }
+
+    let backend = credential_store()
+        .set(&entry, &secret, store)
+        .map_err(CredentialError::entry(&entry))?;
+    info!(
+        "🔐 Stored {} in {}; refer to it in the configuration as",
+        entry, backend
+    );
+    // On stdout, where the log's redaction of key assignments cannot mask it.
+    println!(
+        "{} = \"{}\"",
+        service.config_key(),
+        service.reference(profile)
+    );
+    Ok(())
+}
+
+/// Runs Raindrop's authorization-code flow and keeps the tokens in the
+/// credential store, where later runs of the profile find them.
+async fn login_oauth(profile: &str, port: u16) -> Result<()> {
+    let config = config_builder(ConfigLayer::default())?.build(profile)?;
+    let client = OAuthClient::from_config(&config)?;
+    let loopback = Loopback::bind(port).await?;
+    let tokens = oauth::authorize(&client, loopback, |url| {
+        info!("🌐 Opening Raindrop in the browser; if it does not open, visit:");
+        println!("{}", url);
+        // Best effort: without a desktop the URL above has to do.
+        let _ = std::process::Command::new("xdg-open")
+            .arg(url)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+    })
+    .await?;
+    // The store the configuration was read with, so the passphrase of the
+    // encrypted file is not asked for twice.
+    let store = config.credentials().cloned().unwrap_or_else(credential_store);
+    tokens.save(&store, profile)?;
+    info!(
+        "🔐 Signed in to Raindrop; tokens stored as {}",
+        oauth::token_entry(profile)
+    );
+    Ok(())
+}
+
+fn current_dir() -> Result<PathBuf> {
+    std::env::current_dir().map_err(StorageError::io("."))
 }
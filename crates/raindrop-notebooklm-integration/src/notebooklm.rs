@@ -0,0 +1,525 @@
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use reqwest::RequestBuilder;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::config::Config;
+use crate::credentials::Secret;
+use crate::error::{AppError, Result, ServiceError};
+use crate::http;
+use crate::redact;
+use crate::retry::RetryPolicy;
+
+/// NotebookLM Enterprise is served by the Discovery Engine API. The host is
+/// prefixed by the multi-region (`global-`, `us-`, `eu-`).
+pub const DEFAULT_ENDPOINT: &str = "https://global-discoveryengine.googleapis.com";
+pub const DEFAULT_LOCATION: &str = "global";
+/// The NotebookLM Enterprise web app, where notebooks are opened.
+pub const APP_URL: &str = "https://notebooklm.cloud.google.com";
+/// A standard notebook holds at most 50 sources of up to 500,000 words each.
+pub const DEFAULT_MAX_SOURCES: usize = 50;
+pub const DEFAULT_MAX_WORDS: usize = 50 * 500_000;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notebook {
+    pub notebook_id: String,
+    #[serde(default)]
+    pub title: String,
+    /// Only filled in when a single notebook is retrieved.
+    #[serde(default)]
+    pub sources: Vec<Source>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Source {
+    /// Full resource name, `projects/.../notebooks/.../sources/<id>`.
+    #[serde(default)]
+    pub name: String,
+    pub source_id: SourceId,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub settings: SourceSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SourceId {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceSettings {
+    #[serde(default)]
+    pub status: SourceStatus,
+}
+
+/// Sources are ingested asynchronously; a new source is usually `Pending`
+/// for a while before it can be used for grounding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SourceStatus {
+    #[serde(rename = "SOURCE_STATUS_PENDING")]
+    Pending,
+    #[serde(rename = "SOURCE_STATUS_COMPLETE")]
+    Complete,
+    #[serde(rename = "SOURCE_STATUS_FAILED")]
+    Failed,
+    #[default]
+    #[serde(other, rename = "SOURCE_STATUS_UNSPECIFIED")]
+    Unspecified,
+}
+
+/// What to add to a notebook.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum SourceContent {
+    /// NotebookLM fetches the page itself.
+    #[serde(rename = "webContent")]
+    Web(WebContent),
+    /// Text uploaded as is, e.g. Markdown extracted during sync.
+    #[serde(rename = "textContent")]
+    Text(TextContent),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebContent {
+    pub url: String,
+    pub source_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextContent {
+    pub source_name: String,
+    pub content: String,
+}
+
+impl SourceContent {
+    pub fn web(url: impl Into<String>, name: impl Into<String>) -> Self {
+        SourceContent::Web(WebContent {
+            url: url.into(),
+            source_name: name.into(),
+        })
+    }
+
+    pub fn text(name: impl Into<String>, content: impl Into<String>) -> Self {
+        SourceContent::Text(TextContent {
+            source_name: name.into(),
+            content: content.into(),
+        })
+    }
+}
+
+/// A Discovery Engine long-running operation.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Operation {
+    pub name: String,
+    #[serde(default)]
+    pub done: bool,
+    pub error: Option<OperationError>,
+    pub response: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OperationError {
+    #[serde(default)]
+    pub code: i32,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListNotebooksResponse {
+    #[serde(default)]
+    notebooks: Vec<Notebook>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSourcesRequest<'a> {
+    user_contents: &'a [SourceContent],
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateSourcesResponse {
+    #[serde(default)]
+    sources: Vec<Source>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeleteRequest {
+    names: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Empty {}
+
+pub struct NotebookLMClient {
+    http: reqwest::Client,
+    endpoint: String,
+    project: String,
+    location: String,
+    token: Mutex<Secret>,
+    /// Prints a fresh access token; run again whenever the API rejects the
+    /// current one.
+    token_command: Option<String>,
+    poll_interval: Duration,
+    poll_timeout: Duration,
+    retry: RetryPolicy,
+}
+
+impl NotebookLMClient {
+    pub fn new(token: impl Into<String>, project: impl Into<String>) -> Self {
+        let token = Secret::new(token);
+        redact::register_secret(&token);
+        NotebookLMClient {
+            http: http::client(),
+            endpoint: DEFAULT_ENDPOINT.to_string(),
+            project: project.into(),
+            location: DEFAULT_LOCATION.to_string(),
+            token: Mutex::new(token),
+            token_command: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            poll_timeout: DEFAULT_POLL_TIMEOUT,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Gets the access token by running `command` through the shell, e.g.
+    /// `gcloud auth print-access-token`, and runs it again whenever the API
+    /// rejects the token it printed.
+    pub fn with_token_command(self, command: impl Into<String>) -> Self {
+        NotebookLMClient {
+            token_command: Some(command.into()),
+            ..self
+        }
+    }
+
+    /// A client for the profile's project, authenticated by its token
+    /// command if it has one and by its access token otherwise.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let client = match &config.notebooklm_token_command {
+            Some(command) => {
+                NotebookLMClient::new(String::new(), config.require_notebooklm_project()?)
+                    .with_token_command(command)
+            }
+            None => NotebookLMClient::new(
+                config.require_notebooklm_access_token()?,
+                config.require_notebooklm_project()?,
+            ),
+        };
+        Ok(client
+            .with_endpoint(&config.notebooklm_endpoint)
+            .with_location(&config.notebooklm_location))
+    }
+
+    pub fn with_endpoint(self, endpoint: impl Into<String>) -> Self {
+        NotebookLMClient {
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            ..self
+        }
+    }
+
+    pub fn with_location(self, location: impl Into<String>) -> Self {
+        NotebookLMClient {
+            location: location.into(),
+            ..self
+        }
+    }
+
+    /// How often and for how long long-running operations are polled.
+    pub fn with_polling(self, interval: Duration, timeout: Duration) -> Self {
+        NotebookLMClient {
+            poll_interval: interval,
+            poll_timeout: timeout,
+            ..self
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        NotebookLMClient { retry, ..self }
+    }
+
+    pub fn project(&self) -> &str {
+        &self.project
+    }
+
+    /// Where the notebook opens in the web app.
+    pub fn notebook_url(&self, notebook_id: &str) -> String {
+        format!(
+            "{}/{}/notebook/{}?project={}",
+            APP_URL, self.location, notebook_id, self.project
+        )
+    }
+
+    fn notebooks_url(&self) -> String {
+        format!(
+            "{}/v1alpha/projects/{}/locations/{}/notebooks",
+            self.endpoint, self.project, self.location
+        )
+    }
+
+    fn source_name(&self, notebook_id: &str, source_id: &str) -> String {
+        format!(
+            "projects/{}/locations/{}/notebooks/{}/sources/{}",
+            self.project, self.location, notebook_id, source_id
+        )
+    }
+
+    /// Sends the request with the access token. With a token command, a 401
+    /// gets a fresh token and the request is sent once more.
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let again = request.try_clone();
+        let token = self.access_token().await?;
+        match (
+            http::send_json(request.bearer_auth(&*token), &self.retry).await,
+            again,
+        ) {
+            (Err(ServiceError::Unauthorized { status: 401 }), Some(again))
+                if self.token_command.is_some() =>
+            {
+                let token = self.renew_token(&token).await?;
+                http::send_json(again.bearer_auth(&*token), &self.retry)
+                    .await
+                    .map_err(AppError::NotebookLM)
+            }
+            (outcome, _) => outcome.map_err(AppError::NotebookLM),
+        }
+    }
+
+    async fn access_token(&self) -> Result<Secret> {
+        let mut token = self.token.lock().await;
+        if let (Some(command), true) = (&self.token_command, token.is_empty()) {
+            *token = run_token_command(command).await?;
+        }
+        Ok(token.clone())
+    }
+
+    /// Replaces the `rejected` token, unless a concurrent request already
+    /// has since it was handed out.
+    async fn renew_token(&self, rejected: &Secret) -> Result<Secret> {
+        let mut token = self.token.lock().await;
+        if let (Some(command), true) = (&self.token_command, *token == *rejected) {
+            *token = run_token_command(command).await?;
+        }
+        Ok(token.clone())
+    }
+
+    /// Sends a mutating request and waits for its operation, if it got one.
+    /// Mutating calls answer either with their result or, when the work takes
+    /// longer, with an operation; notebooks and sources have a `name` too, so
+    /// operations are told apart by their `.../operations/...` resource name.
+    async fn send_and_wait<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let reply: serde_json::Value = self.send(request).await?;
+        let is_operation = reply
+            .get("name")
+            .and_then(serde_json::Value::as_str)
+            .is_some_and(|name| name.contains("/operations/"));
+        if is_operation {
+            self.wait_for_operation(decode(reply)?).await
+        } else {
+            decode(reply)
+        }
+    }
+
+    /// Lists the notebooks the caller opened most recently. With a page size
+    /// of one this is the cheapest authenticated request the API offers.
+    pub async fn list_recently_viewed(&self, page_size: usize) -> Result<Vec<Notebook>> {
+        let request = self
+            .http
+            .get(format!("{}:listRecentlyViewed", self.notebooks_url()))
+            .query(&[("pageSize", page_size.to_string())]);
+        self.send::<ListNotebooksResponse>(request)
+            .await
+            .map(|body| body.notebooks)
+    }
+
+    pub async fn create_notebook(&self, title: &str) -> Result<Notebook> {
+        let request = self
+            .http
+            .post(self.notebooks_url())
+            .json(&serde_json::json!({ "title": title }));
+        self.send_and_wait(request).await
+    }
+
+    pub async fn rename_notebook(&self, notebook_id: &str, title: &str) -> Result<Notebook> {
+        let request = self
+            .http
+            .patch(format!("{}/{}", self.notebooks_url(), notebook_id))
+            .query(&[("updateMask", "title")])
+            .json(&serde_json::json!({ "title": title }));
+        self.send_and_wait(request).await
+    }
+
+    pub async fn get_notebook(&self, notebook_id: &str) -> Result<Notebook> {
+        let request = self
+            .http
+            .get(format!("{}/{}", self.notebooks_url(), notebook_id));
+        self.send(request).await
+    }
+
+    /// The API has no separate listing call; sources come with the notebook.
+    pub async fn list_sources(&self, notebook_id: &str) -> Result<Vec<Source>> {
+        self.get_notebook(notebook_id)
+            .await
+            .map(|notebook| notebook.sources)
+    }
+
+    /// Adds several sources in one request. The returned sources are in the
+    /// order of `contents`.
+    pub async fn add_sources(
+        &self,
+        notebook_id: &str,
+        contents: &[SourceContent],
+    ) -> Result<Vec<Source>> {
+        let request = self
+            .http
+            .post(format!(
+                "{}/{}/sources:batchCreate",
+                self.notebooks_url(),
+                notebook_id
+            ))
+            .json(&CreateSourcesRequest {
+                user_contents: contents,
+            });
+        let response: CreateSourcesResponse = self.send_and_wait(request).await?;
+        if response.sources.len() != contents.len() {
+            return Err(AppError::NotebookLM(ServiceError::Unexpected(format!(
+                "asked for {} sources, got {}",
+                contents.len(),
+                response.sources.len()
+            ))));
+        }
+        Ok(response.sources)
+    }
+
+    pub async fn add_web_source(&self, notebook_id: &str, url: &str, name: &str) -> Result<Source> {
+        self.add_single_source(notebook_id, SourceContent::web(url, name))
+            .await
+    }
+
+    pub async fn add_text_source(
+        &self,
+        notebook_id: &str,
+        name: &str,
+        content: &str,
+    ) -> Result<Source> {
+        self.add_single_source(notebook_id, SourceContent::text(name, content))
+            .await
+    }
+
+    async fn add_single_source(&self, notebook_id: &str, content: SourceContent) -> Result<Source> {
+        let mut sources = self.add_sources(notebook_id, &[content]).await?;
+        Ok(sources.remove(0))
+    }
+
+    pub async fn delete_sources(&self, notebook_id: &str, source_ids: &[String]) -> Result<()> {
+        if source_ids.is_empty() {
+            return Ok(());
+        }
+        let request = self
+            .http
+            .post(format!(
+                "{}/{}/sources:batchDelete",
+                self.notebooks_url(),
+                notebook_id
+            ))
+            .json(&DeleteRequest {
+                names: source_ids
+                    .iter()
+                    .map(|id| self.source_name(notebook_id, id))
+                    .collect(),
+            });
+        self.send_and_wait::<Empty>(request).await.map(|_| ())
+    }
+
+    /// Polls the operation until it is done, then decodes its result.
+    pub async fn wait_for_operation<T: DeserializeOwned>(
+        &self,
+        mut operation: Operation,
+    ) -> Result<T> {
+        let deadline = Instant::now() + self.poll_timeout;
+        loop {
+            if operation.done {
+                return operation_result(operation);
+            }
+            if Instant::now() >= deadline {
+                return Err(AppError::NotebookLM(ServiceError::OperationTimeout {
+                    name: operation.name,
+                    seconds: self.poll_timeout.as_secs(),
+                }));
+            }
+            debug!("Waiting for operation {}", operation.name);
+            tokio::time::sleep(self.poll_interval).await;
+            let request = self
+                .http
+                .get(format!("{}/v1alpha/{}", self.endpoint, operation.name));
+            operation = self.send(request).await?;
+        }
+    }
+}
+
+fn operation_result<T: DeserializeOwned>(operation: Operation) -> Result<T> {
+    if let Some(error) = operation.error {
+        return Err(AppError::NotebookLM(ServiceError::OperationFailed {
+            name: operation.name,
+            code: error.code,
+            message: error.message,
+        }));
+    }
+    let response = operation
+        .response
+        .unwrap_or_else(|| serde_json::Value::Object(Default::default()));
+    decode(response)
+}
+
+/// Runs the token command through the shell and takes the token from the
+/// first line it prints.
+async fn run_token_command(command: &str) -> Result<Secret> {
+    let failed = |reason: String| {
+        AppError::NotebookLM(ServiceError::TokenCommand {
+            command: command.to_string(),
+            reason,
+        })
+    };
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = tokio::process::Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(failed(format!("{} ({})", output.status, stderr.trim())));
+    }
+    let first_line = String::from_utf8_lossy(&stdout)
+        .lines()
+        .next()
+        .map(|line| Secret::new(line.trim()));
+    let token = first_line
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| failed("it printed no token".to_string()))?;
+    redact::register_secret(&token);
+    debug!("Got a NotebookLM access token from the token command");
+    Ok(token)
+}
+
+fn decode<T: DeserializeOwned>(value: serde_json::Value) -> Result<T> {
+    serde_json::from_value(value).map_err(|e| AppError::NotebookLM(ServiceError::Json(e)))
+}
@@ -0,0 +1,488 @@
+//! Raindrop's OAuth2 authorization-code flow. `auth login raindrop --oauth`
+//! sends the browser to Raindrop, catches the redirect on a loopback
+//! listener and trades the code for tokens, which are kept in the
+//! credential store. An [`OAuthSession`] then hands the access token to the
+//! Raindrop client and refreshes it when it expires or is rejected.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::config::Config;
+use crate::credentials::{CredentialStore, Secret};
+use crate::error::{AppError, ConfigError, CredentialError, Result, ServiceError};
+use crate::http;
+use crate::redact;
+use crate::retry::RetryPolicy;
+
+pub const DEFAULT_OAUTH_URL: &str = "https://raindrop.io/oauth";
+
+/// How long `auth login` waits for the browser to come back.
+pub const AUTHORIZATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Access tokens this close to expiry are refreshed before they are used.
+const EXPIRY_MARGIN: chrono::Duration = chrono::Duration::seconds(60);
+
+/// How long a connection to the loopback listener may take to send its
+/// request.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CALLBACK_PATH: &str = "/callback";
+
+/// The stored tokens of a profile are kept as `<profile>/raindrop-oauth`.
+pub fn token_entry(profile: &str) -> String {
+    format!("{}/raindrop-oauth", profile)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tokens {
+    pub access_token: Secret,
+    pub refresh_token: Secret,
+    /// Unset if Raindrop did not say.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Tokens {
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at - EXPIRY_MARGIN <= now)
+    }
+
+    /// The tokens kept for `profile`, if `auth login` stored any.
+    pub fn load(store: &CredentialStore, profile: &str) -> Result<Option<Tokens>> {
+        let entry = token_entry(profile);
+        match store.get(&entry) {
+            Ok(json) => serde_json::from_str(&json).map(Some).map_err(|_| {
+                AppError::Config(ConfigError::Invalid(format!(
+                    "the Raindrop tokens stored as {} are damaged \
+                     (run `auth login raindrop --oauth` again)",
+                    entry
+                )))
+            }),
+            Err(CredentialError::NotFound(_)) => Ok(None),
+            Err(e) => Err(CredentialError::entry(&entry)(e)),
+        }
+    }
+
+    pub fn save(&self, store: &CredentialStore, profile: &str) -> Result<()> {
+        let entry = token_entry(profile);
+        let json = Secret::new(serde_json::to_string(self).expect("tokens are plain strings"));
+        store
+            .set(&entry, &json, None)
+            .map(|_| ())
+            .map_err(CredentialError::entry(&entry))
+    }
+}
+
+/// What Raindrop's token endpoint answers.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// Left out when a refresh keeps the old refresh token valid.
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+}
+
+/// A registered Raindrop app and the endpoints of its authorization server.
+pub struct OAuthClient {
+    http: reqwest::Client,
+    client_id: String,
+    client_secret: Secret,
+    oauth_url: String,
+    retry: RetryPolicy,
+}
+
+impl OAuthClient {
+    pub fn new(client_id: impl Into<String>, client_secret: impl Into<String>) -> Self {
+        let client_secret = Secret::new(client_secret);
+        redact::register_secret(&client_secret);
+        OAuthClient {
+            http: http::client(),
+            client_id: client_id.into(),
+            client_secret,
+            oauth_url: DEFAULT_OAUTH_URL.to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// The app configured for the profile.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let (Some(client_id), Some(client_secret)) =
+            (&config.raindrop_client_id, &config.raindrop_client_secret)
+        else {
+            return Err(AppError::Config(ConfigError::Invalid(
+                "Raindrop OAuth needs raindrop_client_id and raindrop_client_secret".to_string(),
+            )));
+        };
+        Ok(
+            OAuthClient::new(client_id.clone(), client_secret.to_string())
+                .with_oauth_url(&config.raindrop_oauth_url),
+        )
+    }
+
+    /// Where `/authorize` and `/access_token` are, normally
+    /// [`DEFAULT_OAUTH_URL`].
+    pub fn with_oauth_url(self, oauth_url: impl Into<String>) -> Self {
+        OAuthClient {
+            oauth_url: oauth_url.into().trim_end_matches('/').to_string(),
+            ..self
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        OAuthClient { retry, ..self }
+    }
+
+    /// The page the user approves access on.
+    pub fn authorize_url(&self, redirect_uri: &str, state: &str) -> String {
+        Url::parse_with_params(
+            &format!("{}/authorize", self.oauth_url),
+            [
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("state", state),
+            ],
+        )
+        .map(String::from)
+        .unwrap_or_else(|_| format!("{}/authorize", self.oauth_url))
+    }
+
+    /// Trades the code from the redirect for tokens.
+    pub async fn exchange_code(&self, code: &str, redirect_uri: &str) -> Result<Tokens> {
+        let response = self
+            .request_tokens(json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "client_id": self.client_id,
+                "client_secret": &*self.client_secret,
+                "redirect_uri": redirect_uri,
+            }))
+            .await?;
+        let refresh_token = response.refresh_token.clone().ok_or_else(|| {
+            AppError::Raindrop(ServiceError::Unexpected(
+                "the token response has no refresh token".to_string(),
+            ))
+        })?;
+        Ok(tokens(response, Secret::new(refresh_token)))
+    }
+
+    /// New tokens for `refresh_token`.
+    pub async fn refresh(&self, refresh_token: &Secret) -> Result<Tokens> {
+        let response = self
+            .request_tokens(json!({
+                "grant_type": "refresh_token",
+                "refresh_token": &**refresh_token,
+                "client_id": self.client_id,
+                "client_secret": &*self.client_secret,
+            }))
+            .await?;
+        let refresh_token = response
+            .refresh_token
+            .clone()
+            .map_or_else(|| refresh_token.clone(), Secret::new);
+        Ok(tokens(response, refresh_token))
+    }
+
+    async fn request_tokens(&self, body: serde_json::Value) -> Result<TokenResponse> {
+        let request = self
+            .http
+            .post(format!("{}/access_token", self.oauth_url))
+            .json(&body);
+        http::send_json(request, &self.retry)
+            .await
+            .map_err(AppError::Raindrop)
+    }
+}
+
+fn tokens(response: TokenResponse, refresh_token: Secret) -> Tokens {
+    let access_token = Secret::new(response.access_token);
+    redact::register_secret(&access_token);
+    redact::register_secret(&refresh_token);
+    Tokens {
+        access_token,
+        refresh_token,
+        expires_at: response
+            .expires_in
+            .map(|seconds| Utc::now() + chrono::Duration::seconds(seconds)),
+    }
+}
+
+/// A listener on `127.0.0.1` that the authorization server redirects the
+/// browser back to.
+pub struct Loopback {
+    listener: TcpListener,
+    redirect_uri: String,
+}
+
+impl Loopback {
+    /// Listens on `port`, or on any free port if it is 0.
+    pub async fn bind(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .map_err(|e| AppError::Raindrop(ServiceError::Callback(e)))?;
+        let port = listener
+            .local_addr()
+            .map_err(|e| AppError::Raindrop(ServiceError::Callback(e)))?
+            .port();
+        Ok(Loopback {
+            listener,
+            redirect_uri: format!("http://127.0.0.1:{}{}", port, CALLBACK_PATH),
+        })
+    }
+
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// Waits for the redirect and returns its code. Requests for other
+    /// paths, such as a browser's favicon, are answered and ignored. So are
+    /// redirects that carry another login's state or no code at all: they
+    /// get a 400 and the wait goes on, bounded only by the caller.
+    ///
+    /// Each connection is served on its own task: browsers open spare
+    /// connections ahead of time that may never carry a request, and one
+    /// of those must not hold up the redirect.
+    pub async fn wait_for_code(self, state: &str) -> Result<Secret> {
+        let (sender, mut redirects) = mpsc::channel(1);
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (stream, _) = accepted
+                        .map_err(|e| AppError::Raindrop(ServiceError::Callback(e)))?;
+                    let sender = sender.clone();
+                    let state = state.to_string();
+                    tokio::spawn(async move {
+                        let served = tokio::time::timeout(CONNECTION_TIMEOUT, serve(stream, &state));
+                        match served.await {
+                            Ok(Ok(Some(outcome))) => {
+                                let _ = sender.send(outcome).await;
+                            }
+                            Ok(Ok(None)) => {}
+                            Ok(Err(e)) => debug!("Dropped a loopback connection: {}", e),
+                            Err(_) => debug!("Dropped an idle loopback connection"),
+                        }
+                    });
+                }
+                Some(outcome) = redirects.recv() => {
+                    return outcome
+                        .map_err(|reason| AppError::Raindrop(ServiceError::Unexpected(reason)));
+                }
+            }
+        }
+    }
+}
+
+/// Answers one request on the loopback listener. Returns the outcome of the
+/// login if it was the redirect of this login, `None` for anything else.
+async fn serve(
+    stream: TcpStream,
+    state: &str,
+) -> std::io::Result<Option<std::result::Result<Secret, String>>> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // The rest of the request is of no interest, but the browser expects it
+    // to be read.
+    let mut header = String::new();
+    while reader.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let url = Url::parse("http://127.0.0.1")
+        .and_then(|base| base.join(target))
+        .ok()
+        .filter(|url| url.path() == CALLBACK_PATH);
+    let Some(url) = url else {
+        respond(reader.get_mut(), "404 Not Found", "Not found").await?;
+        return Ok(None);
+    };
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    // Anyone can send the browser here; only the redirect of this login may
+    // end it, whether with a code or with a refusal.
+    if param("state").as_deref() != Some(state) {
+        warn!("Ignored a sign-in redirect that does not belong to this login");
+        let message = "This redirect does not belong to the sign-in in progress.";
+        respond(reader.get_mut(), "400 Bad Request", message).await?;
+        return Ok(None);
+    }
+    let outcome = match (param("code"), param("error")) {
+        (_, Some(error)) => Err(format!("authorization was refused: {}", error)),
+        (Some(code), None) => Ok(Secret::new(code)),
+        (None, None) => {
+            warn!("Ignored a sign-in redirect that carries no code");
+            let message = "This redirect carries no authorization code.";
+            respond(reader.get_mut(), "400 Bad Request", message).await?;
+            return Ok(None);
+        }
+    };
+    let message = match &outcome {
+        Ok(_) => "Signed in to Raindrop. You can close this window.",
+        Err(_) => "Signing in to Raindrop failed. See the terminal for details.",
+    };
+    let status = if outcome.is_ok() {
+        "200 OK"
+    } else {
+        "400 Bad Request"
+    };
+    // The browser not reading the page does not undo the login.
+    let _ = respond(reader.get_mut(), status, message).await;
+    Ok(Some(outcome))
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) -> std::io::Result<()> {
+    let body = format!(
+        "<!DOCTYPE html><html><body><p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Runs the authorization-code flow: hands the authorization URL to
+/// `open`, waits for the redirect on `loopback` and exchanges the code.
+pub async fn authorize(
+    client: &OAuthClient,
+    loopback: Loopback,
+    open: impl FnOnce(&str),
+) -> Result<Tokens> {
+    let state = random_state();
+    open(&client.authorize_url(loopback.redirect_uri(), &state));
+    let redirect_uri = loopback.redirect_uri().to_string();
+    let code = tokio::time::timeout(AUTHORIZATION_TIMEOUT, loopback.wait_for_code(&state))
+        .await
+        .map_err(|_| {
+            AppError::Raindrop(ServiceError::OperationTimeout {
+                name: "authorization".to_string(),
+                seconds: AUTHORIZATION_TIMEOUT.as_secs(),
+            })
+        })??;
+    client.exchange_code(&code, &redirect_uri).await
+}
+
+fn random_state() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The tokens a Raindrop client authenticates with. Clones share them, so
+/// a refresh by one request serves every other.
+#[derive(Clone)]
+pub struct OAuthSession {
+    inner: Arc<SessionInner>,
+}
+
+struct SessionInner {
+    client: OAuthClient,
+    tokens: Mutex<Tokens>,
+    /// Where refreshed tokens are written back to.
+    store: Option<(CredentialStore, String)>,
+}
+
+impl OAuthSession {
+    pub fn new(client: OAuthClient, tokens: Tokens) -> Self {
+        OAuthSession::with_store(client, tokens, None)
+    }
+
+    /// Keeps refreshed tokens in `store` for `profile`.
+    pub fn persisted(
+        client: OAuthClient,
+        tokens: Tokens,
+        store: CredentialStore,
+        profile: &str,
+    ) -> Self {
+        OAuthSession::with_store(client, tokens, Some((store, profile.to_string())))
+    }
+
+    fn with_store(
+        client: OAuthClient,
+        tokens: Tokens,
+        store: Option<(CredentialStore, String)>,
+    ) -> Self {
+        redact::register_secret(&tokens.access_token);
+        redact::register_secret(&tokens.refresh_token);
+        OAuthSession {
+            inner: Arc::new(SessionInner {
+                client,
+                tokens: Mutex::new(tokens),
+                store,
+            }),
+        }
+    }
+
+    /// The session `auth login raindrop --oauth` left for the profile, or
+    /// `None` if the profile has no OAuth app configured.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        if config.raindrop_client_id.is_none() {
+            return Ok(None);
+        }
+        let client = OAuthClient::from_config(config)?;
+        let store = config.credentials().cloned().unwrap_or_default();
+        let tokens = Tokens::load(&store, &config.profile)?.ok_or_else(|| {
+            AppError::Config(ConfigError::Invalid(format!(
+                "profile '{}' is not signed in to Raindrop (run `auth login raindrop --oauth`)",
+                config.profile
+            )))
+        })?;
+        Ok(Some(OAuthSession::persisted(
+            client,
+            tokens,
+            store,
+            &config.profile,
+        )))
+    }
+
+    /// The current access token, refreshed first if it is about to expire.
+    pub async fn access_token(&self) -> Result<Secret> {
+        let mut tokens = self.inner.tokens.lock().await;
+        if tokens.expired(Utc::now()) {
+            debug!("Raindrop access token expired, refreshing");
+            *tokens = self.renew(&tokens).await?;
+        }
+        Ok(tokens.access_token.clone())
+    }
+
+    /// Refreshes the tokens after `rejected` was turned down, unless
+    /// another request already did. Returns the access token to retry with.
+    pub async fn refresh(&self, rejected: &Secret) -> Result<Secret> {
+        let mut tokens = self.inner.tokens.lock().await;
+        if tokens.access_token == *rejected {
+            info!("🔑 Raindrop access token was rejected, refreshing");
+            *tokens = self.renew(&tokens).await?;
+        }
+        Ok(tokens.access_token.clone())
+    }
+
+    pub async fn tokens(&self) -> Tokens {
+        self.inner.tokens.lock().await.clone()
+    }
+
+    async fn renew(&self, tokens: &Tokens) -> Result<Tokens> {
+        let renewed = self.inner.client.refresh(&tokens.refresh_token).await?;
+        if let Some((store, profile)) = &self.inner.store {
+            renewed.save(store, profile)?;
+        }
+        Ok(renewed)
+    }
+}
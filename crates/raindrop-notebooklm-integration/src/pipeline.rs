@@ -0,0 +1,235 @@
+//! Fetches and extracts pages concurrently while staying polite to the
+//! sites they come from.
+//!
+//! ```text
+//! urls ──▶ download (≤ concurrency, ≤ per_host per host) ──▶ extract (≤ CPUs) ──▶ results
+//!                               bounded channel                 bounded channel
+//! ```
+//!
+//! Every stage waits when the next one falls behind, so however many URLs
+//! are queued, at most a few downloads per slot are held in memory at once.
+//! URLs can be fed in while earlier ones are still downloading.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Url;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::content::{ContentFetcher, Download, PageContent};
+use crate::error::{AppError, ContentError, Result};
+
+pub const DEFAULT_CONCURRENCY: usize = 8;
+pub const DEFAULT_PER_HOST: usize = 2;
+pub const DEFAULT_HOST_DELAY_MS: u64 = 250;
+
+/// URLs taken from the queue per download slot, counting the ones
+/// downloading. Those beyond the slots wait for their host or for a slot;
+/// the lookahead lets other hosts go ahead of a busy one.
+const QUEUED_PER_SLOT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchLimits {
+    /// Downloads in flight across all hosts.
+    pub concurrency: usize,
+    /// Downloads in flight from any one host.
+    pub per_host: usize,
+    /// Minimum time between the start of two downloads from the same host.
+    pub host_delay: Duration,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        FetchLimits {
+            concurrency: DEFAULT_CONCURRENCY,
+            per_host: DEFAULT_PER_HOST,
+            host_delay: Duration::from_millis(DEFAULT_HOST_DELAY_MS),
+        }
+    }
+}
+
+/// How one download went, whether or not its page could be extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Transfer {
+    /// Time spent downloading, not counting the wait for the host.
+    pub elapsed: Duration,
+    /// Bytes received; zero if the download failed.
+    pub bytes: u64,
+}
+
+pub struct Pipeline {
+    fetcher: ContentFetcher,
+    limits: FetchLimits,
+    hosts: Arc<Hosts>,
+}
+
+impl Pipeline {
+    pub fn new(fetcher: ContentFetcher, limits: FetchLimits) -> Self {
+        Pipeline {
+            fetcher,
+            hosts: Arc::new(Hosts {
+                per_host: limits.per_host.max(1),
+                delay: limits.host_delay,
+                known: Mutex::new(HashMap::new()),
+            }),
+            limits,
+        }
+    }
+
+    /// Fetches every URL and yields the results as they complete, tagged
+    /// with the key they were queued with and how the download went.
+    /// Failures are yielded like any other result. The pipeline stops
+    /// early if the receiver is dropped.
+    pub fn run<K: Send + 'static>(
+        &self,
+        urls: Vec<(K, String)>,
+    ) -> mpsc::Receiver<(K, Result<PageContent>, Transfer)> {
+        let (queue, queued) = mpsc::channel(urls.len().max(1));
+        for url in urls {
+            let _ = queue.try_send(url);
+        }
+        self.run_queued(queued)
+    }
+
+    /// Like [`Pipeline::run`], but takes the URLs from `urls` as they are
+    /// sent, until every sender is dropped.
+    ///
+    /// A URL waiting for its host holds no download slot, so URLs of other
+    /// hosts taken from the queue after it may start first.
+    pub fn run_queued<K: Send + 'static>(
+        &self,
+        mut urls: mpsc::Receiver<(K, String)>,
+    ) -> mpsc::Receiver<(K, Result<PageContent>, Transfer)> {
+        let capacity = self.limits.concurrency.max(1);
+        let (downloaded_tx, downloaded_rx) = mpsc::channel(capacity);
+        let (extracted_tx, extracted_rx) = mpsc::channel(capacity);
+
+        let slots = Arc::new(Semaphore::new(capacity));
+        let queued = Arc::new(Semaphore::new(capacity * QUEUED_PER_SLOT));
+        let fetcher = self.fetcher.clone();
+        let hosts = self.hosts.clone();
+        tokio::spawn(async move {
+            while let Some((key, url)) = urls.recv().await {
+                let place = acquire(&queued).await;
+                if downloaded_tx.is_closed() {
+                    break;
+                }
+                let (fetcher, hosts, slots, downloaded_tx) = (
+                    fetcher.clone(),
+                    hosts.clone(),
+                    slots.clone(),
+                    downloaded_tx.clone(),
+                );
+                tokio::spawn(async move {
+                    let (turn, slot) = hosts.wait_turn(&url, &slots).await;
+                    let started = Instant::now();
+                    let download = fetcher.download(&url).await;
+                    let transfer = Transfer {
+                        elapsed: started.elapsed(),
+                        bytes: download.as_ref().map_or(0, Download::size),
+                    };
+                    drop(turn);
+                    // The slot is held until the extractor accepts the
+                    // download; that is what keeps memory bounded.
+                    let _ = downloaded_tx.send((key, download, transfer)).await;
+                    drop(slot);
+                    drop(place);
+                });
+            }
+        });
+        tokio::spawn(extract_all(downloaded_rx, extracted_tx));
+        extracted_rx
+    }
+}
+
+/// Extracts on the blocking pool, a few documents at a time: parsing a
+/// large page or PDF would otherwise stall the downloads sharing the async
+/// workers. A document the extractor panics on fails like any other page
+/// instead of taking the run down with it.
+async fn extract_all<K: Send + 'static>(
+    mut downloaded: mpsc::Receiver<(K, Result<Download>, Transfer)>,
+    extracted: mpsc::Sender<(K, Result<PageContent>, Transfer)>,
+) {
+    let workers = std::thread::available_parallelism().map_or(2, |n| n.get());
+    let slots = Arc::new(Semaphore::new(workers));
+    while let Some((key, download, transfer)) = downloaded.recv().await {
+        let slot = acquire(&slots).await;
+        let extracted = extracted.clone();
+        tokio::spawn(async move {
+            let page = match download {
+                Ok(download) => {
+                    let url = download.url.clone();
+                    tokio::task::spawn_blocking(move || download.extract())
+                        .await
+                        .unwrap_or_else(|source| {
+                            Err(AppError::Content(ContentError::Extract { url, source }))
+                        })
+                }
+                Err(e) => Err(e),
+            };
+            let _ = extracted.send((key, page, transfer)).await;
+            drop(slot);
+        });
+    }
+}
+
+async fn acquire(slots: &Arc<Semaphore>) -> OwnedSemaphorePermit {
+    slots
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("pipeline semaphores are never closed")
+}
+
+struct Hosts {
+    per_host: usize,
+    delay: Duration,
+    known: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+struct Host {
+    slots: Arc<Semaphore>,
+    next_start: tokio::sync::Mutex<Instant>,
+}
+
+impl Hosts {
+    /// Waits until `url`'s host has a free slot and its delay has passed,
+    /// then for one of `slots`. Taking the host's turn first means a URL
+    /// held up by its host keeps no slot from the others; the delay is
+    /// counted from when the download can really start. Both are released
+    /// when the returned permits are dropped.
+    async fn wait_turn(
+        &self,
+        url: &str,
+        slots: &Arc<Semaphore>,
+    ) -> (OwnedSemaphorePermit, OwnedSemaphorePermit) {
+        let host = self.host(url);
+        let turn = acquire(&host.slots).await;
+        let mut next_start = host.next_start.lock().await;
+        tokio::time::sleep_until(*next_start).await;
+        let slot = acquire(slots).await;
+        *next_start = Instant::now() + self.delay;
+        (turn, slot)
+    }
+
+    fn host(&self, url: &str) -> Arc<Host> {
+        // An unparsable URL fails to download anyway; it still gets a host
+        // of its own so it cannot hold up anything else.
+        let name = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+            .unwrap_or_else(|| url.to_string());
+        let mut known = self.known.lock().expect("host table lock poisoned");
+        known
+            .entry(name)
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    slots: Arc::new(Semaphore::new(self.per_host)),
+                    next_start: tokio::sync::Mutex::new(Instant::now()),
+                })
+            })
+            .clone()
+    }
+}
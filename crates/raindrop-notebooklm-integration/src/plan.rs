@@ -0,0 +1,368 @@
+//! What a sync will do, worked out before anything is changed: which
+//! sources are added, replaced or removed in which notebook, and which
+//! bookmarks are skipped and why.
+//!
+//! A dry run prints the plan; `--plan-out` saves it and `--apply-plan`
+//! later executes exactly the saved changes, including the content that was
+//! fetched while planning.
+
+use std::fmt;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, ContentError, Result, StorageError};
+use crate::ledger::FailureClass;
+use crate::raindrop::BookmarkId;
+use crate::state::{self, BookmarkRecord};
+
+/// Bumped whenever the file format changes incompatibly.
+pub const PLAN_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionPlan {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub profile: String,
+    pub scope: String,
+    /// The selection in words, e.g. `#ai AND #rust`.
+    pub selection: String,
+    /// Identifies the scope's sync state when the plan was made. A plan is
+    /// only applied to the state it was made from.
+    pub state_fingerprint: String,
+    /// Whether the notebooks' sources were listed, or the plan relies on
+    /// the local sync state alone.
+    pub checked_sources: bool,
+    pub notebooks: Vec<NotebookPlan>,
+    pub changes: Vec<Change>,
+    pub unchanged: usize,
+    /// Bookmarks that no longer match but stay synced without `--prune`.
+    pub stale: usize,
+    pub skipped: Vec<Skipped>,
+}
+
+/// A notebook as it will look after the plan is applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotebookPlan {
+    pub shard: usize,
+    pub title: String,
+    /// Unset for a notebook that has yet to be created.
+    pub notebook_id: Option<String>,
+    /// The title it has now, if it exists and is renamed.
+    pub renamed_from: Option<String>,
+    pub sources: usize,
+    /// Estimated from the word counts of the extracted content.
+    pub words: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// Upload a new source.
+    Add,
+    /// Upload changed content and delete the source it replaces.
+    Replace,
+    /// Record Raindrop's edit; the content is the same, so nothing is
+    /// uploaded.
+    Refresh,
+    /// Delete the source of a bookmark that no longer matches.
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub bookmark_id: BookmarkId,
+    pub link: String,
+    pub title: String,
+    pub last_update: DateTime<Utc>,
+    pub shard: usize,
+    pub word_count: usize,
+    pub content_hash: String,
+    /// The existing source: replaced, kept or removed depending on `kind`.
+    pub source_id: Option<String>,
+    /// The Markdown to upload, for additions and replacements.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+impl Change {
+    pub fn uploads(&self) -> bool {
+        matches!(self.kind, ChangeKind::Add | ChangeKind::Replace)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Skipped {
+    pub bookmark_id: BookmarkId,
+    pub link: String,
+    pub title: String,
+    pub last_update: DateTime<Utc>,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Another bookmark in the selection has the same URL.
+    Duplicate,
+    /// Beyond the `max_urls` cap.
+    OverLimit {
+        max_urls: usize,
+    },
+    FetchFailed {
+        class: FailureClass,
+        http_status: Option<u16>,
+        error: String,
+    },
+    /// Fetched, but NotebookLM did not take the source.
+    UploadFailed {
+        class: FailureClass,
+        http_status: Option<u16>,
+        error: String,
+    },
+}
+
+impl SkipReason {
+    /// Whether the bookmark went into the failure ledger, to be retried.
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            SkipReason::FetchFailed { .. } | SkipReason::UploadFailed { .. }
+        )
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Duplicate => write!(f, "same URL as another bookmark"),
+            SkipReason::OverLimit { max_urls } => write!(f, "beyond max_urls ({})", max_urls),
+            SkipReason::FetchFailed { class, error, .. } => {
+                write!(f, "could not be fetched ({}): {}", class, error)
+            }
+            SkipReason::UploadFailed { class, error, .. } => {
+                write!(f, "could not be uploaded ({}): {}", class, error)
+            }
+        }
+    }
+}
+
+impl ExecutionPlan {
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| change.kind == kind)
+            .count()
+    }
+
+    /// Pages that could not be fetched or uploaded.
+    pub fn failures(&self) -> usize {
+        self.skipped
+            .iter()
+            .filter(|skipped| skipped.reason.is_failure())
+            .count()
+    }
+
+    /// Moves the changes whose upload failed to the skipped bookmarks, so
+    /// that the plan says what was actually done.
+    pub fn fail_uploads(&mut self, failed: Vec<Skipped>) {
+        let ids = failed
+            .iter()
+            .map(|skipped| skipped.bookmark_id)
+            .collect::<Vec<_>>();
+        self.changes
+            .retain(|change| !ids.contains(&change.bookmark_id));
+        self.skipped.extend(failed);
+    }
+
+    /// Fails with `ContentError::PartialFailure` if any page could not be
+    /// fetched while planning or uploaded while applying, after the rest
+    /// of the plan was handled.
+    pub fn check_fetched(&self) -> Result<()> {
+        let failed = self.failures();
+        if failed == 0 {
+            return Ok(());
+        }
+        let fetched = self
+            .changes
+            .iter()
+            .filter(|change| change.kind != ChangeKind::Remove)
+            .count();
+        Err(AppError::Content(ContentError::PartialFailure {
+            failed,
+            total: failed + fetched,
+        }))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.notebooks.iter().all(|n| n.renamed_from.is_none())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).expect("plans contain only plain data");
+        std::fs::write(path, json).map_err(StorageError::io(path))
+    }
+
+    pub fn load(path: &Path) -> Result<ExecutionPlan> {
+        let text = std::fs::read_to_string(path).map_err(StorageError::io(path))?;
+        let invalid = |reason: String| {
+            AppError::Storage(StorageError::Plan(format!(
+                "{}: {}",
+                path.display(),
+                reason
+            )))
+        };
+        // The version is checked first so that a plan from another release
+        // is reported as such rather than as a missing field.
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let Versioned { version } =
+            serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        if version != PLAN_VERSION {
+            return Err(invalid(format!(
+                "plan format version {} is not supported (expected {})",
+                version, PLAN_VERSION
+            )));
+        }
+        serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+/// Hash of everything about the scope's records that planning relies on.
+pub fn state_fingerprint(records: &[BookmarkRecord]) -> String {
+    let summary = records
+        .iter()
+        .map(|record| {
+            format!(
+                "{}|{}|{}|{}|{}|{}",
+                record.id.0,
+                record.link,
+                record.last_update.to_rfc3339(),
+                record.content_hash,
+                record.source_id.as_deref().unwrap_or_default(),
+                record.shard
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    state::content_hash(&summary)
+}
+
+/// Terraform-style summary: one line per change, then skipped bookmarks
+/// and the totals.
+impl fmt::Display for ExecutionPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Sync plan for {} (profile {})",
+            self.selection, self.profile
+        )?;
+        if !self.checked_sources {
+            writeln!(
+                f,
+                "Note: NotebookLM was not queried; the plan is based on local sync state."
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Notebooks:")?;
+        for notebook in &self.notebooks {
+            let (symbol, name) = match (&notebook.notebook_id, &notebook.renamed_from) {
+                (None, _) => ("+", format!("\"{}\"", notebook.title)),
+                (Some(_), Some(old)) => ("~", format!("\"{}\" → \"{}\"", old, notebook.title)),
+                (Some(_), None) => (" ", format!("\"{}\"", notebook.title)),
+            };
+            writeln!(
+                f,
+                "  {} {}: {} sources, ~{} words",
+                symbol,
+                name,
+                notebook.sources,
+                thousands(notebook.words)
+            )?;
+        }
+
+        if !self.changes.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Changes:")?;
+        }
+        for change in &self.changes {
+            let (symbol, verb) = match change.kind {
+                ChangeKind::Add => ("+", "add"),
+                ChangeKind::Replace => ("-/+", "replace"),
+                ChangeKind::Refresh => ("~", "refresh"),
+                ChangeKind::Remove => ("-", "remove"),
+            };
+            let notebook = self
+                .notebooks
+                .iter()
+                .find(|notebook| notebook.shard == change.shard)
+                .map(|notebook| notebook.title.as_str())
+                .unwrap_or("?");
+            writeln!(
+                f,
+                "  {:>3} {:<7} {} <{}>",
+                symbol, verb, change.title, change.link
+            )?;
+            writeln!(
+                f,
+                "              {} words in \"{}\"{}",
+                thousands(change.word_count),
+                notebook,
+                match (&change.kind, &change.source_id) {
+                    (ChangeKind::Replace | ChangeKind::Remove, Some(id)) =>
+                        format!(", source {}", id),
+                    _ => String::new(),
+                }
+            )?;
+        }
+
+        if !self.skipped.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Skipped:")?;
+        }
+        for skipped in &self.skipped {
+            writeln!(
+                f,
+                "  ! {} <{}>: {}",
+                skipped.title, skipped.link, skipped.reason
+            )?;
+        }
+
+        writeln!(f)?;
+        write!(
+            f,
+            "Plan: {} to add, {} to replace, {} to refresh, {} to remove; {} unchanged, {} skipped.",
+            self.count(ChangeKind::Add),
+            self.count(ChangeKind::Replace),
+            self.count(ChangeKind::Refresh),
+            self.count(ChangeKind::Remove),
+            self.unchanged,
+            self.skipped.len()
+        )?;
+        if self.stale > 0 {
+            write!(
+                f,
+                " {} no longer matching are kept; use --prune to remove them.",
+                self.stale
+            )?;
+        }
+        writeln!(f)
+    }
+}
+
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
@@ -0,0 +1,273 @@
+//! The progress display of a sync (requirement 2.4): a bar each for
+//! listing, fetching and uploading on a terminal, or a log line per stage
+//! every few seconds when stdout is redirected, so that CI logs stay
+//! readable. Both are drawn from the run's [`Event`]s.
+
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+
+use crate::events::{Event, EventKind, Stage};
+
+/// How often the bars are redrawn, so that rates and ETAs stay current.
+pub const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often stages still running are logged without a terminal.
+pub const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+const BAR_TEMPLATE: &str = "{prefix:>9} [{bar:30}] {pos}/{len} {msg}";
+
+/// Counts for one stage, enough for a rate and an ETA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageProgress {
+    pub stage: Stage,
+    pub done: usize,
+    /// Unset until known; listing learns it from the first page.
+    pub total: Option<usize>,
+    pub bytes: u64,
+    /// The URL or title handled last.
+    pub current: Option<String>,
+    pub started: Instant,
+}
+
+impl StageProgress {
+    pub fn new(stage: Stage, total: Option<usize>, started: Instant) -> Self {
+        StageProgress {
+            stage,
+            done: 0,
+            total,
+            bytes: 0,
+            current: None,
+            started,
+        }
+    }
+
+    pub fn per_second(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.started).as_secs_f64();
+        if elapsed > 0.0 {
+            self.done as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Unknown until the total is known and something has been done.
+    pub fn eta(&self, now: Instant) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.done);
+        let rate = self.per_second(now);
+        (rate > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / rate))
+    }
+
+    /// `12/40`, or `12` while the total is unknown.
+    pub fn count(&self) -> String {
+        match self.total {
+            Some(total) => format!("{}/{}", self.done, total),
+            None => self.done.to_string(),
+        }
+    }
+
+    /// `3.0/s, 1.20 MiB, ETA 9 seconds`. Bytes are left out for stages
+    /// that move none, such as listing.
+    pub fn rates(&self, now: Instant) -> String {
+        let mut parts = vec![format!("{:.1}/s", self.per_second(now))];
+        if self.bytes > 0 {
+            parts.push(HumanBytes(self.bytes).to_string());
+        }
+        if let Some(eta) = self.eta(now) {
+            parts.push(format!("ETA {}", HumanDuration(eta)));
+        }
+        parts.join(", ")
+    }
+
+    /// The whole stage on one line, as logged without a terminal.
+    pub fn describe(&self, now: Instant) -> String {
+        let line = format!("{} {} ({})", self.stage, self.count(), self.rates(now));
+        match &self.current {
+            Some(current) => format!("{} {}", line, current),
+            None => line,
+        }
+    }
+}
+
+/// Follows a run's events and shows how far each stage has got.
+pub struct ProgressTracker {
+    display: Display,
+    /// The stages still running.
+    stages: BTreeMap<Stage, StageProgress>,
+}
+
+enum Display {
+    Bars {
+        multi: MultiProgress,
+        bars: BTreeMap<Stage, ProgressBar>,
+    },
+    LogLines,
+}
+
+impl ProgressTracker {
+    /// Draws bars through `multi`.
+    pub fn bars(multi: MultiProgress) -> Self {
+        ProgressTracker {
+            display: Display::Bars {
+                multi,
+                bars: BTreeMap::new(),
+            },
+            stages: BTreeMap::new(),
+        }
+    }
+
+    /// Logs each running stage every [`LOG_INTERVAL`].
+    pub fn log_lines() -> Self {
+        ProgressTracker {
+            display: Display::LogLines,
+            stages: BTreeMap::new(),
+        }
+    }
+
+    /// Bars when stdout is a terminal, log lines otherwise.
+    pub fn for_stdout(multi: MultiProgress) -> Self {
+        if std::io::stdout().is_terminal() {
+            ProgressTracker::bars(multi)
+        } else {
+            ProgressTracker::log_lines()
+        }
+    }
+
+    /// How often [`ProgressTracker::tick`] wants to be called.
+    pub fn interval(&self) -> Duration {
+        match self.display {
+            Display::Bars { .. } => REDRAW_INTERVAL,
+            Display::LogLines => LOG_INTERVAL,
+        }
+    }
+
+    pub fn stages(&self) -> impl Iterator<Item = &StageProgress> {
+        self.stages.values()
+    }
+
+    pub fn update(&mut self, event: &Event) {
+        match &event.kind {
+            EventKind::RunStarted { .. } | EventKind::RunFinished { .. } => self.finish(),
+            EventKind::StageStarted { stage, total } => self.start(*stage, *total),
+            EventKind::StageTotal { stage, total } => {
+                self.advance(*stage, |progress| progress.total = Some(*total))
+            }
+            EventKind::StageFinished { stage } => self.finish_stage(*stage),
+            EventKind::ListingPage {
+                search,
+                page,
+                items,
+                total,
+            } => self.advance(Stage::Listing, |progress| {
+                // Each search's first page brings its match count.
+                if *page == 0 {
+                    progress.total = Some(progress.total.unwrap_or(0) + total);
+                }
+                progress.done += items;
+                progress.current = Some(format!("{}, page {}", search, page + 1));
+            }),
+            EventKind::FetchSucceeded { link, bytes, .. } => {
+                self.advance(Stage::Fetching, |progress| {
+                    progress.done += 1;
+                    progress.bytes += bytes;
+                    progress.current = Some(link.clone());
+                })
+            }
+            EventKind::FetchFailed { link, .. } => self.advance(Stage::Fetching, |progress| {
+                progress.done += 1;
+                progress.current = Some(link.clone());
+            }),
+            EventKind::SourceUploaded { title, bytes, .. } => {
+                self.advance(Stage::Uploading, |progress| {
+                    progress.done += 1;
+                    progress.bytes += bytes;
+                    progress.current = Some(title.clone());
+                })
+            }
+            EventKind::BookmarkListed { .. } | EventKind::ServiceChecked { .. } => {}
+        }
+    }
+
+    /// Redraws the bars, or logs the running stages.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        match &self.display {
+            Display::Bars { bars, .. } => self
+                .stages
+                .values()
+                .for_each(|progress| redraw(bars, progress, now)),
+            Display::LogLines => self
+                .stages
+                .values()
+                .for_each(|progress| info!("⏳ {}", progress.describe(now))),
+        }
+    }
+
+    /// Reports the running stages a last time and ends them.
+    pub fn finish(&mut self) {
+        let running = self.stages.keys().copied().collect::<Vec<_>>();
+        running
+            .into_iter()
+            .for_each(|stage| self.finish_stage(stage));
+    }
+
+    fn finish_stage(&mut self, stage: Stage) {
+        let Some(progress) = self.stages.remove(&stage) else {
+            return;
+        };
+        let now = Instant::now();
+        match &mut self.display {
+            Display::Bars { bars, .. } => {
+                redraw(bars, &progress, now);
+                if let Some(bar) = bars.remove(&stage) {
+                    bar.finish();
+                }
+            }
+            Display::LogLines => info!("✅ {}", progress.describe(now)),
+        }
+    }
+
+    fn start(&mut self, stage: Stage, total: Option<usize>) {
+        if let Display::Bars { multi, bars } = &mut self.display {
+            let style = ProgressStyle::with_template(BAR_TEMPLATE)
+                .expect("the bar template is valid")
+                .progress_chars("=> ");
+            let bar = multi.add(
+                ProgressBar::new(total.unwrap_or(0) as u64)
+                    .with_style(style)
+                    .with_prefix(stage.to_string()),
+            );
+            bars.insert(stage, bar);
+        }
+        self.stages
+            .insert(stage, StageProgress::new(stage, total, Instant::now()));
+    }
+
+    fn advance(&mut self, stage: Stage, update: impl FnOnce(&mut StageProgress)) {
+        let Some(progress) = self.stages.get_mut(&stage) else {
+            return;
+        };
+        update(progress);
+        if let Display::Bars { bars, .. } = &self.display {
+            redraw(bars, progress, Instant::now());
+        }
+    }
+}
+
+fn redraw(bars: &BTreeMap<Stage, ProgressBar>, progress: &StageProgress, now: Instant) {
+    let Some(bar) = bars.get(&progress.stage) else {
+        return;
+    };
+    if let Some(total) = progress.total {
+        bar.set_length(total as u64);
+    }
+    bar.set_position(progress.done as u64);
+    let message = match &progress.current {
+        Some(current) => format!("{} {}", progress.rates(now), current),
+        None => progress.rates(now),
+    };
+    bar.set_message(message);
+}
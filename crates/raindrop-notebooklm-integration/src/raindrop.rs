@@ -0,0 +1,412 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use reqwest::{RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::config::Config;
+use crate::credentials::Secret;
+use crate::error::{AppError, Result, ServiceError};
+use crate::events::{EventKind, Events};
+use crate::http;
+use crate::oauth::OAuthSession;
+use crate::redact;
+use crate::retry::RetryPolicy;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.raindrop.io";
+
+/// Raindrop caps `perpage` at 50; anything larger is silently clamped.
+const PAGE_SIZE: usize = 50;
+
+/// Newest first, which is the order searches are listed in.
+const SORT: &str = "-created";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BookmarkId(pub u64);
+
+/// Raindrop reserves `0` for "all collections", `-1` for Unsorted and `-99` for Trash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CollectionId(pub i64);
+
+impl CollectionId {
+    pub const ALL: CollectionId = CollectionId(0);
+}
+
+/// How several requested tags combine when selecting bookmarks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum TagMatch {
+    /// A bookmark must carry every tag.
+    #[default]
+    All,
+    /// A bookmark may carry any one of the tags.
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: BookmarkId,
+    pub title: String,
+    pub link: String,
+    pub excerpt: String,
+    pub note: String,
+    pub tags: Vec<String>,
+    pub created: DateTime<Utc>,
+    pub last_update: DateTime<Utc>,
+    pub collection_id: CollectionId,
+    pub domain: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RaindropsPage {
+    items: Vec<RawBookmark>,
+    /// The search's match count, for progress only: Raindrop leaves it out
+    /// of some responses and it can be stale while bookmarks are edited.
+    #[serde(default)]
+    count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBookmark {
+    #[serde(rename = "_id")]
+    id: u64,
+    #[serde(default)]
+    title: String,
+    link: String,
+    #[serde(default)]
+    excerpt: String,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    created: DateTime<Utc>,
+    last_update: DateTime<Utc>,
+    collection: CollectionRef,
+    #[serde(default)]
+    domain: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CollectionRef {
+    #[serde(rename = "$id")]
+    id: i64,
+}
+
+impl From<RawBookmark> for Bookmark {
+    fn from(raw: RawBookmark) -> Self {
+        Bookmark {
+            id: BookmarkId(raw.id),
+            title: raw.title,
+            link: raw.link,
+            excerpt: raw.excerpt,
+            note: raw.note,
+            tags: raw.tags,
+            created: raw.created,
+            last_update: raw.last_update,
+            collection_id: CollectionId(raw.collection.id),
+            domain: raw.domain,
+        }
+    }
+}
+
+/// Request quota as advertised by Raindrop's rate-limit headers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RateLimit {
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    /// Unix timestamp at which the quota resets.
+    pub reset: Option<u64>,
+}
+
+impl RateLimit {
+    /// Raindrop documents both `X-RateLimit-*` and bare `RateLimit-*` names
+    /// and has sent either over time, so both are accepted.
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<RateLimit> {
+        let read = |name: &str| {
+            [format!("x-ratelimit-{}", name), format!("ratelimit-{}", name)]
+                .iter()
+                .find_map(|header| headers.get(header.as_str()))
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+        };
+        let rate_limit = RateLimit {
+            limit: read("limit"),
+            remaining: read("remaining"),
+            reset: read("reset"),
+        };
+        (rate_limit != RateLimit::empty()).then_some(rate_limit)
+    }
+
+    fn empty() -> RateLimit {
+        RateLimit {
+            limit: None,
+            remaining: None,
+            reset: None,
+        }
+    }
+}
+
+/// The outcome of a successful [`RaindropClient::authenticate`] call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub user: User,
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserResponse {
+    user: User,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    #[serde(rename = "_id")]
+    pub id: u64,
+    #[serde(default)]
+    pub full_name: String,
+}
+
+pub struct RaindropClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: Secret,
+    /// Replaces `token` when the profile signs in with OAuth.
+    oauth: Option<OAuthSession>,
+    retry: RetryPolicy,
+    events: Events,
+}
+
+impl RaindropClient {
+    pub fn new(token: impl Into<String>) -> Self {
+        let token = Secret::new(token);
+        redact::register_secret(&token);
+        RaindropClient {
+            http: http::client(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            token,
+            oauth: None,
+            retry: RetryPolicy::default(),
+            events: Events::default(),
+        }
+    }
+
+    /// Authenticates with the session's access token, refreshing it when
+    /// Raindrop rejects it.
+    pub fn with_oauth(session: OAuthSession) -> Self {
+        RaindropClient {
+            oauth: Some(session),
+            ..RaindropClient::new(String::new())
+        }
+    }
+
+    /// The profile's OAuth session if it has an OAuth app configured, its
+    /// API key otherwise.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let client = match OAuthSession::from_config(config)? {
+            Some(session) => RaindropClient::with_oauth(session),
+            None => RaindropClient::new(config.require_raindrop_api_key()?),
+        };
+        Ok(client.with_base_url(&config.raindrop_base_url))
+    }
+
+    pub fn with_base_url(self, base_url: impl Into<String>) -> Self {
+        RaindropClient {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            ..self
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        RaindropClient { retry, ..self }
+    }
+
+    /// Reports every page of a search as it arrives.
+    pub fn with_events(self, events: Events) -> Self {
+        RaindropClient { events, ..self }
+    }
+
+    /// Verifies the token by requesting the current user, which is also the
+    /// cheapest authenticated call for reading the remaining rate limit.
+    pub async fn authenticate(&self) -> Result<Session> {
+        let response = self
+            .send(|| self.http.get(format!("{}/rest/v1/user", self.base_url)))
+            .await?;
+
+        let rate_limit = RateLimit::from_headers(response.headers());
+        response
+            .json::<UserResponse>()
+            .await
+            .map(|body| Session {
+                user: body.user,
+                rate_limit,
+            })
+            .map_err(|e| AppError::Raindrop(ServiceError::Decode(e)))
+    }
+
+    /// Sends the bookmarks carrying the tags to `found` page by page as
+    /// they arrive, newest first, and stops early once `found` is closed.
+    /// Raindrop ANDs space-separated search terms, so `All` is a single
+    /// search. There is no OR operator for tags, so `Any` runs one search
+    /// per tag and merges them into that one order; a bookmark with several
+    /// of the tags is sent once for each of them.
+    pub async fn send_bookmarks_by_tags(
+        &self,
+        collection: CollectionId,
+        tags: &[String],
+        tag_match: TagMatch,
+        found: &mpsc::Sender<Bookmark>,
+    ) -> Result<()> {
+        let mut searches = match tag_match {
+            TagMatch::All => vec![Search::new(collection, search_all(tags))],
+            TagMatch::Any => tags
+                .iter()
+                .map(|tag| Search::new(collection, tag_query(tag)))
+                .collect(),
+        };
+        loop {
+            for search in searches.iter_mut() {
+                if search.buffered.is_empty() {
+                    search.next_page(self).await?;
+                }
+            }
+            let Some(newest) = searches
+                .iter_mut()
+                .filter(|search| !search.buffered.is_empty())
+                .min_by(|a, b| newest_first(&a.buffered[0], &b.buffered[0]))
+            else {
+                return Ok(());
+            };
+            let bookmark = newest
+                .buffered
+                .pop_front()
+                .expect("only searches with bookmarks left");
+            if found.send(bookmark).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn fetch_page(
+        &self,
+        collection: CollectionId,
+        search: &str,
+        page: usize,
+    ) -> Result<RaindropsPage> {
+        let response = self
+            .send(|| {
+                self.http
+                    .get(format!(
+                        "{}/rest/v1/raindrops/{}",
+                        self.base_url, collection.0
+                    ))
+                    .query(&[
+                        ("search", search.to_string()),
+                        ("page", page.to_string()),
+                        ("perpage", PAGE_SIZE.to_string()),
+                        ("sort", SORT.to_string()),
+                    ])
+            })
+            .await?;
+        response
+            .json()
+            .await
+            .map_err(|e| AppError::Raindrop(ServiceError::Decode(e)))
+    }
+
+    /// Sends the request `build` makes with the bearer token. With OAuth, a
+    /// 401 refreshes the access token and the request is sent once more.
+    async fn send(&self, build: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let Some(oauth) = &self.oauth else {
+            return http::send(build().bearer_auth(&*self.token), &self.retry)
+                .await
+                .map_err(AppError::Raindrop);
+        };
+        let token = oauth.access_token().await?;
+        match http::send(build().bearer_auth(&*token), &self.retry).await {
+            Err(ServiceError::Unauthorized { status: 401 }) => {
+                let token = oauth.refresh(&token).await?;
+                http::send(build().bearer_auth(&*token), &self.retry)
+                    .await
+                    .map_err(AppError::Raindrop)
+            }
+            outcome => outcome.map_err(AppError::Raindrop),
+        }
+    }
+}
+
+/// A search read one page at a time.
+struct Search {
+    collection: CollectionId,
+    search: String,
+    next: Option<usize>,
+    /// Received but not yet handed on.
+    buffered: VecDeque<Bookmark>,
+}
+
+impl Search {
+    fn new(collection: CollectionId, search: String) -> Search {
+        Search {
+            collection,
+            search,
+            next: Some(0),
+            buffered: VecDeque::new(),
+        }
+    }
+
+    /// Adds the next page to the buffered bookmarks. Returns false once
+    /// there are no more pages.
+    async fn next_page(&mut self, client: &RaindropClient) -> Result<bool> {
+        // The page count is unknown until the first response arrives, and
+        // `count` cannot be trusted to tell, so a full page means there may
+        // be another and a short one that there is not.
+        let Some(page) = self.next else {
+            return Ok(false);
+        };
+        let body = client
+            .fetch_page(self.collection, &self.search, page)
+            .await?;
+        let received = body.items.len();
+        debug!(
+            "Fetched page {} of '{}' ({} items, {} total)",
+            page, self.search, received, body.count
+        );
+        client.events.emit(EventKind::ListingPage {
+            search: self.search.clone(),
+            page,
+            items: received,
+            total: body.count,
+        });
+        self.buffered
+            .extend(body.items.into_iter().map(Bookmark::from));
+        self.next = (received == PAGE_SIZE).then_some(page + 1);
+        Ok(true)
+    }
+}
+
+/// Orders bookmarks newest first, the oldest id first among those created
+/// at the same time.
+pub fn newest_first(a: &Bookmark, b: &Bookmark) -> Ordering {
+    b.created.cmp(&a.created).then(a.id.cmp(&b.id))
+}
+
+/// The search for bookmarks carrying every one of `tags`.
+fn search_all(tags: &[String]) -> String {
+    tags.iter()
+        .map(|tag| tag_query(tag))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Builds a Raindrop search expression matching a single tag. Tags containing
+/// whitespace must be quoted, otherwise Raindrop treats the rest as free text.
+pub fn tag_query(tag: &str) -> String {
+    if tag.chars().any(char::is_whitespace) {
+        format!("#\"{}\"", tag)
+    } else {
+        format!("#{}", tag)
+    }
+}
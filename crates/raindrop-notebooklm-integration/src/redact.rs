@@ -0,0 +1,101 @@
+//! Masks secrets in everything a run writes for people to read: log lines,
+//! error messages, snapshots and summaries (requirement 5.6). Two kinds of
+//! secret are caught: the configured API keys, registered once known, and
+//! anything shaped like a credential, such as a bearer token, a cookie or
+//! a `token=` query parameter.
+//!
+//! State and plan files are left alone; they keep the real links, which
+//! later runs fetch again.
+
+use std::sync::{OnceLock, RwLock};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::credentials::Secret;
+
+/// What a secret is replaced by.
+pub const REDACTED: &str = "********";
+
+/// Shorter values are not registered: masking every `abc` in a log would
+/// hide more than it protects.
+const MIN_SECRET_LEN: usize = 8;
+
+/// Held as [`Secret`]s so that they never show in `Debug` output.
+static SECRETS: RwLock<Vec<Secret>> = RwLock::new(Vec::new());
+
+/// Masks `secret` wherever it turns up for the rest of the run. Replaced
+/// tokens stay registered: a late log line or error can still quote them.
+pub fn register_secret(secret: &str) {
+    let secret = secret.trim();
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.write().expect("secret registry lock poisoned");
+    if !secrets.iter().any(|known| &**known == secret) {
+        secrets.push(Secret::from(secret));
+        // Longest first, so that a secret containing another is masked whole.
+        secrets.sort_by_key(|known| std::cmp::Reverse(known.len()));
+    }
+}
+
+/// `text` with every registered secret and every credential-shaped value
+/// replaced by [`REDACTED`].
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.read().expect("secret registry lock poisoned");
+    let text = secrets
+        .iter()
+        .map(|secret| &**secret)
+        .filter(|secret| text.contains(secret))
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret, REDACTED)
+        });
+    rules().iter().fold(text, |text, (pattern, replacement)| {
+        pattern.replace_all(&text, *replacement).into_owned()
+    })
+}
+
+/// Redacts every string in `value`, keys included. Going through the
+/// strings rather than the serialized text keeps the JSON well-formed.
+pub fn redact_json(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = redact(text),
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::Object(fields) => {
+            *fields = std::mem::take(fields)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    redact_json(&mut value);
+                    (redact(&key), value)
+                })
+                .collect();
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+/// Each pattern keeps its first group, the name of what is masked, and
+/// replaces the value after it.
+fn rules() -> &'static [(Regex, &'static str)] {
+    static RULES: OnceLock<Vec<(Regex, &'static str)>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        [
+            // `Authorization: Bearer abc`, `proxy-authorization=Basic abc`
+            r#"(?i)\b((?:proxy-)?authorization["']?\s*[:=]\s*["']?)[^"'\r\n]+"#,
+            // A bearer token on its own, as in an error echoing a header.
+            r"(?i)\b(bearer\s+)[A-Za-z0-9._~+/=-]{8,}",
+            // `Cookie: session=abc; theme=dark`, `Set-Cookie: ...`
+            r#"(?i)\b((?:set-)?cookie["']?\s*[:=]\s*["']?)[^"'\r\n]+"#,
+            // `?token=abc`, `&sig=abc` and the like in URLs.
+            r#"(?i)([?&](?:access_token|refresh_token|id_token|token|api_key|apikey|key|sig|signature|secret|client_secret|password|code)=)[^&#\s"'<>]+"#,
+            // `"access_token": "abc"` in response bodies quoted by errors.
+            r#"(?i)(["']?(?:access_token|refresh_token|id_token|client_secret|api_key|apikey|password)["']?\s*[:=]\s*["'])[^"']+"#,
+        ]
+        .iter()
+        .map(|pattern| {
+            let regex = Regex::new(pattern).expect("static regex is valid");
+            (regex, "${1}********")
+        })
+        .collect()
+    })
+}
@@ -0,0 +1,305 @@
+//! The Markdown summary every sync leaves next to its snapshot
+//! (requirement 5.4): what was found, fetched, skipped and uploaded, where
+//! the time went and where the sources ended up.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+
+use crate::error::{Result, StorageError};
+use crate::plan::SkipReason;
+use crate::redact;
+use crate::snapshot::{self, Outcome, Snapshot, SnapshotItem};
+use crate::state::BookmarkRecord;
+
+/// Pages listed as the slowest and as the largest.
+pub const TOP_PAGES: usize = 5;
+
+/// One of the scope's notebooks after the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotebookLink {
+    pub shard: usize,
+    pub title: String,
+    pub notebook_id: String,
+    /// Unset when the run had no NotebookLM client to build it from.
+    pub url: Option<String>,
+}
+
+/// Renders the summary of a run, with secrets redacted. `records` are the
+/// scope's sync state after the run and place each source in its notebook.
+pub fn render(
+    snapshot: &Snapshot,
+    notebooks: &[NotebookLink],
+    records: &[BookmarkRecord],
+) -> String {
+    let run = &snapshot.run;
+    let mut lines = vec![
+        format!("# Sync summary: {}", run.selection),
+        String::new(),
+        format!("- Profile: {}", run.profile),
+        format!(
+            "- Started: {}, took {}",
+            run.started_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            seconds((run.finished_at - run.started_at).num_milliseconds().max(0) as u64)
+        ),
+        format!("- Version: {}", run.tool_version),
+    ];
+    if run.dry_run {
+        lines.push(
+            "- Dry run: nothing was changed; the counts are what would have happened.".to_string(),
+        );
+    }
+    if let Some(error) = &run.error {
+        lines.push(format!("- Stopped early: {}", error));
+    }
+
+    lines.extend(section("Counts", counts(&snapshot.items)));
+    lines.extend(section("Domains", domains(&snapshot.items)));
+    lines.extend(section("Slowest pages", slowest(&snapshot.items)));
+    lines.extend(section("Largest pages", largest(&snapshot.items)));
+    lines.extend(section("Failures", failures(&snapshot.items)));
+    lines.extend(section("Notebooks", notebook_list(notebooks, records)));
+    redact::redact(&(lines.join("\n") + "\n"))
+}
+
+/// Writes the summary to `dir` as `summary-<profile>.md`, or under a
+/// timestamped name if that file is already there. Returns the path.
+pub fn write(dir: &Path, snapshot: &Snapshot, markdown: &str) -> Result<PathBuf> {
+    let stem = format!("summary-{}", snapshot.run.profile);
+    let path = snapshot::unique_path(dir, &stem, "md", snapshot.run.finished_at);
+    std::fs::write(&path, markdown).map_err(StorageError::io(&path))?;
+    Ok(path)
+}
+
+fn section(heading: &str, body: Vec<String>) -> Vec<String> {
+    let mut lines = vec![String::new(), format!("## {}", heading), String::new()];
+    lines.extend(body);
+    lines
+}
+
+/// A row of the counts table and the items it counts.
+type CountRow = (&'static str, fn(&SnapshotItem) -> bool);
+
+fn counts(items: &[SnapshotItem]) -> Vec<String> {
+    let rows: [CountRow; 8] = [
+        ("Found", |item| item.bookmark.is_some()),
+        ("Fetched", |item| item.content.is_some()),
+        ("Skipped", |item| {
+            matches!(item.outcome, Outcome::Skipped { .. })
+        }),
+        ("Uploaded", |item| {
+            matches!(item.outcome, Outcome::Added | Outcome::Replaced)
+        }),
+        ("Refreshed", |item| item.outcome == Outcome::Refreshed),
+        ("Unchanged", |item| item.outcome == Outcome::Unchanged),
+        ("Removed", |item| item.outcome == Outcome::Removed),
+        ("No longer matching", |item| item.outcome == Outcome::Stale),
+    ];
+    let mut lines = vec![
+        "| Bookmarks | Count |".to_string(),
+        "| --- | --- |".to_string(),
+    ];
+    lines.extend(rows.iter().map(|(label, counts)| {
+        let count = items.iter().filter(|item| counts(item)).count();
+        format!("| {} | {} |", label, count)
+    }));
+    lines
+}
+
+#[derive(Default)]
+struct DomainRow {
+    found: usize,
+    fetched: usize,
+    skipped: usize,
+    words: usize,
+}
+
+/// Bookmarks per site, busiest first.
+fn domains(items: &[SnapshotItem]) -> Vec<String> {
+    let mut rows = BTreeMap::<String, DomainRow>::new();
+    for item in items {
+        let Some(bookmark) = &item.bookmark else {
+            continue;
+        };
+        let domain = Some(bookmark.domain.clone())
+            .filter(|domain| !domain.is_empty())
+            .or_else(|| Url::parse(&item.link).ok()?.host_str().map(str::to_string))
+            .unwrap_or_else(|| "unknown".to_string());
+        let row = rows.entry(domain).or_default();
+        row.found += 1;
+        row.fetched += usize::from(item.content.is_some());
+        row.skipped += usize::from(matches!(item.outcome, Outcome::Skipped { .. }));
+        row.words += item.content.as_ref().map_or(0, |page| page.word_count);
+    }
+    if rows.is_empty() {
+        return vec!["No bookmarks.".to_string()];
+    }
+    let mut rows = rows.into_iter().collect::<Vec<_>>();
+    rows.sort_by(|(a_name, a), (b_name, b)| b.found.cmp(&a.found).then(a_name.cmp(b_name)));
+    let mut lines = vec![
+        "| Domain | Found | Fetched | Skipped | Words |".to_string(),
+        "| --- | --- | --- | --- | --- |".to_string(),
+    ];
+    lines.extend(rows.iter().map(|(domain, row)| {
+        format!(
+            "| {} | {} | {} | {} | {} |",
+            cell(domain),
+            row.found,
+            row.fetched,
+            row.skipped,
+            row.words
+        )
+    }));
+    lines
+}
+
+fn slowest(items: &[SnapshotItem]) -> Vec<String> {
+    let mut timed = items
+        .iter()
+        .filter_map(|item| Some((item.fetch_ms?, item)))
+        .collect::<Vec<_>>();
+    timed.sort_by(|(a, _), (b, _)| b.cmp(a));
+    if timed.is_empty() {
+        return vec!["No pages were fetched.".to_string()];
+    }
+    let mut lines = vec![
+        "| Title | Link | Time |".to_string(),
+        "| --- | --- | --- |".to_string(),
+    ];
+    lines.extend(timed.iter().take(TOP_PAGES).map(|(ms, item)| {
+        format!(
+            "| {} | <{}> | {} |",
+            cell(&item.title),
+            item.link,
+            seconds(*ms)
+        )
+    }));
+    lines
+}
+
+fn largest(items: &[SnapshotItem]) -> Vec<String> {
+    let mut sized = items
+        .iter()
+        .filter_map(|item| Some((item.content.as_ref()?, item)))
+        .collect::<Vec<_>>();
+    sized.sort_by_key(|(page, _)| std::cmp::Reverse(page.word_count));
+    if sized.is_empty() {
+        return vec!["No pages were fetched.".to_string()];
+    }
+    let mut lines = vec![
+        "| Title | Link | Words |".to_string(),
+        "| --- | --- | --- |".to_string(),
+    ];
+    lines.extend(sized.iter().take(TOP_PAGES).map(|(page, item)| {
+        format!(
+            "| {} | <{}> | {} |",
+            cell(&page.title),
+            item.link,
+            page.word_count
+        )
+    }));
+    lines
+}
+
+/// Skipped bookmarks grouped by cause, most frequent first.
+fn failures(items: &[SnapshotItem]) -> Vec<String> {
+    let mut groups = BTreeMap::<String, Vec<String>>::new();
+    for item in items {
+        let Outcome::Skipped { reason } = &item.outcome else {
+            continue;
+        };
+        let (cause, detail) = match reason {
+            SkipReason::Duplicate => ("Same URL as another bookmark".to_string(), None),
+            SkipReason::OverLimit { max_urls } => (format!("Beyond max_urls ({})", max_urls), None),
+            SkipReason::FetchFailed { class, error, .. } => {
+                (format!("Could not be fetched: {}", class), Some(error))
+            }
+            SkipReason::UploadFailed { class, error, .. } => {
+                (format!("Could not be uploaded: {}", class), Some(error))
+            }
+        };
+        let entry = match detail {
+            Some(detail) => format!("- {}: {}", link(&item.title, &item.link), detail),
+            None => format!("- {}", link(&item.title, &item.link)),
+        };
+        groups.entry(cause).or_default().push(entry);
+    }
+    if groups.is_empty() {
+        return vec!["None.".to_string()];
+    }
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by(|(a_cause, a), (b_cause, b)| b.len().cmp(&a.len()).then(a_cause.cmp(b_cause)));
+    groups
+        .into_iter()
+        .enumerate()
+        .flat_map(|(index, (cause, entries))| {
+            let mut lines = if index == 0 {
+                vec![]
+            } else {
+                vec![String::new()]
+            };
+            lines.push(format!("### {} ({})", cause, entries.len()));
+            lines.push(String::new());
+            lines.extend(entries);
+            lines
+        })
+        .collect()
+}
+
+fn notebook_list(notebooks: &[NotebookLink], records: &[BookmarkRecord]) -> Vec<String> {
+    if notebooks.is_empty() {
+        return vec!["No notebooks yet.".to_string()];
+    }
+    let mut notebooks = notebooks.iter().collect::<Vec<_>>();
+    notebooks.sort_by_key(|notebook| notebook.shard);
+    notebooks
+        .into_iter()
+        .enumerate()
+        .flat_map(|(index, notebook)| {
+            let mut lines = if index == 0 {
+                vec![]
+            } else {
+                vec![String::new()]
+            };
+            lines.push(match &notebook.url {
+                Some(url) => format!("### {}", link(&notebook.title, url)),
+                None => format!("### {} (`{}`)", notebook.title, notebook.notebook_id),
+            });
+            lines.push(String::new());
+            let sources = records
+                .iter()
+                .filter(|record| record.shard == notebook.shard)
+                .filter_map(|record| Some((record, record.source_id.as_ref()?)))
+                .map(|(record, source_id)| {
+                    format!(
+                        "- {}: source `{}`",
+                        link(&record.title, &record.link),
+                        source_id
+                    )
+                })
+                .collect::<Vec<_>>();
+            if sources.is_empty() {
+                lines.push("No sources.".to_string());
+            }
+            lines.extend(sources);
+            lines
+        })
+        .collect()
+}
+
+fn link(title: &str, url: &str) -> String {
+    format!(
+        "[{}](<{}>)",
+        title.replace('[', "\\[").replace(']', "\\]"),
+        url
+    )
+}
+
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.1} s", ms as f64 / 1000.0)
+}
@@ -0,0 +1,174 @@
+//! When and how long to wait before sending a failed request again. One
+//! policy is shared by the Raindrop, content and NotebookLM clients of a
+//! run, so a service having a bad day cannot stretch the run indefinitely.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_BASE_DELAY_MS: u64 = 500;
+pub const DEFAULT_MAX_DELAY_MS: u64 = 60_000;
+pub const DEFAULT_BUDGET: u32 = 100;
+
+/// Rate-limit reset headers carry either a Unix timestamp (Raindrop) or the
+/// seconds left; anything this large can only be a timestamp.
+const EPOCH_THRESHOLD: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryLimits {
+    /// Retries of a single request after its first attempt.
+    pub max_retries: u32,
+    /// Wait before the first retry; doubled for every further one.
+    pub base_delay: Duration,
+    /// Longest single wait. A server asking for more is not retried.
+    pub max_delay: Duration,
+    /// Retries across all requests of a run.
+    pub budget: u32,
+}
+
+impl Default for RetryLimits {
+    fn default() -> Self {
+        RetryLimits {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+            budget: DEFAULT_BUDGET,
+        }
+    }
+}
+
+/// Clones share the run's retry budget.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    limits: RetryLimits,
+    remaining: Arc<AtomicU32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(RetryLimits::default())
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(limits: RetryLimits) -> Self {
+        RetryPolicy {
+            limits,
+            remaining: Arc::new(AtomicU32::new(limits.budget)),
+        }
+    }
+
+    /// Sends every request exactly once.
+    pub fn none() -> Self {
+        RetryPolicy::new(RetryLimits {
+            max_retries: 0,
+            budget: 0,
+            ..RetryLimits::default()
+        })
+    }
+
+    pub fn limits(&self) -> RetryLimits {
+        self.limits
+    }
+
+    /// Retries left in the run's budget.
+    pub fn remaining(&self) -> u32 {
+        self.remaining.load(Ordering::SeqCst)
+    }
+
+    /// How long to wait before retry number `retries + 1`, or `None` to
+    /// give up. `requested` is the wait the server asked for, if any; it is
+    /// honoured as long as it is within `max_delay`. Otherwise the wait
+    /// grows exponentially. Either way it is jittered so that concurrent
+    /// requests do not all come back at the same moment.
+    pub fn next_delay(&self, retries: u32, requested: Option<Duration>) -> Option<Duration> {
+        if retries >= self.limits.max_retries {
+            return None;
+        }
+        let delay = match requested {
+            Some(wait) if wait > self.limits.max_delay => return None,
+            Some(wait) => wait + jitter(self.limits.base_delay / 2),
+            None => {
+                let backoff = self
+                    .limits
+                    .base_delay
+                    .saturating_mul(2u32.saturating_pow(retries))
+                    .min(self.limits.max_delay);
+                backoff / 2 + jitter(backoff / 2)
+            }
+        };
+        self.remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            })
+            .ok()
+            .map(|_| delay)
+    }
+}
+
+/// 429 and 5xx mean the request may well succeed later. 501 (Not
+/// Implemented) and 505 (HTTP Version Not Supported) never will.
+pub fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || (status.is_server_error()
+            && status != StatusCode::NOT_IMPLEMENTED
+            && status != StatusCode::HTTP_VERSION_NOT_SUPPORTED)
+}
+
+/// 429 and 503 say the server turned the request away without acting on
+/// it, so even a request that is not safe to repeat can be sent again.
+/// Other server errors may come after the request took effect.
+pub fn is_unprocessed(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// The wait a response asks for, from `Retry-After` (seconds or an HTTP
+/// date) or failing that from `X-RateLimit-Reset` / `RateLimit-Reset`.
+pub fn requested_wait(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    let until = |at: DateTime<Utc>| (at - now).to_std().unwrap_or(Duration::ZERO);
+
+    if let Some(value) = header(RETRY_AFTER.as_str()) {
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(Duration::from_secs(seconds));
+        }
+        if let Ok(at) = DateTime::parse_from_rfc2822(value) {
+            return Some(until(at.with_timezone(&Utc)));
+        }
+    }
+    ["x-ratelimit-reset", "ratelimit-reset"]
+        .into_iter()
+        .find_map(|name| header(name)?.parse::<u64>().ok())
+        .map(|reset| match reset {
+            reset if reset >= EPOCH_THRESHOLD => i64::try_from(reset)
+                .ok()
+                .and_then(|reset| DateTime::from_timestamp(reset, 0))
+                .map_or(Duration::ZERO, until),
+            seconds => Duration::from_secs(seconds),
+        })
+}
+
+/// A random duration up to `max`. Good enough for spreading retries out;
+/// not for anything that needs real randomness.
+fn jitter(max: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    max.mul_f64(hasher.finish() as f64 / u64::MAX as f64)
+}
@@ -0,0 +1,178 @@
+//! Splits a scope's bookmarks across several notebooks once a single
+//! notebook would exceed NotebookLM's source or word limits.
+//!
+//! A bookmark keeps the shard it was first assigned to, and new bookmarks
+//! are placed into the first shard with room as their pages come in.
+//! Re-syncing the same selection therefore never moves a source to another
+//! notebook.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::error::{Result, StorageError};
+use crate::raindrop::BookmarkId;
+use crate::state::StateStore;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardLimits {
+    pub max_sources: usize,
+    pub max_words: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Usage {
+    sources: usize,
+    words: usize,
+}
+
+/// How full each shard is, for placing bookmarks one at a time.
+#[derive(Debug, Clone)]
+pub struct Shards {
+    usage: Vec<Usage>,
+    limits: ShardLimits,
+}
+
+impl Shards {
+    /// `placed` holds the shard and word count of every bookmark that
+    /// already has one.
+    pub fn new(placed: &[(usize, usize)], limits: ShardLimits) -> Shards {
+        let mut shards = Shards {
+            usage: Vec::new(),
+            limits,
+        };
+        for &(shard, words) in placed {
+            shards.usage_mut(shard).sources += 1;
+            shards.usage_mut(shard).words += words;
+        }
+        shards
+    }
+
+    /// The first shard with room for a bookmark of `words`, which is then
+    /// counted in it. A bookmark larger than `max_words` on its own still
+    /// gets a shard, alone in an otherwise empty one.
+    pub fn place(&mut self, words: usize) -> usize {
+        let limits = self.limits;
+        let fits = |used: &Usage| {
+            used.sources == 0
+                || (used.sources < limits.max_sources && used.words + words <= limits.max_words)
+        };
+        let shard = self.usage.iter().position(fits).unwrap_or(self.usage.len());
+        self.usage_mut(shard).sources += 1;
+        self.usage_mut(shard).words += words;
+        shard
+    }
+
+    /// Counts a placed bookmark whose content changed at its new size.
+    pub fn resize(&mut self, shard: usize, from_words: usize, to_words: usize) {
+        let used = self.usage_mut(shard);
+        used.words = (used.words + to_words).saturating_sub(from_words);
+    }
+
+    fn usage_mut(&mut self, shard: usize) -> &mut Usage {
+        if self.usage.len() <= shard {
+            self.usage.resize(shard + 1, Usage::default());
+        }
+        &mut self.usage[shard]
+    }
+}
+
+/// `Research` while everything fits in one notebook, `Research (2/3)` once
+/// the scope is split.
+pub fn title(base: &str, shard: usize, count: usize) -> String {
+    if count <= 1 {
+        base.to_string()
+    } else {
+        format!("{} ({}/{})", base, shard + 1, count)
+    }
+}
+
+/// Which bookmark lives in which notebook, for every scope in the state
+/// database.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub generated_at: DateTime<Utc>,
+    pub scopes: Vec<ScopeManifest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScopeManifest {
+    pub scope: String,
+    pub notebooks: Vec<NotebookManifest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotebookManifest {
+    pub shard: usize,
+    /// Unset until the first upload created the notebook.
+    pub notebook_id: Option<String>,
+    pub title: Option<String>,
+    pub words: usize,
+    pub bookmarks: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    pub id: BookmarkId,
+    pub title: String,
+    pub link: String,
+    pub word_count: usize,
+    pub source_id: Option<String>,
+}
+
+pub fn manifest(store: &StateStore) -> Result<Manifest> {
+    let scopes = store
+        .scopes()?
+        .into_iter()
+        .map(|scope| {
+            let records = store.records(&scope)?;
+            let known = store.notebooks(&scope)?;
+            let count = records
+                .iter()
+                .map(|record| record.shard + 1)
+                .chain(known.iter().map(|notebook| notebook.shard + 1))
+                .max()
+                .unwrap_or(0);
+            let notebooks = (0..count)
+                .map(|shard| {
+                    let notebook = known.iter().find(|notebook| notebook.shard == shard);
+                    let bookmarks = records
+                        .iter()
+                        .filter(|record| record.shard == shard)
+                        .map(|record| ManifestEntry {
+                            id: record.id,
+                            title: record.title.clone(),
+                            link: record.link.clone(),
+                            word_count: record.word_count,
+                            source_id: record.source_id.clone(),
+                        })
+                        .collect::<Vec<_>>();
+                    NotebookManifest {
+                        shard: shard + 1,
+                        notebook_id: notebook.map(|notebook| notebook.notebook_id.clone()),
+                        title: notebook.map(|notebook| notebook.title.clone()),
+                        words: bookmarks.iter().map(|entry| entry.word_count).sum(),
+                        bookmarks,
+                    }
+                })
+                .collect();
+            Ok(ScopeManifest { scope, notebooks })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Manifest {
+        generated_at: Utc::now(),
+        scopes,
+    })
+}
+
+/// Writes the manifest next to the state database and returns its path.
+pub fn write_manifest(store: &StateStore, state_dir: &Path) -> Result<PathBuf> {
+    let path = state_dir.join(MANIFEST_FILE);
+    let json = serde_json::to_string_pretty(&manifest(store)?)
+        .expect("manifest contains only strings and numbers");
+    std::fs::write(&path, json).map_err(StorageError::io(&path))?;
+    Ok(path)
+}
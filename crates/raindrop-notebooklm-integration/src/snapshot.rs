@@ -0,0 +1,393 @@
+//! The JSON record every sync leaves in the output directory: what was
+//! selected, what each page contained and what became of it (requirement
+//! 5.1). The format is published as a JSON Schema, [`SCHEMA`], so that
+//! other tools can read it without tracking this crate.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::ser::{Error as _, SerializeMap, SerializeSeq};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::content::PageContent;
+use crate::error::{Result, StorageError};
+use crate::plan::{ChangeKind, ExecutionPlan, SkipReason};
+use crate::raindrop::{Bookmark, BookmarkId};
+use crate::redact;
+use crate::state::BookmarkRecord;
+
+/// Bumped whenever the format changes incompatibly, together with
+/// `schema/snapshot.schema.json`.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// JSON Schema (draft-07) of the snapshot format.
+pub const SCHEMA: &str = include_str!("../schema/snapshot.schema.json");
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub run: RunInfo,
+    /// The resolved configuration, with secrets redacted.
+    pub config: BTreeMap<String, Option<String>>,
+    pub items: Vec<SnapshotItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub tool_version: String,
+    pub profile: String,
+    pub scope: String,
+    /// The selection in words, e.g. `#ai AND #rust`.
+    pub selection: String,
+    /// In a dry run the outcomes are what would have happened.
+    pub dry_run: bool,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Why applying the plan stopped part way, if it did.
+    pub error: Option<String>,
+}
+
+/// One bookmark the run looked at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotItem {
+    pub bookmark_id: BookmarkId,
+    pub link: String,
+    pub title: String,
+    /// Raindrop's data; unset for bookmarks that no longer match and in
+    /// runs of a saved plan.
+    pub bookmark: Option<Bookmark>,
+    /// Unset unless the page was fetched in this run.
+    pub content: Option<PageContent>,
+    /// How long downloading the page took, if it was tried in this run.
+    pub fetch_ms: Option<u64>,
+    /// The bookmark's NotebookLM source after the run.
+    pub source_id: Option<String>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    Added,
+    Replaced,
+    Refreshed,
+    Unchanged,
+    Removed,
+    /// No longer matches, but stays synced without `--prune`.
+    Stale,
+    Skipped {
+        reason: SkipReason,
+    },
+}
+
+impl Outcome {
+    fn of(kind: ChangeKind) -> Outcome {
+        match kind {
+            ChangeKind::Add => Outcome::Added,
+            ChangeKind::Replace => Outcome::Replaced,
+            ChangeKind::Refresh => Outcome::Refreshed,
+            ChangeKind::Remove => Outcome::Removed,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Puts the run together from its plan. `listed` are the bookmarks
+    /// Raindrop returned, `pages` the content fetched for some of them,
+    /// `durations` how long each download took and `records` the scope's
+    /// sync state after the run.
+    pub fn new(
+        run: RunInfo,
+        config: BTreeMap<String, Option<String>>,
+        plan: &ExecutionPlan,
+        listed: &[Bookmark],
+        pages: &HashMap<BookmarkId, PageContent>,
+        durations: &HashMap<BookmarkId, Duration>,
+        records: &[BookmarkRecord],
+    ) -> Snapshot {
+        let changes = plan
+            .changes
+            .iter()
+            .map(|change| (change.bookmark_id, change))
+            .collect::<HashMap<_, _>>();
+        let skipped = plan
+            .skipped
+            .iter()
+            .map(|skipped| (skipped.bookmark_id, &skipped.reason))
+            .collect::<HashMap<_, _>>();
+        let source_ids = records
+            .iter()
+            .map(|record| (record.id, record.source_id.clone()))
+            .collect::<HashMap<_, _>>();
+        let outcome = |id: BookmarkId| match (changes.get(&id), skipped.get(&id)) {
+            (Some(change), _) => Outcome::of(change.kind),
+            (None, Some(reason)) => Outcome::Skipped {
+                reason: (*reason).clone(),
+            },
+            (None, None) => Outcome::Unchanged,
+        };
+
+        let mut seen = HashSet::new();
+        let mut items = listed
+            .iter()
+            .filter(|bookmark| seen.insert(bookmark.id))
+            .map(|bookmark| SnapshotItem {
+                bookmark_id: bookmark.id,
+                link: bookmark.link.clone(),
+                title: bookmark.title.clone(),
+                bookmark: Some(bookmark.clone()),
+                content: pages.get(&bookmark.id).cloned(),
+                fetch_ms: durations
+                    .get(&bookmark.id)
+                    .map(|elapsed| elapsed.as_millis() as u64),
+                source_id: source_ids.get(&bookmark.id).cloned().flatten(),
+                outcome: outcome(bookmark.id),
+            })
+            .collect::<Vec<_>>();
+        let removed = plan
+            .changes
+            .iter()
+            .filter(|change| change.kind == ChangeKind::Remove)
+            .map(|change| (change.bookmark_id, &change.link, &change.title));
+        let stale = records
+            .iter()
+            .map(|record| (record.id, &record.link, &record.title));
+        for (id, link, title) in removed.chain(stale) {
+            if !seen.insert(id) {
+                continue;
+            }
+            items.push(SnapshotItem {
+                bookmark_id: id,
+                link: link.clone(),
+                title: title.clone(),
+                bookmark: None,
+                content: None,
+                fetch_ms: None,
+                source_id: source_ids.get(&id).cloned().flatten(),
+                outcome: match changes.get(&id) {
+                    Some(_) => Outcome::Removed,
+                    None => Outcome::Stale,
+                },
+            });
+        }
+
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            run,
+            config,
+            items,
+        }
+    }
+
+    /// Puts together a run of a saved plan. The plan names only the
+    /// bookmarks it changes or skips, so those are the items; Raindrop's
+    /// data and the page content stay with the dry run that made the plan.
+    pub fn applied(
+        run: RunInfo,
+        config: BTreeMap<String, Option<String>>,
+        plan: &ExecutionPlan,
+        records: &[BookmarkRecord],
+    ) -> Snapshot {
+        let source_ids = records
+            .iter()
+            .map(|record| (record.id, record.source_id.clone()))
+            .collect::<HashMap<_, _>>();
+        let item = |id: BookmarkId, link: &str, title: &str, outcome| SnapshotItem {
+            bookmark_id: id,
+            link: link.to_string(),
+            title: title.to_string(),
+            bookmark: None,
+            content: None,
+            fetch_ms: None,
+            source_id: source_ids.get(&id).cloned().flatten(),
+            outcome,
+        };
+        let changed = plan.changes.iter().map(|change| {
+            let outcome = Outcome::of(change.kind);
+            item(change.bookmark_id, &change.link, &change.title, outcome)
+        });
+        let skipped = plan.skipped.iter().map(|skipped| {
+            let outcome = Outcome::Skipped {
+                reason: skipped.reason.clone(),
+            };
+            item(skipped.bookmark_id, &skipped.link, &skipped.title, outcome)
+        });
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            run,
+            config,
+            items: changed.chain(skipped).collect(),
+        }
+    }
+
+    /// Writes the snapshot to `dir` as `sync-<profile>.json`, or under a
+    /// timestamped name if that file is already there, with secrets
+    /// redacted. Returns the path.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf> {
+        self.write(dir, None)
+    }
+
+    /// Like [`Snapshot::save`], for a snapshot whose pages had their text
+    /// set aside in `spool`. The items are written one at a time, so only
+    /// one page's text is read back at once.
+    pub fn save_spooled(&self, dir: &Path, spool: &mut PageSpool) -> Result<PathBuf> {
+        self.write(dir, Some(spool))
+    }
+
+    fn write(&self, dir: &Path, spool: Option<&mut PageSpool>) -> Result<PathBuf> {
+        let stem = format!("sync-{}", self.run.profile);
+        let path = unique_path(dir, &stem, "json", self.run.finished_at);
+        let file = File::create(&path).map_err(StorageError::io(&path))?;
+        let mut out = BufWriter::new(file);
+        let written = Redacted {
+            snapshot: self,
+            spool: RefCell::new(spool),
+        };
+        serde_json::to_writer_pretty(&mut out, &written)
+            .map_err(std::io::Error::from)
+            .and_then(|_| out.flush())
+            .map_err(StorageError::io(&path))?;
+        Ok(path)
+    }
+}
+
+/// The snapshot as it is written: every string redacted, keys included,
+/// and each page's text put back from the spool as its item is written.
+struct Redacted<'a> {
+    snapshot: &'a Snapshot,
+    spool: RefCell<Option<&'a mut PageSpool>>,
+}
+
+impl Serialize for Redacted<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        // In the order of the keys, as when the whole snapshot went
+        // through a JSON object.
+        let mut map = serializer.serialize_map(Some(4))?;
+        map.serialize_entry("config", &redacted(&self.snapshot.config))?;
+        map.serialize_entry("items", &Items(self))?;
+        map.serialize_entry("run", &redacted(&self.snapshot.run))?;
+        map.serialize_entry("version", &self.snapshot.version)?;
+        map.end()
+    }
+}
+
+struct Items<'a, 'b>(&'b Redacted<'a>);
+
+impl Serialize for Items<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let items = &self.0.snapshot.items;
+        let mut spool = self.0.spool.borrow_mut();
+        let mut seq = serializer.serialize_seq(Some(items.len()))?;
+        for item in items {
+            let mut item = item.clone();
+            if let (Some(spool), Some(page)) = (spool.as_deref_mut(), item.content.as_mut()) {
+                spool
+                    .restore(item.bookmark_id, page)
+                    .map_err(|e| S::Error::custom(e.chain()))?;
+            }
+            seq.serialize_element(&redacted(&item))?;
+        }
+        seq.end()
+    }
+}
+
+fn redacted<T: Serialize>(value: &T) -> Value {
+    let mut value = serde_json::to_value(value).expect("snapshots contain only plain data");
+    redact::redact_json(&mut value);
+    value
+}
+
+/// The text and Markdown of the pages a run fetched, set aside in a file
+/// in the output directory until the snapshot is saved, so that a run does
+/// not hold every page in memory. The file is removed when the spool is
+/// dropped.
+pub struct PageSpool {
+    path: PathBuf,
+    file: File,
+    /// Where each page's text starts in the file, and how long it is.
+    entries: HashMap<BookmarkId, (u64, u64)>,
+    end: u64,
+}
+
+impl PageSpool {
+    pub fn create(dir: &Path, profile: &str) -> Result<PageSpool> {
+        let path = dir.join(format!(".sync-{}-{}.pages", profile, std::process::id()));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(StorageError::io(&path))?;
+        Ok(PageSpool {
+            path,
+            file,
+            entries: HashMap::new(),
+            end: 0,
+        })
+    }
+
+    /// Moves the text and Markdown of `page` into the spool, leaving the
+    /// rest of it.
+    pub fn put(&mut self, id: BookmarkId, page: &mut PageContent) -> Result<()> {
+        let text = (
+            std::mem::take(&mut page.text),
+            std::mem::take(&mut page.markdown),
+        );
+        let bytes = serde_json::to_vec(&text).expect("strings always serialize");
+        self.file
+            .seek(SeekFrom::Start(self.end))
+            .and_then(|_| self.file.write_all(&bytes))
+            .map_err(StorageError::io(&self.path))?;
+        self.entries.insert(id, (self.end, bytes.len() as u64));
+        self.end += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Puts the text and Markdown set aside for `id` back into `page`.
+    pub fn restore(&mut self, id: BookmarkId, page: &mut PageContent) -> Result<()> {
+        let Some(&(start, len)) = self.entries.get(&id) else {
+            return Ok(());
+        };
+        let mut bytes = vec![0; len as usize];
+        self.file
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| self.file.read_exact(&mut bytes))
+            .map_err(StorageError::io(&self.path))?;
+        (page.text, page.markdown) =
+            serde_json::from_slice(&bytes).map_err(|e| StorageError::io(&self.path)(e.into()))?;
+        Ok(())
+    }
+}
+
+impl Drop for PageSpool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// `dir/<stem>.<extension>`, or if that exists the same with the time
+/// appended, e.g. `sync-default-20240501T120000Z.json` (requirement 5.5).
+/// A number follows the time for files written within the same second.
+pub fn unique_path(dir: &Path, stem: &str, extension: &str, now: DateTime<Utc>) -> PathBuf {
+    let plain = dir.join(format!("{}.{}", stem, extension));
+    if !plain.exists() {
+        return plain;
+    }
+    let stamped = format!("{}-{}", stem, now.format("%Y%m%dT%H%M%SZ"));
+    (1..)
+        .map(|n| match n {
+            1 => format!("{}.{}", stamped, extension),
+            n => format!("{}-{}.{}", stamped, n, extension),
+        })
+        .map(|name| dir.join(name))
+        .find(|path| !path.exists())
+        .expect("some numbered name is free")
+}
@@ -0,0 +1,357 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Result, StorageError};
+use crate::ledger::{FailedFetch, FailureClass};
+use crate::raindrop::{Bookmark, BookmarkId, CollectionId, TagMatch};
+
+pub const STATE_FILE: &str = "state.sqlite3";
+
+/// Every version of the schema, oldest first, so existing databases are
+/// upgraded in place. SQLite's `user_version` counts how many have run.
+const MIGRATIONS: &[&str] = &[
+    "
+CREATE TABLE bookmarks (
+    scope        TEXT    NOT NULL,
+    bookmark_id  INTEGER NOT NULL,
+    link         TEXT    NOT NULL,
+    title        TEXT    NOT NULL,
+    last_update  TEXT    NOT NULL,
+    content_hash TEXT    NOT NULL,
+    source_id    TEXT,
+    synced_at    TEXT    NOT NULL,
+    PRIMARY KEY (scope, bookmark_id)
+);
+CREATE TABLE notebooks (
+    scope       TEXT NOT NULL PRIMARY KEY,
+    notebook_id TEXT NOT NULL,
+    title       TEXT NOT NULL
+);
+",
+    "
+ALTER TABLE bookmarks ADD COLUMN shard INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE bookmarks ADD COLUMN word_count INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE notebook_shards (
+    scope       TEXT    NOT NULL,
+    shard       INTEGER NOT NULL,
+    notebook_id TEXT    NOT NULL,
+    title       TEXT    NOT NULL,
+    PRIMARY KEY (scope, shard)
+);
+INSERT INTO notebook_shards SELECT scope, 0, notebook_id, title FROM notebooks;
+DROP TABLE notebooks;
+",
+    "
+CREATE TABLE failed_fetches (
+    scope           TEXT    NOT NULL,
+    bookmark_id     INTEGER NOT NULL,
+    link            TEXT    NOT NULL,
+    title           TEXT    NOT NULL,
+    last_update     TEXT    NOT NULL,
+    selection       TEXT    NOT NULL,
+    class           TEXT    NOT NULL,
+    http_status     INTEGER,
+    error           TEXT    NOT NULL,
+    attempts        INTEGER NOT NULL,
+    first_failed_at TEXT    NOT NULL,
+    last_failed_at  TEXT    NOT NULL,
+    PRIMARY KEY (scope, bookmark_id)
+);
+",
+];
+
+/// What was synced for one bookmark the last time it was processed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookmarkRecord {
+    pub id: BookmarkId,
+    pub link: String,
+    pub title: String,
+    /// Raindrop's `lastUpdate` at the time the content was fetched.
+    pub last_update: DateTime<Utc>,
+    pub content_hash: String,
+    /// The NotebookLM source created from this bookmark, once uploaded.
+    pub source_id: Option<String>,
+    /// Index of the notebook shard the bookmark was assigned to. Never
+    /// changes once assigned, so re-syncs do not move sources around.
+    pub shard: usize,
+    pub word_count: usize,
+    pub synced_at: DateTime<Utc>,
+}
+
+impl BookmarkRecord {
+    /// Whether `bookmark` was edited in Raindrop since it was synced.
+    pub fn is_outdated(&self, bookmark: &Bookmark) -> bool {
+        self.last_update != bookmark.last_update || self.link != bookmark.link
+    }
+}
+
+/// One of the notebooks a scope is split across.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotebookShard {
+    pub shard: usize,
+    pub notebook_id: String,
+    pub title: String,
+}
+
+/// SQLite database in the profile's state directory. Records are kept per
+/// scope (collection, tags and match mode) so that syncing a different tag
+/// selection never reports the other selection's bookmarks as removed.
+pub struct StateStore {
+    connection: Connection,
+    path: PathBuf,
+}
+
+impl StateStore {
+    /// Opens the database in `state_dir`, creating it on first use.
+    pub fn open(state_dir: &Path) -> Result<StateStore> {
+        let path = state_dir.join(STATE_FILE);
+        let connection = Connection::open(&path).map_err(StorageError::database(&path))?;
+        migrate(&connection).map_err(StorageError::database(&path))?;
+        Ok(StateStore { connection, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn records(&self, scope: &str) -> Result<Vec<BookmarkRecord>> {
+        let query = || -> rusqlite::Result<Vec<BookmarkRecord>> {
+            let mut statement = self.connection.prepare(
+                "SELECT bookmark_id, link, title, last_update, content_hash, source_id, shard,
+                        word_count, synced_at
+                 FROM bookmarks WHERE scope = ?1 ORDER BY bookmark_id",
+            )?;
+            let records = statement.query_map(params![scope], record_from_row)?;
+            records.collect()
+        };
+        query().map_err(StorageError::database(&self.path))
+    }
+
+    pub fn save(&self, scope: &str, record: &BookmarkRecord) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO bookmarks
+                     (scope, bookmark_id, link, title, last_update, content_hash, source_id,
+                      shard, word_count, synced_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT (scope, bookmark_id) DO UPDATE SET
+                     link = excluded.link,
+                     title = excluded.title,
+                     last_update = excluded.last_update,
+                     content_hash = excluded.content_hash,
+                     source_id = excluded.source_id,
+                     shard = excluded.shard,
+                     word_count = excluded.word_count,
+                     synced_at = excluded.synced_at",
+                params![
+                    scope,
+                    record.id.0 as i64,
+                    record.link,
+                    record.title,
+                    record.last_update,
+                    record.content_hash,
+                    record.source_id,
+                    record.shard as i64,
+                    record.word_count as i64,
+                    record.synced_at,
+                ],
+            )
+            .map(|_| ())
+            .map_err(StorageError::database(&self.path))
+    }
+
+    /// The notebooks that receive this scope's sources, by shard.
+    pub fn notebooks(&self, scope: &str) -> Result<Vec<NotebookShard>> {
+        let query = || -> rusqlite::Result<Vec<NotebookShard>> {
+            let mut statement = self.connection.prepare(
+                "SELECT shard, notebook_id, title FROM notebook_shards
+                 WHERE scope = ?1 ORDER BY shard",
+            )?;
+            let shards = statement.query_map(params![scope], |row| {
+                Ok(NotebookShard {
+                    shard: row.get::<_, i64>(0)? as usize,
+                    notebook_id: row.get(1)?,
+                    title: row.get(2)?,
+                })
+            })?;
+            shards.collect()
+        };
+        query().map_err(StorageError::database(&self.path))
+    }
+
+    pub fn save_notebook(&self, scope: &str, notebook: &NotebookShard) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO notebook_shards (scope, shard, notebook_id, title)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (scope, shard) DO UPDATE SET
+                     notebook_id = excluded.notebook_id,
+                     title = excluded.title",
+                params![
+                    scope,
+                    notebook.shard as i64,
+                    notebook.notebook_id,
+                    notebook.title
+                ],
+            )
+            .map(|_| ())
+            .map_err(StorageError::database(&self.path))
+    }
+
+    /// Every scope with at least one record or notebook.
+    pub fn scopes(&self) -> Result<Vec<String>> {
+        let query = || -> rusqlite::Result<Vec<String>> {
+            let mut statement = self.connection.prepare(
+                "SELECT scope FROM bookmarks UNION SELECT scope FROM notebook_shards
+                 ORDER BY scope",
+            )?;
+            let scopes = statement.query_map([], |row| row.get(0))?;
+            scopes.collect()
+        };
+        query().map_err(StorageError::database(&self.path))
+    }
+
+    /// Adds a failed fetch to the ledger, or counts one more attempt for a
+    /// bookmark that is already in it.
+    pub fn record_failure(&self, scope: &str, failure: &FailedFetch) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO failed_fetches
+                     (scope, bookmark_id, link, title, last_update, selection, class,
+                      http_status, error, attempts, first_failed_at, last_failed_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                 ON CONFLICT (scope, bookmark_id) DO UPDATE SET
+                     link = excluded.link,
+                     title = excluded.title,
+                     last_update = excluded.last_update,
+                     selection = excluded.selection,
+                     class = excluded.class,
+                     http_status = excluded.http_status,
+                     error = excluded.error,
+                     attempts = failed_fetches.attempts + excluded.attempts,
+                     last_failed_at = excluded.last_failed_at",
+                params![
+                    scope,
+                    failure.bookmark_id.0 as i64,
+                    failure.link,
+                    failure.title,
+                    failure.last_update,
+                    failure.selection,
+                    failure.class.as_str(),
+                    failure.http_status,
+                    failure.error,
+                    failure.attempts,
+                    failure.first_failed_at,
+                    failure.last_failed_at,
+                ],
+            )
+            .map(|_| ())
+            .map_err(StorageError::database(&self.path))
+    }
+
+    /// The ledger across all scopes, with the scope of each entry.
+    pub fn failures(&self) -> Result<Vec<(String, FailedFetch)>> {
+        let query = || -> rusqlite::Result<Vec<(String, FailedFetch)>> {
+            let mut statement = self.connection.prepare(
+                "SELECT scope, bookmark_id, link, title, last_update, selection, class,
+                        http_status, error, attempts, first_failed_at, last_failed_at
+                 FROM failed_fetches ORDER BY scope, bookmark_id",
+            )?;
+            let failures = statement.query_map([], |row| {
+                let class: String = row.get(6)?;
+                let failure = FailedFetch {
+                    bookmark_id: BookmarkId(row.get::<_, i64>(1)? as u64),
+                    link: row.get(2)?,
+                    title: row.get(3)?,
+                    last_update: row.get(4)?,
+                    selection: row.get(5)?,
+                    // Classes written by a newer version read as malformed.
+                    class: FailureClass::parse(&class).unwrap_or(FailureClass::Malformed),
+                    http_status: row.get(7)?,
+                    error: row.get(8)?,
+                    attempts: row.get(9)?,
+                    first_failed_at: row.get(10)?,
+                    last_failed_at: row.get(11)?,
+                };
+                Ok((row.get(0)?, failure))
+            })?;
+            failures.collect()
+        };
+        query().map_err(StorageError::database(&self.path))
+    }
+
+    pub fn clear_failure(&self, scope: &str, id: BookmarkId) -> Result<()> {
+        self.connection
+            .execute(
+                "DELETE FROM failed_fetches WHERE scope = ?1 AND bookmark_id = ?2",
+                params![scope, id.0 as i64],
+            )
+            .map(|_| ())
+            .map_err(StorageError::database(&self.path))
+    }
+
+    pub fn remove(&self, scope: &str, id: BookmarkId) -> Result<()> {
+        self.connection
+            .execute(
+                "DELETE FROM bookmarks WHERE scope = ?1 AND bookmark_id = ?2",
+                params![scope, id.0 as i64],
+            )
+            .map(|_| ())
+            .map_err(StorageError::database(&self.path))
+    }
+}
+
+/// Runs every pending migration in one transaction, so a database is only
+/// ever at a version that `MIGRATIONS` produced.
+fn migrate(connection: &Connection) -> rusqlite::Result<()> {
+    let applied: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let pending = MIGRATIONS[(applied as usize).min(MIGRATIONS.len())..].concat();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    connection.execute_batch(&format!(
+        "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+        pending,
+        MIGRATIONS.len()
+    ))
+}
+
+fn record_from_row(row: &Row) -> rusqlite::Result<BookmarkRecord> {
+    Ok(BookmarkRecord {
+        id: BookmarkId(row.get::<_, i64>(0)? as u64),
+        link: row.get(1)?,
+        title: row.get(2)?,
+        last_update: row.get(3)?,
+        content_hash: row.get(4)?,
+        source_id: row.get(5)?,
+        shard: row.get::<_, i64>(6)? as usize,
+        word_count: row.get::<_, i64>(7)? as usize,
+        synced_at: row.get(8)?,
+    })
+}
+
+/// Identifies one bookmark selection, e.g. `collection=0;match=all;tags=ai,rust`.
+/// Tags are sorted so that `--tag a --tag b` and `--tag b --tag a` share state.
+pub fn scope(collection: CollectionId, tags: &[String], tag_match: TagMatch) -> String {
+    let mut tags = tags.to_vec();
+    tags.sort();
+    tags.dedup();
+    let tag_match = match tag_match {
+        TagMatch::All => "all",
+        TagMatch::Any => "any",
+    };
+    format!(
+        "collection={};match={};tags={}",
+        collection.0,
+        tag_match,
+        tags.join(",")
+    )
+}
+
+/// SHA-256 of the extracted content, hex encoded.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
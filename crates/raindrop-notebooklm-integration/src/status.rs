@@ -0,0 +1,230 @@
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::{exit_code, AppError, ServiceError};
+use crate::notebooklm::NotebookLMClient;
+use crate::raindrop::{RaindropClient, RateLimit};
+use crate::retry::RetryPolicy;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    Raindrop,
+    NotebookLM,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Service::Raindrop => write!(f, "Raindrop API"),
+            Service::NotebookLM => write!(f, "NotebookLM API"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CheckOutcome {
+    Ok {
+        account: String,
+        rate_limit: Option<RateLimit>,
+    },
+    NotConfigured {
+        reason: String,
+    },
+    Unreachable {
+        reason: String,
+    },
+    Unauthorized {
+        http_status: u16,
+    },
+    ApiError {
+        http_status: Option<u16>,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceStatus {
+    pub service: Service,
+    pub latency_ms: Option<u64>,
+    pub outcome: CheckOutcome,
+}
+
+/// `reachable` and `authenticated` are derived from the outcome, but
+/// monitoring dashboards should not have to re-derive them.
+impl Serialize for ServiceStatus {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            service: Service,
+            reachable: bool,
+            authenticated: bool,
+            latency_ms: Option<u64>,
+            #[serde(flatten)]
+            outcome: &'a CheckOutcome,
+        }
+        Json {
+            service: self.service,
+            reachable: self.reachable(),
+            authenticated: self.authenticated(),
+            latency_ms: self.latency_ms,
+            outcome: &self.outcome,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl ServiceStatus {
+    pub fn reachable(&self) -> bool {
+        matches!(
+            self.outcome,
+            CheckOutcome::Ok { .. }
+                | CheckOutcome::Unauthorized { .. }
+                | CheckOutcome::ApiError {
+                    http_status: Some(_),
+                    ..
+                }
+        )
+    }
+
+    pub fn authenticated(&self) -> bool {
+        matches!(self.outcome, CheckOutcome::Ok { .. })
+    }
+
+    /// Uses the same codes as a failing sync (see [`exit_code`]) so a cron
+    /// wrapper can alert differently on "service down" and "token expired".
+    pub fn exit_code(&self) -> u8 {
+        use exit_code::*;
+        match (&self.outcome, self.service) {
+            (CheckOutcome::Ok { .. }, _) => OK,
+            (CheckOutcome::NotConfigured { .. }, _) => CONFIG,
+            (CheckOutcome::Unreachable { .. }, Service::Raindrop) => RAINDROP_UNREACHABLE,
+            (CheckOutcome::Unauthorized { .. }, Service::Raindrop) => RAINDROP_UNAUTHORIZED,
+            (CheckOutcome::ApiError { .. }, Service::Raindrop) => RAINDROP_API_ERROR,
+            (CheckOutcome::Unreachable { .. }, Service::NotebookLM) => NOTEBOOKLM_UNREACHABLE,
+            (CheckOutcome::Unauthorized { .. }, Service::NotebookLM) => NOTEBOOKLM_UNAUTHORIZED,
+            (CheckOutcome::ApiError { .. }, Service::NotebookLM) => NOTEBOOKLM_API_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProfileStatus {
+    pub profile: String,
+    pub services: Vec<ServiceStatus>,
+}
+
+impl ProfileStatus {
+    /// The code of the first failing service, Raindrop before NotebookLM,
+    /// since nothing downstream works without the bookmark source.
+    pub fn exit_code(&self) -> u8 {
+        self.services
+            .iter()
+            .map(ServiceStatus::exit_code)
+            .find(|&code| code != exit_code::OK)
+            .unwrap_or(exit_code::OK)
+    }
+}
+
+/// Each service is asked once, without retries, so the report shows how it
+/// is doing right now.
+pub async fn check(config: &Config) -> ProfileStatus {
+    ProfileStatus {
+        profile: config.profile.clone(),
+        services: vec![check_raindrop(config).await, check_notebooklm(config).await],
+    }
+}
+
+async fn check_raindrop(config: &Config) -> ServiceStatus {
+    let client = match RaindropClient::from_config(config) {
+        Ok(client) => client.with_retry(RetryPolicy::none()),
+        Err(e) => return not_configured(Service::Raindrop, e),
+    };
+
+    timed(Service::Raindrop, client.authenticate(), |session| {
+        CheckOutcome::Ok {
+            account: session.user.full_name,
+            rate_limit: session.rate_limit,
+        }
+    })
+    .await
+}
+
+async fn check_notebooklm(config: &Config) -> ServiceStatus {
+    let client = match NotebookLMClient::from_config(config) {
+        Ok(client) => client.with_retry(RetryPolicy::none()),
+        Err(e) => return not_configured(Service::NotebookLM, e),
+    };
+
+    // The Discovery Engine API neither echoes the caller's identity nor sends
+    // quota headers, so the project is the most useful "account" to report.
+    let account = format!("project {}", client.project());
+    timed(Service::NotebookLM, client.list_recently_viewed(1), |_| {
+        CheckOutcome::Ok {
+            account,
+            rate_limit: None,
+        }
+    })
+    .await
+}
+
+fn not_configured(service: Service, error: AppError) -> ServiceStatus {
+    ServiceStatus {
+        service,
+        latency_ms: None,
+        outcome: CheckOutcome::NotConfigured {
+            reason: error.chain(),
+        },
+    }
+}
+
+async fn timed<T>(
+    service: Service,
+    request: impl Future<Output = crate::error::Result<T>>,
+    on_success: impl FnOnce(T) -> CheckOutcome,
+) -> ServiceStatus {
+    let started = Instant::now();
+    let result = request.await;
+    let latency = started.elapsed();
+
+    let outcome = match result {
+        Ok(value) => on_success(value),
+        Err(e) => classify(&e),
+    };
+    let latency_ms =
+        (!matches!(outcome, CheckOutcome::Unreachable { .. })).then(|| millis(latency));
+    ServiceStatus {
+        service,
+        latency_ms,
+        outcome,
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn classify(error: &AppError) -> CheckOutcome {
+    let reason = error.chain();
+    match error {
+        AppError::Raindrop(service) | AppError::NotebookLM(service) => match service {
+            ServiceError::Unreachable(_) => CheckOutcome::Unreachable { reason },
+            ServiceError::Unauthorized { status } => CheckOutcome::Unauthorized {
+                http_status: *status,
+            },
+            other => CheckOutcome::ApiError {
+                http_status: other.http_status(),
+                reason,
+            },
+        },
+        _ => CheckOutcome::ApiError {
+            http_status: None,
+            reason,
+        },
+    }
+}
@@ -0,0 +1,1429 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use tokio::sync::mpsc;
+
+use crate::content::{ContentFetcher, PageContent};
+use crate::error::{AppError, Result, ServiceError, StorageError};
+use crate::events::{EventKind, Events, Stage};
+use crate::ledger::{self, FailedFetch, FailureClass};
+use crate::notebooklm::NotebookLMClient;
+use crate::pipeline::{FetchLimits, Pipeline};
+use crate::plan::{
+    self, Change, ChangeKind, ExecutionPlan, NotebookPlan, SkipReason, Skipped, PLAN_VERSION,
+};
+use crate::raindrop::{Bookmark, BookmarkId, CollectionId, RaindropClient, TagMatch};
+use crate::report::{self, NotebookLink};
+use crate::shard::{self, ShardLimits, Shards};
+use crate::snapshot::{PageSpool, RunInfo, Snapshot};
+use crate::state::{self, BookmarkRecord, NotebookShard, StateStore};
+
+/// Bookmarks or pages waiting between two stages of a sync or export.
+/// Each stage waits when the next one falls behind, so a run holds only a
+/// few pages in memory however many bookmarks it handles.
+pub const CHANNEL_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub profile: String,
+    pub tags: Vec<String>,
+    pub tag_match: TagMatch,
+    pub collection: CollectionId,
+    pub max_urls: Option<usize>,
+    pub output_dir: PathBuf,
+    pub state_dir: PathBuf,
+    pub dry_run: bool,
+    /// Forget bookmarks that no longer match instead of only reporting them.
+    pub prune: bool,
+    /// Title for newly created notebooks; derived from the tags if unset.
+    pub notebook_title: Option<String>,
+    pub shard_limits: ShardLimits,
+    pub fetch_limits: FetchLimits,
+    /// The resolved configuration for the run snapshot, secrets redacted.
+    pub config: BTreeMap<String, Option<String>>,
+    /// Receives the bookmarks listed, pages fetched and sources uploaded.
+    pub events: Events,
+}
+
+/// What a sync run leaves behind.
+#[derive(Debug)]
+pub struct SyncRun {
+    pub plan: ExecutionPlan,
+    /// The JSON snapshot in the output directory.
+    pub snapshot: PathBuf,
+    /// The Markdown summary in the output directory, and its text.
+    pub summary_path: PathBuf,
+    pub summary: String,
+}
+
+/// Plans the sync and, unless this is a dry run, carries it out as it
+/// goes: bookmarks are fetched while the listing goes on, and each page is
+/// uploaded once it has come in. `notebooklm` is required to apply; in a
+/// dry run it is optional and only used to compare against the notebooks'
+/// actual sources.
+///
+/// Bookmarks that could not be fetched do not fail the run; they are listed
+/// in the returned plan, see `ExecutionPlan::check_fetched`. Either way the
+/// run leaves a [`Snapshot`] and a summary in the output directory.
+pub async fn run(
+    raindrop: &RaindropClient,
+    fetcher: &ContentFetcher,
+    notebooklm: Option<&NotebookLMClient>,
+    options: &SyncOptions,
+) -> Result<SyncRun> {
+    if options.dry_run {
+        warn!("🔍 Running in dry-run mode - only the snapshot and summary will be written");
+    }
+
+    [&options.output_dir, &options.state_dir]
+        .into_iter()
+        .try_for_each(|dir| std::fs::create_dir_all(dir).map_err(StorageError::io(dir)))?;
+    info!("📁 Writing artifacts to {}", options.output_dir.display());
+
+    let started_at = Utc::now();
+    let store = StateStore::open(&options.state_dir)?;
+    let mut collected = Collected {
+        listed: Vec::new(),
+        pages: HashMap::new(),
+        spool: PageSpool::create(&options.output_dir, &options.profile)?,
+        durations: HashMap::new(),
+    };
+    // The snapshot and summary are written even if the sync stops part
+    // way, so that they record how far it got.
+    let (plan, synced) = sync(
+        raindrop,
+        fetcher,
+        notebooklm,
+        &store,
+        &mut collected,
+        options,
+    )
+    .await?;
+
+    let records = store.records(&plan.scope)?;
+    let snapshot = Snapshot::new(
+        RunInfo {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            profile: options.profile.clone(),
+            scope: plan.scope.clone(),
+            selection: plan.selection.clone(),
+            dry_run: options.dry_run,
+            started_at,
+            finished_at: Utc::now(),
+            error: synced.as_ref().err().map(AppError::chain),
+        },
+        options.config.clone(),
+        &plan,
+        &collected.listed,
+        &collected.pages,
+        &collected.durations,
+        &records,
+    );
+    let snapshot_path = snapshot.save_spooled(&options.output_dir, &mut collected.spool)?;
+    info!("💾 Snapshot written to {}", snapshot_path.display());
+
+    let (summary_path, summary) =
+        summarize(&store, &snapshot, &records, notebooklm, &options.output_dir)?;
+
+    synced?;
+    Ok(SyncRun {
+        plan,
+        snapshot: snapshot_path,
+        summary_path,
+        summary,
+    })
+}
+
+/// Writes the summary of the run `snapshot` records to `output_dir` and
+/// returns its path and text.
+fn summarize(
+    store: &StateStore,
+    snapshot: &Snapshot,
+    records: &[BookmarkRecord],
+    notebooklm: Option<&NotebookLMClient>,
+    output_dir: &Path,
+) -> Result<(PathBuf, String)> {
+    let notebooks = store
+        .notebooks(&snapshot.run.scope)?
+        .into_iter()
+        .map(|notebook| NotebookLink {
+            url: notebooklm.map(|client| client.notebook_url(&notebook.notebook_id)),
+            shard: notebook.shard,
+            title: notebook.title,
+            notebook_id: notebook.notebook_id,
+        })
+        .collect::<Vec<_>>();
+    let summary = report::render(snapshot, &notebooks, records);
+    let summary_path = report::write(output_dir, snapshot, &summary)?;
+    info!("📝 Summary written to {}", summary_path.display());
+    Ok((summary_path, summary))
+}
+
+/// What a sync gathered on the way, for the snapshot.
+struct Collected {
+    /// Each bookmark once, in the order Raindrop listed them.
+    listed: Vec<Bookmark>,
+    /// The pages fetched, their text set aside in `spool`.
+    pages: HashMap<BookmarkId, PageContent>,
+    spool: PageSpool,
+    durations: HashMap<BookmarkId, Duration>,
+}
+
+/// Where a run of a saved plan keeps its state and artifacts.
+#[derive(Debug, Clone)]
+pub struct ApplyOptions {
+    pub output_dir: PathBuf,
+    pub state_dir: PathBuf,
+    /// The resolved configuration for the run snapshot, secrets redacted.
+    pub config: BTreeMap<String, Option<String>>,
+    /// Receives the sources uploaded.
+    pub events: Events,
+}
+
+/// Executes a plan saved by an earlier dry run. Nothing is fetched again:
+/// the plan carries the content it was made with. The returned plan lists
+/// the changes whose upload failed as skipped; they are in the failure
+/// ledger. Like [`run`], this leaves a [`Snapshot`] and a summary in the
+/// output directory, covering the bookmarks the plan changes or skips.
+pub async fn apply_plan(
+    mut plan: ExecutionPlan,
+    notebooklm: &NotebookLMClient,
+    options: &ApplyOptions,
+) -> Result<SyncRun> {
+    let state_dir = &options.state_dir;
+    [&options.output_dir, state_dir]
+        .into_iter()
+        .try_for_each(|dir| std::fs::create_dir_all(dir).map_err(StorageError::io(dir)))?;
+    let store = StateStore::open(state_dir)?;
+    if plan::state_fingerprint(&store.records(&plan.scope)?) != plan.state_fingerprint {
+        return Err(AppError::Storage(StorageError::Plan(
+            "the sync state changed since the plan was made; create a new plan".to_string(),
+        )));
+    }
+    info!("📁 Writing artifacts to {}", options.output_dir.display());
+    let started_at = Utc::now();
+    let ledger = Ledger {
+        store: &store,
+        scope: &plan.scope,
+        selection: &plan.selection,
+    };
+    // Recorded before anything is uploaded, so that they are not lost if
+    // the uploads fail.
+    ledger.record(&plan.skipped, plan.created_at)?;
+    info!(
+        "📚 Applying plan: {} to add, {} to replace, {} to refresh, {} to remove",
+        plan.count(ChangeKind::Add),
+        plan.count(ChangeKind::Replace),
+        plan.count(ChangeKind::Refresh),
+        plan.count(ChangeKind::Remove)
+    );
+
+    let events = &options.events;
+    let mut failed = Vec::new();
+    let applied = async {
+        let mut applier =
+            Applier::new(notebooklm, ledger, Titles::Planned(&plan.notebooks), events)?;
+        applier.notebooks.retitle(&plan.notebooks).await?;
+        events.emit(EventKind::StageStarted {
+            stage: Stage::Uploading,
+            total: Some(plan.count(ChangeKind::Add) + plan.count(ChangeKind::Replace)),
+        });
+        for change in &plan.changes {
+            failed.extend(applier.apply(change).await?);
+        }
+        events.emit(EventKind::StageFinished {
+            stage: Stage::Uploading,
+        });
+        if plan.failures() + failed.len() > 0 {
+            info!(
+                "📝 Failures recorded in {}; run `retry-failed` to try them again",
+                store.path().display()
+            );
+        }
+        let manifest = shard::write_manifest(&store, state_dir)?;
+        info!("🧾 Notebook manifest written to {}", manifest.display());
+        Ok(())
+    }
+    .await;
+    plan.fail_uploads(failed);
+
+    // Written even if applying stops part way, as for a sync.
+    let records = store.records(&plan.scope)?;
+    let snapshot = Snapshot::applied(
+        RunInfo {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            profile: plan.profile.clone(),
+            scope: plan.scope.clone(),
+            selection: plan.selection.clone(),
+            dry_run: false,
+            started_at,
+            finished_at: Utc::now(),
+            error: applied.as_ref().err().map(AppError::chain),
+        },
+        options.config.clone(),
+        &plan,
+        &records,
+    );
+    let snapshot_path = snapshot.save(&options.output_dir)?;
+    info!("💾 Snapshot written to {}", snapshot_path.display());
+    let (summary_path, summary) = summarize(
+        &store,
+        &snapshot,
+        &records,
+        Some(notebooklm),
+        &options.output_dir,
+    )?;
+
+    applied?;
+    Ok(SyncRun {
+        plan,
+        snapshot: snapshot_path,
+        summary_path,
+        summary,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryOptions {
+    pub profile: String,
+    /// Only retry failures of these classes; all of them if empty.
+    pub classes: Vec<FailureClass>,
+    pub state_dir: PathBuf,
+    pub dry_run: bool,
+    pub notebook_title: Option<String>,
+    pub shard_limits: ShardLimits,
+    pub fetch_limits: FetchLimits,
+}
+
+/// Fetches the bookmarks in the failure ledger again and, unless this is a
+/// dry run, uploads the pages that now come through. Nothing is asked of
+/// Raindrop: the ledger has everything needed. Returns one plan per scope
+/// with failures to retry, in scope order.
+pub async fn retry_failed(
+    fetcher: &ContentFetcher,
+    notebooklm: Option<&NotebookLMClient>,
+    options: &RetryOptions,
+) -> Result<Vec<ExecutionPlan>> {
+    if options.dry_run {
+        warn!("🔍 Running in dry-run mode - no actual changes will be made");
+    }
+    std::fs::create_dir_all(&options.state_dir).map_err(StorageError::io(&options.state_dir))?;
+    let store = StateStore::open(&options.state_dir)?;
+
+    let mut by_scope = BTreeMap::<String, Vec<FailedFetch>>::new();
+    for (scope, failure) in store.failures()? {
+        if options.classes.is_empty() || options.classes.contains(&failure.class) {
+            by_scope.entry(scope).or_default().push(failure);
+        }
+    }
+    if by_scope.is_empty() {
+        info!("✨ No failed fetches to retry");
+    }
+
+    let mut plans = Vec::new();
+    for (scope, failures) in by_scope {
+        plans.push(retry_scope(fetcher, notebooklm, &store, scope, &failures, options).await?);
+    }
+    Ok(plans)
+}
+
+async fn retry_scope(
+    fetcher: &ContentFetcher,
+    notebooklm: Option<&NotebookLMClient>,
+    store: &StateStore,
+    scope: String,
+    failures: &[FailedFetch],
+    options: &RetryOptions,
+) -> Result<ExecutionPlan> {
+    let selection = failures[0].selection.clone();
+    info!(
+        "🔁 Retrying {} failed fetches for {}",
+        failures.len(),
+        selection
+    );
+    let records = store.records(&scope)?;
+    let base_title = options
+        .notebook_title
+        .clone()
+        .unwrap_or_else(|| format!("Raindrop: {}", selection));
+    let mut plan = ExecutionPlan {
+        version: PLAN_VERSION,
+        created_at: Utc::now(),
+        profile: options.profile.clone(),
+        state_fingerprint: plan::state_fingerprint(&records),
+        scope: scope.clone(),
+        selection: selection.clone(),
+        checked_sources: false,
+        notebooks: Vec::new(),
+        changes: Vec::new(),
+        unchanged: 0,
+        stale: 0,
+        skipped: Vec::new(),
+    };
+
+    let events = Events::default();
+    let mut applier = if options.dry_run {
+        None
+    } else {
+        let client = notebooklm.expect("a NotebookLM client is required outside dry runs");
+        let ledger = Ledger {
+            store,
+            scope: &scope,
+            selection: &selection,
+        };
+        let titles = Titles::Provisional(base_title.clone());
+        Some(Applier::new(client, ledger, titles, &events)?)
+    };
+    let known_records = records
+        .iter()
+        .map(|record| (record.id, record))
+        .collect::<HashMap<_, _>>();
+    let stopped = AtomicBool::new(false);
+    let (queue, queued) = mpsc::channel(CHANNEL_CAPACITY);
+    let queueing = async {
+        for (index, failure) in failures.iter().enumerate() {
+            if stopped.load(Ordering::Relaxed) {
+                break;
+            }
+            let bookmark = ledger_bookmark(failure);
+            let link = bookmark.link.clone();
+            let fetch = Fetch {
+                index,
+                record: known_records
+                    .get(&bookmark.id)
+                    .map(|&record| record.clone()),
+                missing: false,
+                bookmark,
+            };
+            if queue.send((fetch, link)).await.is_err() {
+                break;
+            }
+        }
+        drop(queue);
+        Ok(())
+    };
+    let placed = records
+        .iter()
+        .map(|record| (record.shard, record.word_count))
+        .collect::<Vec<_>>();
+    let mut fetched = Fetched::new(Shards::new(&placed, options.shard_limits));
+    let pipeline = Pipeline::new(fetcher.clone(), options.fetch_limits);
+    let streamed = tokio::try_join!(
+        queueing,
+        fetch_and_apply(
+            &pipeline,
+            queued,
+            applier.as_mut(),
+            |_, _, _| Ok(()),
+            &stopped,
+            &mut fetched,
+            &events,
+        )
+    );
+    fetched.add_to(&mut plan);
+    streamed?;
+
+    let known = store.notebooks(&scope)?;
+    plan.notebooks = plan_notebooks(&base_title, &records, &plan.changes, &known);
+    if let Some(applier) = &mut applier {
+        applier.notebooks.retitle(&plan.notebooks).await?;
+        shard::write_manifest(store, &options.state_dir)?;
+    }
+    Ok(plan)
+}
+
+/// Enough of the bookmark to fetch and upload it again. The failure time
+/// stands in for the creation date, which only orders new bookmarks.
+fn ledger_bookmark(failure: &FailedFetch) -> Bookmark {
+    Bookmark {
+        id: failure.bookmark_id,
+        title: failure.title.clone(),
+        link: failure.link.clone(),
+        excerpt: String::new(),
+        note: String::new(),
+        tags: Vec::new(),
+        created: failure.first_failed_at,
+        last_update: failure.last_update,
+        collection_id: CollectionId::ALL,
+        domain: String::new(),
+    }
+}
+
+/// Lists the selection, fetches what changed and, outside dry runs,
+/// uploads it, all at once: each bookmark goes on to be fetched as soon as
+/// it is listed, and each page on to be uploaded as soon as it is fetched.
+/// What the run comes across is kept in `collected`. Once the listing has
+/// started, the plan is returned however the run ends, with the error that
+/// stopped it if any.
+async fn sync(
+    raindrop: &RaindropClient,
+    fetcher: &ContentFetcher,
+    notebooklm: Option<&NotebookLMClient>,
+    store: &StateStore,
+    collected: &mut Collected,
+    options: &SyncOptions,
+) -> Result<(ExecutionPlan, Result<()>)> {
+    info!("🔗 Connecting to Raindrop API...");
+    let session = raindrop.authenticate().await?;
+    info!("👤 Authenticated as {}", session.user.full_name);
+
+    let tag_list = describe_tags(&options.tags, options.tag_match);
+    let scope = state::scope(options.collection, &options.tags, options.tag_match);
+    let records = store.records(&scope)?;
+    // Sources deleted by hand in NotebookLM are uploaded again.
+    let live_sources = match notebooklm {
+        Some(client) => {
+            let mut ids = HashSet::new();
+            for notebook in store.notebooks(&scope)? {
+                let sources = client.list_sources(&notebook.notebook_id).await?;
+                ids.extend(sources.into_iter().map(|source| source.source_id.id));
+            }
+            Some(ids)
+        }
+        None => None,
+    };
+    let base_title = options
+        .notebook_title
+        .clone()
+        .unwrap_or_else(|| format!("Raindrop: {}", tag_list));
+    let mut plan = ExecutionPlan {
+        version: PLAN_VERSION,
+        created_at: Utc::now(),
+        profile: options.profile.clone(),
+        scope: scope.clone(),
+        selection: tag_list.clone(),
+        state_fingerprint: plan::state_fingerprint(&records),
+        checked_sources: live_sources.is_some(),
+        notebooks: Vec::new(),
+        changes: Vec::new(),
+        unchanged: 0,
+        stale: 0,
+        skipped: Vec::new(),
+    };
+
+    let events = &options.events;
+    let mut applier = if options.dry_run {
+        None
+    } else {
+        let client = notebooklm.expect("a NotebookLM client is required outside dry runs");
+        let ledger = Ledger {
+            store,
+            scope: &scope,
+            selection: &tag_list,
+        };
+        let titles = Titles::Provisional(base_title.clone());
+        Some(Applier::new(client, ledger, titles, events)?)
+    };
+
+    let synced = async {
+        events.emit(EventKind::StageStarted {
+            stage: Stage::Listing,
+            total: None,
+        });
+        events.emit(EventKind::StageStarted {
+            stage: Stage::Fetching,
+            total: None,
+        });
+        if applier.is_some() {
+            events.emit(EventKind::StageStarted {
+                stage: Stage::Uploading,
+                total: None,
+            });
+        }
+        info!("📄 Fetching page contents as bookmarks are listed...");
+
+        let Collected {
+            listed,
+            pages,
+            spool,
+            durations,
+        } = &mut *collected;
+        let (found, listing) = mpsc::channel(CHANNEL_CAPACITY);
+        let (queue, queued) = mpsc::channel(CHANNEL_CAPACITY);
+        let stopped = AtomicBool::new(false);
+
+        let listing_stage = async {
+            raindrop
+                .send_bookmarks_by_tags(
+                    options.collection,
+                    &options.tags,
+                    options.tag_match,
+                    &found,
+                )
+                .await?;
+            drop(found);
+            events.emit(EventKind::StageFinished {
+                stage: Stage::Listing,
+            });
+            Ok(())
+        };
+
+        let known_records = records
+            .iter()
+            .map(|record| (record.id, record))
+            .collect::<HashMap<_, _>>();
+        let mut skipped = Vec::new();
+        let (mut new, mut changed, mut unchanged, mut missing) = (0, 0, 0, 0);
+        let selecting = async {
+            let mut listing = listing;
+            let mut selector = Selector::new(options.max_urls);
+            let mut queued = 0;
+            while let Some(bookmark) = listing.recv().await {
+                let reason = match selector.select(&bookmark) {
+                    // Reported once each, however many of the tags a
+                    // bookmark carries.
+                    Selection::Repeated => continue,
+                    Selection::Selected => None,
+                    Selection::Skipped(reason) => Some(reason),
+                };
+                events.emit(EventKind::BookmarkListed {
+                    bookmark_id: bookmark.id,
+                    link: bookmark.link.clone(),
+                    title: bookmark.title.clone(),
+                });
+                listed.push(bookmark.clone());
+                if let Some(reason) = reason {
+                    skipped.push(skip(&bookmark, reason));
+                    continue;
+                }
+                info!("  - {} ({})", bookmark.title, bookmark.link);
+
+                let record = known_records.get(&bookmark.id).copied();
+                let is_missing = match record {
+                    Some(record) if !record.is_outdated(&bookmark) => {
+                        match (&live_sources, &record.source_id) {
+                            (Some(live), Some(id)) => !live.contains(id),
+                            (_, None) => true,
+                            (None, Some(_)) => false,
+                        }
+                    }
+                    _ => false,
+                };
+                match record {
+                    None => new += 1,
+                    Some(record) if record.is_outdated(&bookmark) => changed += 1,
+                    Some(_) if is_missing => missing += 1,
+                    Some(_) => {
+                        unchanged += 1;
+                        continue;
+                    }
+                }
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let link = bookmark.link.clone();
+                let fetch = Fetch {
+                    index: queued,
+                    bookmark,
+                    record: record.cloned(),
+                    missing: is_missing,
+                };
+                if queue.send((fetch, link)).await.is_err() {
+                    break;
+                }
+                queued += 1;
+            }
+            drop(queue);
+            events.emit(EventKind::StageTotal {
+                stage: Stage::Fetching,
+                total: queued,
+            });
+            Ok(())
+        };
+
+        // Known bookmarks keep their shard, those beyond `max_urls`
+        // included. So do those about to be pruned, which are only known
+        // once the listing is done.
+        let placed = records
+            .iter()
+            .map(|record| (record.shard, record.word_count))
+            .collect::<Vec<_>>();
+        let mut fetched = Fetched::new(Shards::new(&placed, options.shard_limits));
+        let pipeline = Pipeline::new(fetcher.clone(), options.fetch_limits);
+        let fetching = fetch_and_apply(
+            &pipeline,
+            queued,
+            applier.as_mut(),
+            |id, page, elapsed| {
+                durations.insert(id, elapsed);
+                if let Some(mut page) = page {
+                    spool.put(id, &mut page)?;
+                    pages.insert(id, page);
+                }
+                Ok(())
+            },
+            &stopped,
+            &mut fetched,
+            events,
+        );
+        let streamed = tokio::try_join!(listing_stage, selecting, fetching);
+        plan.unchanged = unchanged;
+        plan.skipped.append(&mut skipped);
+        fetched.add_to(&mut plan);
+        streamed?;
+
+        info!(
+            "🔖 Selected {} bookmarks for {}",
+            new + changed + unchanged + missing,
+            tag_list
+        );
+        // With nothing synced before, an empty selection is most likely a typo
+        // in a tag; otherwise every bookmark that was synced has gone away.
+        if listed.is_empty() && records.is_empty() {
+            return Err(AppError::Raindrop(ServiceError::NotFound(format!(
+                "no bookmarks found for {}",
+                tag_list
+            ))));
+        }
+        let listed_ids = listed
+            .iter()
+            .map(|bookmark| bookmark.id)
+            .collect::<HashSet<_>>();
+        let removed = records
+            .iter()
+            .filter(|record| !listed_ids.contains(&record.id))
+            .collect::<Vec<_>>();
+        info!(
+            "🗂️ {} new, {} changed, {} unchanged, {} no longer matching (state: {})",
+            new,
+            changed,
+            unchanged + missing,
+            removed.len(),
+            store.path().display()
+        );
+        if missing > 0 {
+            warn!(
+                "⚠️ {} unchanged bookmarks had no source in NotebookLM and were fetched again",
+                missing
+            );
+        }
+        if let Some(applier) = &applier {
+            let forgotten = applier.ledger.forget_unlisted(&listed_ids)?;
+            if forgotten > 0 {
+                info!(
+                    "🧹 Dropped {} failed fetches that no longer match {}",
+                    forgotten, tag_list
+                );
+            }
+        }
+        for record in removed {
+            warn!(
+                "  🗑️ {} ({}) no longer matches {}: deleted, untagged or moved",
+                record.title, record.link, tag_list
+            );
+            if !options.prune {
+                plan.stale += 1;
+                continue;
+            }
+            let change = Change {
+                kind: ChangeKind::Remove,
+                bookmark_id: record.id,
+                link: record.link.clone(),
+                title: record.title.clone(),
+                last_update: record.last_update,
+                shard: record.shard,
+                word_count: record.word_count,
+                content_hash: record.content_hash.clone(),
+                source_id: record.source_id.clone(),
+                content: None,
+            };
+            if let Some(applier) = &mut applier {
+                applier.apply(&change).await?;
+            }
+            plan.changes.push(change);
+        }
+        if applier.is_some() {
+            events.emit(EventKind::StageFinished {
+                stage: Stage::Uploading,
+            });
+        }
+
+        let known = store.notebooks(&scope)?;
+        plan.notebooks = plan_notebooks(&base_title, &records, &plan.changes, &known);
+        if plan.notebooks.len() > 1 {
+            info!(
+                "📚 {} is split across {} notebooks (at most {} sources and {} words each)",
+                tag_list,
+                plan.notebooks.len(),
+                options.shard_limits.max_sources,
+                options.shard_limits.max_words
+            );
+        }
+        if let Some(applier) = &mut applier {
+            applier.notebooks.retitle(&plan.notebooks).await?;
+            if plan.failures() > 0 {
+                info!(
+                    "📝 Failures recorded in {}; run `retry-failed` to try them again",
+                    store.path().display()
+                );
+            }
+            let manifest = shard::write_manifest(store, &options.state_dir)?;
+            info!("🧾 Notebook manifest written to {}", manifest.display());
+        }
+        Ok(())
+    }
+    .await;
+    Ok((plan, synced))
+}
+
+/// A bookmark on its way through the pipeline.
+struct Fetch {
+    /// Its place among the bookmarks queued, counted from 0 without gaps.
+    index: usize,
+    bookmark: Bookmark,
+    record: Option<BookmarkRecord>,
+    /// Whether its source vanished from the notebook.
+    missing: bool,
+}
+
+/// What came of the pages fetched, each with its place among them, so
+/// that the plan lists them in that order however the downloads complete.
+struct Fetched {
+    /// Where the new bookmarks go.
+    shards: Shards,
+    changes: Vec<(usize, Change)>,
+    fetch_failed: Vec<(usize, Skipped)>,
+    upload_failed: Vec<(usize, Skipped)>,
+}
+
+impl Fetched {
+    fn new(shards: Shards) -> Fetched {
+        Fetched {
+            shards,
+            changes: Vec::new(),
+            fetch_failed: Vec::new(),
+            upload_failed: Vec::new(),
+        }
+    }
+
+    /// Adds the changes to `plan`, and the failures after the bookmarks
+    /// it already skips.
+    fn add_to(self, plan: &mut ExecutionPlan) {
+        fn in_order<T>(mut items: Vec<(usize, T)>) -> impl Iterator<Item = T> {
+            items.sort_by_key(|(index, _)| *index);
+            items.into_iter().map(|(_, item)| item)
+        }
+        plan.changes.extend(in_order(self.changes));
+        plan.skipped.extend(in_order(self.fetch_failed));
+        plan.skipped.extend(in_order(self.upload_failed));
+    }
+}
+
+/// Fetches the bookmarks queued up and turns each page into a change as it
+/// comes in, which `applier`, if there is one, carries out right away.
+/// Changes wait for it in a bounded channel, so fetching runs no further
+/// ahead of uploading than that. Pages that could not be fetched are
+/// skipped, not fatal (requirement 2.3).
+///
+/// `keep` gets each page once its change is made, or `None` for a page
+/// that could not be fetched, and how long the download took. Once the
+/// applier stops the run, `stopped` is set and the changes still to come
+/// are dropped.
+async fn fetch_and_apply(
+    pipeline: &Pipeline,
+    queue: mpsc::Receiver<(Fetch, String)>,
+    mut applier: Option<&mut Applier<'_>>,
+    mut keep: impl FnMut(BookmarkId, Option<PageContent>, Duration) -> Result<()>,
+    stopped: &AtomicBool,
+    fetched: &mut Fetched,
+    events: &Events,
+) -> Result<()> {
+    let ledger = applier.as_ref().map(|applier| applier.ledger);
+    let Fetched {
+        shards,
+        changes: applied,
+        fetch_failed,
+        upload_failed,
+    } = fetched;
+    let mut results = pipeline.run_queued(queue);
+    let (changes, mut changed) = mpsc::channel(CHANNEL_CAPACITY);
+
+    let receiving = async {
+        let mut uploads = 0;
+        // Pages wait here for those queued before them, so that new
+        // bookmarks go into shards in listing order however the downloads
+        // complete. A page that could not be fetched leaves `None`.
+        let mut waiting = BTreeMap::new();
+        let mut next = 0;
+        'receiving: while let Some((fetch, result, transfer)) = results.recv().await {
+            let index = fetch.index;
+            let bookmark = &fetch.bookmark;
+            let fetch_ms = transfer.elapsed.as_millis() as u64;
+            match result {
+                Ok(page) => {
+                    info!("  ✓ {} ({} words)", page.title, page.word_count);
+                    events.emit(EventKind::FetchSucceeded {
+                        bookmark_id: bookmark.id,
+                        link: bookmark.link.clone(),
+                        title: page.title.clone(),
+                        word_count: page.word_count,
+                        fetch_ms,
+                        bytes: transfer.bytes,
+                    });
+                    waiting.insert(index, Some((fetch, page, transfer.elapsed)));
+                }
+                Err(e) => {
+                    warn!("  ✗ Skipping {}: {}", bookmark.link, e.chain());
+                    let (class, http_status) = ledger::classify(&e);
+                    events.emit(EventKind::FetchFailed {
+                        bookmark_id: bookmark.id,
+                        link: bookmark.link.clone(),
+                        class,
+                        http_status,
+                        error: e.chain(),
+                        fetch_ms,
+                    });
+                    let failed = skip(
+                        bookmark,
+                        SkipReason::FetchFailed {
+                            class,
+                            http_status,
+                            error: e.chain(),
+                        },
+                    );
+                    // Recorded right away, so that it is not lost if
+                    // uploading stops the run.
+                    if let Some(ledger) = ledger {
+                        ledger.record(std::slice::from_ref(&failed), Utc::now())?;
+                    }
+                    fetch_failed.push((index, failed));
+                    keep(bookmark.id, None, transfer.elapsed)?;
+                    waiting.insert(index, None);
+                }
+            }
+            while let Some(arrived) = waiting.remove(&next) {
+                next += 1;
+                let Some((fetch, page, elapsed)) = arrived else {
+                    continue;
+                };
+                let Fetch {
+                    index,
+                    bookmark,
+                    record,
+                    missing,
+                } = fetch;
+                let shard = match &record {
+                    Some(record) => {
+                        shards.resize(record.shard, record.word_count, page.word_count);
+                        record.shard
+                    }
+                    None => shards.place(page.word_count),
+                };
+                let change = page_change(&bookmark, &page, record.as_ref(), missing, shard);
+                if change.content.is_some() {
+                    uploads += 1;
+                }
+                keep(bookmark.id, Some(page), elapsed)?;
+                if changes.send((index, change)).await.is_err() {
+                    break 'receiving;
+                }
+            }
+        }
+        drop(changes);
+        events.emit(EventKind::StageFinished {
+            stage: Stage::Fetching,
+        });
+        if ledger.is_some() {
+            events.emit(EventKind::StageTotal {
+                stage: Stage::Uploading,
+                total: uploads,
+            });
+        }
+        Ok(())
+    };
+
+    let applying = async {
+        let mut stop = None;
+        while let Some((index, mut change)) = changed.recv().await {
+            let Some(applier) = applier.as_deref_mut() else {
+                applied.push((index, change));
+                continue;
+            };
+            if stop.is_some() {
+                continue;
+            }
+            match applier.apply(&change).await {
+                Ok(None) => {
+                    // The page is in the notebook; only the record of it
+                    // is kept.
+                    change.content = None;
+                    applied.push((index, change));
+                }
+                Ok(Some(failed)) => upload_failed.push((index, failed)),
+                Err(e) => {
+                    stopped.store(true, Ordering::Relaxed);
+                    stop = Some(e);
+                }
+            }
+        }
+        stop.map_or(Ok(()), Err)
+    };
+
+    tokio::try_join!(receiving, applying).map(|_| ())
+}
+
+/// What to do with a fetched page: upload it, replace the source it had,
+/// or only refresh its record when the content is unchanged. `missing`
+/// means its source vanished from the notebook.
+fn page_change(
+    bookmark: &Bookmark,
+    page: &PageContent,
+    record: Option<&BookmarkRecord>,
+    missing: bool,
+    shard: usize,
+) -> Change {
+    let content_hash = state::content_hash(&page.markdown);
+    let source_id = record.and_then(|record| record.source_id.clone());
+    let kind = match record {
+        None => ChangeKind::Add,
+        Some(_) if missing => ChangeKind::Add,
+        Some(_) if source_id.is_none() => ChangeKind::Add,
+        Some(record) if record.content_hash == content_hash => ChangeKind::Refresh,
+        Some(_) => ChangeKind::Replace,
+    };
+    let uploads = matches!(kind, ChangeKind::Add | ChangeKind::Replace);
+    Change {
+        kind,
+        bookmark_id: bookmark.id,
+        link: bookmark.link.clone(),
+        title: page.title.clone(),
+        last_update: bookmark.last_update,
+        shard,
+        word_count: page.word_count,
+        content_hash,
+        // A source that vanished from the notebook is not replaced.
+        source_id: source_id.filter(|_| !missing),
+        content: uploads.then(|| page.markdown.clone()),
+    }
+}
+
+fn skip(bookmark: &Bookmark, reason: SkipReason) -> Skipped {
+    Skipped {
+        bookmark_id: bookmark.id,
+        link: bookmark.link.clone(),
+        title: bookmark.title.clone(),
+        last_update: bookmark.last_update,
+        reason,
+    }
+}
+
+/// Every notebook the scope will have once `changes` are applied, with its
+/// final title and estimated size.
+fn plan_notebooks(
+    base_title: &str,
+    records: &[BookmarkRecord],
+    changes: &[Change],
+    known: &[NotebookShard],
+) -> Vec<NotebookPlan> {
+    let mut contents: HashMap<BookmarkId, (usize, usize)> = records
+        .iter()
+        .map(|record| (record.id, (record.shard, record.word_count)))
+        .collect();
+    for change in changes {
+        match change.kind {
+            ChangeKind::Remove => contents.remove(&change.bookmark_id),
+            _ => contents.insert(change.bookmark_id, (change.shard, change.word_count)),
+        };
+    }
+    let count = contents
+        .values()
+        .map(|&(shard, _)| shard + 1)
+        .chain(known.iter().map(|notebook| notebook.shard + 1))
+        .max()
+        .unwrap_or(0);
+    (0..count)
+        .filter_map(|shard| {
+            let existing = known.iter().find(|notebook| notebook.shard == shard);
+            let members = contents
+                .values()
+                .filter(|&&(member_shard, _)| member_shard == shard);
+            let (sources, words) =
+                members.fold((0, 0), |(sources, words), &(_, w)| (sources + 1, words + w));
+            if existing.is_none() && sources == 0 {
+                return None;
+            }
+            let title = shard::title(base_title, shard, count);
+            Some(NotebookPlan {
+                shard,
+                notebook_id: existing.map(|notebook| notebook.notebook_id.clone()),
+                renamed_from: existing
+                    .filter(|notebook| notebook.title != title)
+                    .map(|notebook| notebook.title.clone()),
+                title,
+                sources,
+                words,
+            })
+        })
+        .collect()
+}
+
+/// The failure ledger of one scope.
+#[derive(Clone, Copy)]
+struct Ledger<'a> {
+    store: &'a StateStore,
+    scope: &'a str,
+    selection: &'a str,
+}
+
+impl Ledger<'_> {
+    /// Puts the bookmarks of `skipped` that failed to be fetched or
+    /// uploaded into the ledger.
+    fn record(&self, skipped: &[Skipped], failed_at: DateTime<Utc>) -> Result<()> {
+        for skipped in skipped {
+            let (SkipReason::FetchFailed {
+                class,
+                http_status,
+                error,
+            }
+            | SkipReason::UploadFailed {
+                class,
+                http_status,
+                error,
+            }) = &skipped.reason
+            else {
+                continue;
+            };
+            self.store.record_failure(
+                self.scope,
+                &FailedFetch {
+                    bookmark_id: skipped.bookmark_id,
+                    link: skipped.link.clone(),
+                    title: skipped.title.clone(),
+                    last_update: skipped.last_update,
+                    selection: self.selection.to_string(),
+                    class: *class,
+                    http_status: *http_status,
+                    error: error.clone(),
+                    attempts: 1,
+                    first_failed_at: failed_at,
+                    last_failed_at: failed_at,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Drops the entries of bookmarks the selection no longer lists, which
+    /// `retry-failed` would otherwise upload again. Returns how many there
+    /// were.
+    fn forget_unlisted(&self, listed: &HashSet<BookmarkId>) -> Result<usize> {
+        let mut forgotten = 0;
+        for (scope, failure) in self.store.failures()? {
+            if scope == self.scope && !listed.contains(&failure.bookmark_id) {
+                self.store.clear_failure(self.scope, failure.bookmark_id)?;
+                forgotten += 1;
+            }
+        }
+        Ok(forgotten)
+    }
+}
+
+/// Carries out changes one at a time, in NotebookLM and in the sync state.
+struct Applier<'a> {
+    client: &'a NotebookLMClient,
+    notebooks: Notebooks<'a>,
+    ledger: Ledger<'a>,
+    titles: Titles<'a>,
+    events: &'a Events,
+}
+
+impl<'a> Applier<'a> {
+    fn new(
+        client: &'a NotebookLMClient,
+        ledger: Ledger<'a>,
+        titles: Titles<'a>,
+        events: &'a Events,
+    ) -> Result<Applier<'a>> {
+        Ok(Applier {
+            client,
+            notebooks: Notebooks::load(client, ledger.store, ledger.scope)?,
+            ledger,
+            titles,
+            events,
+        })
+    }
+
+    /// Uploads, replaces or removes the source of `change` and records the
+    /// result. A source NotebookLM does not take goes into the failure
+    /// ledger and is returned, so that the rest can carry on. Only rejected
+    /// credentials, which would fail every upload alike, stop the run.
+    async fn apply(&mut self, change: &Change) -> Result<Option<Skipped>> {
+        let (client, store, scope) = (self.client, self.ledger.store, self.ledger.scope);
+        let mut source_id = change.source_id.clone();
+        match change.kind {
+            ChangeKind::Add | ChangeKind::Replace => {
+                let notebook_id = self.notebook_id(change.shard).await?;
+                let content = change.content.as_deref().unwrap_or_default();
+                let source = match client
+                    .add_text_source(&notebook_id, &change.title, content)
+                    .await
+                {
+                    Ok(source) => source,
+                    Err(e @ AppError::NotebookLM(ServiceError::Unauthorized { .. })) => {
+                        return Err(e)
+                    }
+                    Err(e) => {
+                        warn!("  ✗ Could not upload {}: {}", change.title, e.chain());
+                        let (class, http_status) = ledger::classify(&e);
+                        let failed = Skipped {
+                            bookmark_id: change.bookmark_id,
+                            link: change.link.clone(),
+                            title: change.title.clone(),
+                            last_update: change.last_update,
+                            reason: SkipReason::UploadFailed {
+                                class,
+                                http_status,
+                                error: e.chain(),
+                            },
+                        };
+                        self.ledger
+                            .record(std::slice::from_ref(&failed), Utc::now())?;
+                        return Ok(Some(failed));
+                    }
+                };
+                info!("  + {} as source {}", change.title, source.source_id.id);
+                self.events.emit(EventKind::SourceUploaded {
+                    bookmark_id: change.bookmark_id,
+                    title: change.title.clone(),
+                    notebook_id: notebook_id.clone(),
+                    source_id: source.source_id.id.clone(),
+                    bytes: content.len() as u64,
+                });
+                if let (ChangeKind::Replace, Some(old)) = (change.kind, &change.source_id) {
+                    // The new source is already in place, so a stale copy
+                    // left behind is worth a warning but not a failed run.
+                    if let Err(e) = client
+                        .delete_sources(&notebook_id, std::slice::from_ref(old))
+                        .await
+                    {
+                        warn!(
+                            "     Could not remove replaced source {}: {}",
+                            old,
+                            e.chain()
+                        );
+                    }
+                }
+                source_id = Some(source.source_id.id);
+            }
+            ChangeKind::Refresh => {}
+            ChangeKind::Remove => {
+                if let (Some(notebook_id), Some(old)) =
+                    (self.notebooks.existing(change.shard), &change.source_id)
+                {
+                    client
+                        .delete_sources(&notebook_id, std::slice::from_ref(old))
+                        .await?;
+                    info!("  - {} (source {})", change.title, old);
+                }
+                store.remove(scope, change.bookmark_id)?;
+                store.clear_failure(scope, change.bookmark_id)?;
+                return Ok(None);
+            }
+        }
+        store.clear_failure(scope, change.bookmark_id)?;
+        store.save(
+            scope,
+            &BookmarkRecord {
+                id: change.bookmark_id,
+                link: change.link.clone(),
+                title: change.title.clone(),
+                last_update: change.last_update,
+                content_hash: change.content_hash.clone(),
+                source_id,
+                shard: change.shard,
+                word_count: change.word_count,
+                synced_at: Utc::now(),
+            },
+        )?;
+        Ok(None)
+    }
+
+    async fn notebook_id(&mut self, shard: usize) -> Result<String> {
+        match self.notebooks.existing(shard) {
+            Some(notebook_id) => Ok(notebook_id),
+            None => {
+                let title = self.titles.title(shard, self.notebooks.count());
+                self.notebooks.create(shard, title).await
+            }
+        }
+    }
+}
+
+/// What the notebooks an [`Applier`] creates are called.
+enum Titles<'a> {
+    /// As in a plan made beforehand.
+    Planned(&'a [NotebookPlan]),
+    /// Numbered from the base title by the notebooks there are so far, as
+    /// when the run cannot know yet how many there will be. They are
+    /// renamed once it does.
+    Provisional(String),
+}
+
+impl Titles<'_> {
+    fn title(&self, shard: usize, count: usize) -> String {
+        match self {
+            Titles::Planned(planned) => planned
+                .iter()
+                .find(|notebook| notebook.shard == shard)
+                .map(|notebook| notebook.title.clone())
+                .expect("every shard receiving sources is in the plan"),
+            Titles::Provisional(base) => shard::title(base, shard, count.max(shard + 1)),
+        }
+    }
+}
+
+/// The notebooks a scope is split across. Notebooks are created on first
+/// use and renamed when the number of shards changes, so that every title
+/// reads `Research (n/total)`. A notebook deleted by hand in NotebookLM is
+/// not recreated; remove the state database to start over.
+struct Notebooks<'a> {
+    client: &'a NotebookLMClient,
+    store: &'a StateStore,
+    scope: &'a str,
+    known: HashMap<usize, NotebookShard>,
+}
+
+impl<'a> Notebooks<'a> {
+    fn load(
+        client: &'a NotebookLMClient,
+        store: &'a StateStore,
+        scope: &'a str,
+    ) -> Result<Notebooks<'a>> {
+        let known = store
+            .notebooks(scope)?
+            .into_iter()
+            .map(|notebook| (notebook.shard, notebook))
+            .collect();
+        Ok(Notebooks {
+            client,
+            store,
+            scope,
+            known,
+        })
+    }
+
+    fn existing(&self, shard: usize) -> Option<String> {
+        self.known
+            .get(&shard)
+            .map(|notebook| notebook.notebook_id.clone())
+    }
+
+    /// How many notebooks there are, going by the highest shard.
+    fn count(&self) -> usize {
+        self.known.keys().map(|shard| shard + 1).max().unwrap_or(0)
+    }
+
+    async fn create(&mut self, shard: usize, title: String) -> Result<String> {
+        let created = self.client.create_notebook(&title).await?;
+        info!(
+            "📚 Created notebook \"{}\" ({})",
+            title, created.notebook_id
+        );
+        let notebook = NotebookShard {
+            shard,
+            notebook_id: created.notebook_id,
+            title,
+        };
+        self.store.save_notebook(self.scope, &notebook)?;
+        let id = notebook.notebook_id.clone();
+        self.known.insert(shard, notebook);
+        Ok(id)
+    }
+
+    /// A failed rename only costs a misleading title, so it is not fatal.
+    async fn retitle(&mut self, planned: &[NotebookPlan]) -> Result<()> {
+        for planned in planned {
+            let Some(notebook) = self.known.get_mut(&planned.shard) else {
+                continue;
+            };
+            if notebook.title == planned.title {
+                continue;
+            }
+            match self
+                .client
+                .rename_notebook(&notebook.notebook_id, &planned.title)
+                .await
+            {
+                Ok(_) => {
+                    info!("📚 Renamed \"{}\" to \"{}\"", notebook.title, planned.title);
+                    notebook.title = planned.title.clone();
+                    self.store.save_notebook(self.scope, notebook)?;
+                }
+                Err(e) => warn!(
+                    "Could not rename notebook \"{}\" to \"{}\": {}",
+                    notebook.title,
+                    planned.title,
+                    e.chain()
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Picks bookmarks out of a listing that comes newest first, one at a time.
+/// Duplicates are skipped (the same raindrop listed under several tags, or
+/// the same page saved twice), then the `max_urls` cap applies. Capping
+/// last means the cap always keeps the most recent distinct pages.
+pub struct Selector {
+    max_urls: Option<usize>,
+    ids: HashSet<BookmarkId>,
+    links: HashSet<String>,
+    kept: usize,
+}
+
+pub enum Selection {
+    Selected,
+    /// The same raindrop again, listed under another tag.
+    Repeated,
+    Skipped(SkipReason),
+}
+
+impl Selector {
+    pub fn new(max_urls: Option<usize>) -> Selector {
+        Selector {
+            max_urls,
+            ids: HashSet::new(),
+            links: HashSet::new(),
+            kept: 0,
+        }
+    }
+
+    pub fn select(&mut self, bookmark: &Bookmark) -> Selection {
+        if !self.ids.insert(bookmark.id) {
+            return Selection::Repeated;
+        }
+        if !self.links.insert(normalize_link(&bookmark.link)) {
+            return Selection::Skipped(SkipReason::Duplicate);
+        }
+        match self.max_urls {
+            Some(max_urls) if self.kept >= max_urls => {
+                Selection::Skipped(SkipReason::OverLimit { max_urls })
+            }
+            _ => {
+                self.kept += 1;
+                Selection::Selected
+            }
+        }
+    }
+}
+
+fn normalize_link(link: &str) -> String {
+    link.trim().trim_end_matches('/').to_string()
+}
+
+/// `#a AND #b` or `#a OR #b`, for log lines and titles.
+pub fn describe_tags(tags: &[String], tag_match: TagMatch) -> String {
+    let separator = match tag_match {
+        TagMatch::All => " AND ",
+        TagMatch::Any => " OR ",
+    };
+    tags.iter()
+        .map(|tag| format!("#{}", tag))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+
+use raindrop_notebooklm_integration::config::{
+    ConfigBuilder, ConfigLayer, ConfigSource, DEFAULT_PROFILE,
+};
+
+fn env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn should_let_later_layers_override_earlier_ones_and_report_the_source() {
+    let project_file = PathBuf::from("/work/raindrop-notebooklm.toml");
+    let config = ConfigBuilder::new()
+        .with_layer(
+            ConfigSource::UserFile(PathBuf::from(
+                "/home/me/.config/raindrop-notebooklm/config.toml",
+            )),
+            ConfigLayer::from_toml("max_urls = 10\nraindrop_api_key = \"from-user-file\"").unwrap(),
+        )
+        .with_layer(
+            ConfigSource::ProjectFile(project_file.clone()),
+            ConfigLayer::from_toml("max_urls = 20").unwrap(),
+        )
+        .with_env(env(&[("RAINDROP_TOKEN", "from-env")]))
+        .unwrap()
+        .with_cli(ConfigLayer {
+            output_dir: Some(PathBuf::from("./cli-output")),
+            ..ConfigLayer::default()
+        })
+        .build(DEFAULT_PROFILE)
+        .unwrap();
+
+    assert_eq!(config.max_urls, Some(20));
+    assert_eq!(
+        config.source_of("max_urls"),
+        Some(&ConfigSource::ProjectFile(project_file))
+    );
+    assert_eq!(config.raindrop_api_key.as_deref(), Some("from-env"));
+    assert_eq!(
+        config.source_of("raindrop_api_key"),
+        Some(&ConfigSource::Env("RAINDROP_TOKEN"))
+    );
+    assert_eq!(config.output_dir, PathBuf::from("./cli-output"));
+    assert_eq!(config.source_of("output_dir"), Some(&ConfigSource::Cli));
+    assert_eq!(
+        config.source_of("raindrop_base_url"),
+        Some(&ConfigSource::Default)
+    );
+}
+
+#[test]
+fn should_redact_secrets_in_resolved_entries() {
+    let config = ConfigBuilder::new()
+        .with_env(env(&[
+            ("RAINDROP_TOKEN", "super-secret"),
+            ("NOTEBOOKLM_ACCESS_TOKEN", "also-secret"),
+        ]))
+        .unwrap()
+        .build(DEFAULT_PROFILE)
+        .unwrap();
+
+    let rendered = format!("{:?}", config.entries());
+
+    assert!(!rendered.contains("super-secret"));
+    assert!(!rendered.contains("also-secret"));
+}
+
+#[test]
+fn should_reject_unknown_keys_in_configuration_files() {
+    assert!(ConfigLayer::from_toml("max_url = 10").is_err());
+}
+
+#[test]
+fn should_read_the_notebooklm_access_token() {
+    let layer = ConfigLayer::from_toml("notebooklm_access_token = \"ya29.token\"").unwrap();
+
+    assert_eq!(layer.notebooklm_access_token.as_deref(), Some("ya29.token"));
+}
+
+#[test]
+fn should_reject_non_numeric_max_urls_from_environment() {
+    let result = ConfigBuilder::new().with_env(env(&[("RAINDROP_NOTEBOOKLM_MAX_URLS", "many")]));
+
+    assert!(result.is_err());
+}
+
+const PROFILES_TOML: &str = r#"
+max_urls = 10
+
+[profiles.personal]
+raindrop_api_key = "personal-token"
+notebook_title = "Personal research"
+
+[profiles.shared]
+raindrop_api_key = "shared-token"
+notebook_title = "Team research"
+"#;
+
+fn profiles_builder() -> ConfigBuilder {
+    ConfigBuilder::new().with_layer(
+        ConfigSource::ProjectFile(PathBuf::from("/work/raindrop-notebooklm.toml")),
+        ConfigLayer::from_toml(PROFILES_TOML).unwrap(),
+    )
+}
+
+#[test]
+fn should_apply_selected_profile_on_top_of_file_defaults() {
+    let config = profiles_builder().build("shared").unwrap();
+
+    assert_eq!(config.raindrop_api_key.as_deref(), Some("shared-token"));
+    assert_eq!(config.notebook_title.as_deref(), Some("Team research"));
+    assert_eq!(config.max_urls, Some(10));
+    assert_eq!(
+        config.source_of("raindrop_api_key"),
+        Some(&ConfigSource::Profile {
+            file: PathBuf::from("/work/raindrop-notebooklm.toml"),
+            name: "shared".to_string(),
+        })
+    );
+}
+
+#[test]
+fn should_give_each_profile_its_own_state_directory() {
+    let builder = profiles_builder();
+    let names = builder.profile_names();
+    let state_dirs: Vec<PathBuf> = names
+        .iter()
+        .map(|name| builder.clone().build(name).unwrap().state_dir)
+        .collect();
+
+    assert_eq!(names, vec!["personal".to_string(), "shared".to_string()]);
+    assert_eq!(
+        state_dirs,
+        vec![
+            PathBuf::from("./output/state/personal"),
+            PathBuf::from("./output/state/shared"),
+        ]
+    );
+}
+
+#[test]
+fn should_reject_unknown_profiles() {
+    assert!(profiles_builder().build("missing").is_err());
+}
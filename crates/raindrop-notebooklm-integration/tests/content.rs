@@ -0,0 +1,311 @@
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use mockito::Server;
+use raindrop_notebooklm_integration::content::{self, ContentFetcher};
+use raindrop_notebooklm_integration::error::{exit_code, AppError, ContentError};
+use raindrop_notebooklm_integration::ledger::{self, FailureClass};
+use serde::Deserialize;
+
+/// What a fixture in `tests/fixtures/content` must extract to. Main-text
+/// accuracy is checked by sentences that must survive and boilerplate that
+/// must not, so fixtures stay robust to harmless whitespace changes.
+#[derive(Debug, Deserialize)]
+struct Expected {
+    title: String,
+    byline: Option<String>,
+    published: Option<DateTime<Utc>>,
+    language: Option<String>,
+    min_words: usize,
+    contains: Vec<String>,
+    excludes: Vec<String>,
+}
+
+#[test]
+fn should_extract_main_text_and_metadata_from_every_fixture() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/content");
+    let mut fixtures = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no fixtures in {}", dir.display());
+
+    for fixture in fixtures {
+        let name = fixture.file_stem().unwrap().to_string_lossy().to_string();
+        let html = fs::read_to_string(&fixture).unwrap();
+        let expected: Expected =
+            serde_json::from_str(&fs::read_to_string(fixture.with_extension("json")).unwrap())
+                .unwrap();
+
+        let page = content::extract("https://example.com/page", &html);
+
+        assert_eq!(page.title, expected.title, "{}: title", name);
+        assert_eq!(page.byline, expected.byline, "{}: byline", name);
+        assert_eq!(page.published, expected.published, "{}: published", name);
+        assert_eq!(page.language, expected.language, "{}: language", name);
+        assert!(
+            page.word_count >= expected.min_words,
+            "{}: only {} words in:\n{}",
+            name,
+            page.word_count,
+            page.text
+        );
+        for sentence in &expected.contains {
+            assert!(
+                page.text.contains(sentence.as_str()),
+                "{}: missing {:?} in:\n{}",
+                name,
+                sentence,
+                page.text
+            );
+        }
+        for boilerplate in &expected.excludes {
+            assert!(
+                !page.text.contains(boilerplate.as_str()),
+                "{}: kept {:?} in:\n{}",
+                name,
+                boilerplate,
+                page.text
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn should_fetch_and_extract_an_html_page() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/article")
+        .with_header("content-type", "text/html; charset=utf-8")
+        .with_body(
+            "<html lang=\"en\"><head><title>Hello</title></head><body>\
+             <p>This paragraph is long enough to count as the article body.</p>\
+             </body></html>",
+        )
+        .create_async()
+        .await;
+
+    let page = ContentFetcher::new()
+        .fetch_content(&format!("{}/article", server.url()))
+        .await
+        .unwrap();
+
+    assert_eq!(page.title, "Hello");
+    assert_eq!(page.language.as_deref(), Some("en"));
+    assert_eq!(
+        page.text,
+        "This paragraph is long enough to count as the article body."
+    );
+    assert_eq!(page.markdown, page.text);
+    assert_eq!(page.word_count, 11);
+}
+
+#[tokio::test]
+async fn should_report_unreachable_pages_as_content_errors() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/gone")
+        .with_status(404)
+        .create_async()
+        .await;
+
+    let error = ContentFetcher::new()
+        .fetch_content(&format!("{}/gone", server.url()))
+        .await
+        .unwrap_err();
+
+    assert_eq!(error.exit_code(), exit_code::CONTENT);
+    assert!(error.chain().contains("HTTP 404"), "{}", error.chain());
+}
+
+#[tokio::test]
+async fn should_reject_responses_that_are_not_html() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/image")
+        .with_header("content-type", "image/png")
+        .with_body([0x89, b'P', b'N', b'G'])
+        .create_async()
+        .await;
+
+    let error = ContentFetcher::new()
+        .fetch_content(&format!("{}/image", server.url()))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        AppError::Content(ContentError::UnsupportedType { ref content_type, .. })
+            if content_type == "image/png"
+    ));
+}
+
+#[tokio::test]
+async fn should_decode_pages_in_their_declared_charset() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/latin1")
+        .with_header("content-type", "text/html; charset=\"ISO-8859-1\"")
+        .with_body(
+            b"<html><body><p>Caf\xe9 cr\xe8me, a long enough paragraph of text.</p></body></html>",
+        )
+        .create_async()
+        .await;
+
+    let page = ContentFetcher::new()
+        .fetch_content(&format!("{}/latin1", server.url()))
+        .await
+        .unwrap();
+
+    assert_eq!(page.text, "Café crème, a long enough paragraph of text.");
+}
+
+#[tokio::test]
+async fn should_decode_pages_in_the_charset_their_meta_tag_declares() {
+    let mut server = Server::new_async().await;
+    let heads = [
+        r#"<meta charset="Shift_JIS">"#,
+        r#"<meta http-equiv="Content-Type" content="text/html; charset=shift_jis">"#,
+    ];
+    for (index, head) in heads.iter().enumerate() {
+        let html = format!(
+            "<html><head>{}</head><body><p>日本語の段落です。抽出するのに十分な長さの本文があります。</p></body></html>",
+            head
+        );
+        server
+            .mock("GET", format!("/sjis/{}", index).as_str())
+            .with_header("content-type", "text/html")
+            .with_body(encoding_rs::SHIFT_JIS.encode(&html).0)
+            .create_async()
+            .await;
+    }
+
+    for index in 0..heads.len() {
+        let page = ContentFetcher::new()
+            .fetch_content(&format!("{}/sjis/{}", server.url(), index))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            page.text,
+            "日本語の段落です。抽出するのに十分な長さの本文があります。"
+        );
+    }
+}
+
+#[tokio::test]
+async fn should_stop_reading_pages_larger_than_the_size_limit() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/declared")
+        .with_header("content-type", "application/pdf")
+        .with_body(vec![b'x'; 4096])
+        .create_async()
+        .await;
+    // No Content-Length, so only reading the body can tell.
+    server
+        .mock("GET", "/streamed")
+        .with_header("content-type", "text/html")
+        .with_chunked_body(|writer| {
+            for _ in 0..64 {
+                writer.write_all(&[b'x'; 1024])?;
+            }
+            Ok(())
+        })
+        .create_async()
+        .await;
+    let fetcher = ContentFetcher::new().with_max_bytes(1024);
+
+    for path in ["/declared", "/streamed"] {
+        let error = fetcher
+            .fetch_content(&format!("{}{}", server.url(), path))
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(
+                error,
+                AppError::Content(ContentError::TooLarge { limit: 1024, .. })
+            ),
+            "{}: {}",
+            path,
+            error.chain()
+        );
+        assert_eq!(ledger::classify(&error), (FailureClass::TooLarge, None));
+    }
+}
+
+fn pdf_fixture(name: &str) -> Vec<u8> {
+    fs::read(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/pdf")
+            .join(name),
+    )
+    .unwrap()
+}
+
+#[test]
+fn should_extract_pdf_text_page_by_page_with_document_metadata() {
+    let page = content::extract_pdf(
+        "https://arxiv.org/pdf/1706.03762",
+        &pdf_fixture("paper.pdf"),
+    )
+    .unwrap();
+
+    assert_eq!(page.title, "Attention Is All You Need");
+    assert_eq!(page.byline.as_deref(), Some("Ashish Vaswani"));
+    assert_eq!(
+        page.published,
+        Some(Utc.with_ymd_and_hms(2017, 6, 12, 15, 30, 0).unwrap())
+    );
+    assert_eq!(page.language.as_deref(), Some("en-US"));
+    assert!(page
+        .text
+        .contains("We propose a new simple network architecture"));
+    assert!(page
+        .text
+        .contains("Experiments on two machine translation tasks"));
+    let first = page.markdown.find("## Page 1").unwrap();
+    let second = page.markdown.find("## Page 2").unwrap();
+    let experiments = page.markdown.find("Experiments on two").unwrap();
+    assert!(first < second && second < experiments, "{}", page.markdown);
+    assert_eq!(page.word_count, page.text.split_whitespace().count());
+}
+
+#[test]
+fn should_flag_scanned_pdfs_without_a_text_layer() {
+    let error = content::extract_pdf("https://example.com/scan.pdf", &pdf_fixture("scanned.pdf"))
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        AppError::Content(ContentError::NoTextLayer { pages: 1, .. })
+    ));
+    assert_eq!(error.exit_code(), exit_code::CONTENT);
+}
+
+#[tokio::test]
+async fn should_fetch_pdfs_served_as_pdf_or_as_generic_binary() {
+    let mut server = Server::new_async().await;
+    for (path, content_type) in [
+        ("/paper", "application/pdf"),
+        ("/bucket/paper.pdf", "application/octet-stream"),
+    ] {
+        server
+            .mock("GET", path)
+            .with_header("content-type", content_type)
+            .with_body(pdf_fixture("paper.pdf"))
+            .create_async()
+            .await;
+
+        let page = ContentFetcher::new()
+            .fetch_content(&format!("{}{}", server.url(), path))
+            .await
+            .unwrap();
+
+        assert_eq!(page.title, "Attention Is All You Need", "{}", content_type);
+    }
+}
@@ -0,0 +1,154 @@
+use std::path::PathBuf;
+
+use raindrop_notebooklm_integration::config::{ConfigBuilder, ConfigSource, DEFAULT_PROFILE};
+use raindrop_notebooklm_integration::credentials::{
+    Backend, CredentialKind, CredentialStore, Secret,
+};
+use raindrop_notebooklm_integration::error::{exit_code, CredentialError};
+
+fn credentials_file(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-credentials-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("credentials.enc")
+}
+
+fn store(path: &PathBuf, passphrase: &str) -> CredentialStore {
+    CredentialStore::file_only(path).with_passphrase(Secret::from(passphrase))
+}
+
+#[test]
+fn should_read_back_keys_from_the_encrypted_file() {
+    let path = credentials_file("round-trip");
+    let writer = store(&path, "correct horse");
+    let raindrop = CredentialKind::Raindrop.entry("work");
+    let notebooklm = CredentialKind::NotebookLM.entry("work");
+
+    let backend = writer
+        .set(&raindrop, &Secret::from("raindrop-key-123"), None)
+        .unwrap();
+    writer
+        .set(
+            &notebooklm,
+            &Secret::from("notebooklm-key-456"),
+            Some(Backend::File),
+        )
+        .unwrap();
+
+    assert_eq!(backend, Backend::File);
+    let reader = store(&path, "correct horse");
+    assert_eq!(&*reader.get(&raindrop).unwrap(), "raindrop-key-123");
+    assert_eq!(&*reader.get(&notebooklm).unwrap(), "notebooklm-key-456");
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(!text.contains("raindrop-key-123"));
+    assert!(!text.contains("work/raindrop"));
+}
+
+#[test]
+fn should_replace_the_file_whole_and_keep_it_private() {
+    let path = credentials_file("replace");
+    let writer = store(&path, "correct horse");
+
+    writer
+        .set("default/raindrop", &Secret::from("raindrop-key-123"), None)
+        .unwrap();
+    writer
+        .set(
+            "default/notebooklm",
+            &Secret::from("notebooklm-key-456"),
+            None,
+        )
+        .unwrap();
+
+    let files = std::fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(files, ["credentials.enc"]);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn should_reject_a_wrong_passphrase() {
+    let path = credentials_file("wrong-passphrase");
+    store(&path, "correct horse")
+        .set("default/raindrop", &Secret::from("raindrop-key-123"), None)
+        .unwrap();
+
+    let error = store(&path, "battery staple")
+        .get("default/raindrop")
+        .unwrap_err();
+
+    assert!(matches!(error, CredentialError::Decrypt { .. }));
+}
+
+#[test]
+fn should_report_a_missing_entry() {
+    let path = credentials_file("missing");
+    store(&path, "correct horse")
+        .set("default/raindrop", &Secret::from("raindrop-key-123"), None)
+        .unwrap();
+
+    let error = store(&path, "correct horse")
+        .get("default/notebooklm")
+        .unwrap_err();
+
+    assert!(matches!(error, CredentialError::NotFound(entry) if entry == "default/notebooklm"));
+}
+
+#[test]
+fn should_resolve_keyring_references_in_the_configuration() {
+    let path = credentials_file("resolve");
+    let credentials = store(&path, "correct horse");
+    credentials
+        .set(
+            &CredentialKind::Raindrop.entry(DEFAULT_PROFILE),
+            &Secret::from("raindrop-key-123"),
+            None,
+        )
+        .unwrap();
+    let env = vec![(
+        "RAINDROP_TOKEN".to_string(),
+        CredentialKind::Raindrop.reference(DEFAULT_PROFILE),
+    )];
+
+    let config = ConfigBuilder::new()
+        .with_env(env)
+        .unwrap()
+        .with_credentials(credentials)
+        .build(DEFAULT_PROFILE)
+        .unwrap();
+
+    assert_eq!(config.raindrop_api_key.as_deref(), Some("raindrop-key-123"));
+    assert_eq!(
+        config.source_of("raindrop_api_key"),
+        Some(&ConfigSource::Env("RAINDROP_TOKEN"))
+    );
+    assert!(!format!("{:?}", config).contains("raindrop-key-123"));
+}
+
+#[test]
+fn should_fail_as_a_configuration_error_when_a_reference_cannot_be_resolved() {
+    let env = vec![(
+        "NOTEBOOKLM_ACCESS_TOKEN".to_string(),
+        "keyring:default/notebooklm".to_string(),
+    )];
+
+    let error = ConfigBuilder::new()
+        .with_env(env)
+        .unwrap()
+        .with_credentials(store(&credentials_file("unresolved"), "correct horse"))
+        .build(DEFAULT_PROFILE)
+        .unwrap_err();
+
+    assert_eq!(error.exit_code(), exit_code::CONFIG);
+    assert!(error.chain().contains("default/notebooklm"));
+}
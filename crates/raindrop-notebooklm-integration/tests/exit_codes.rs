@@ -0,0 +1,63 @@
+use std::process::Command;
+
+use raindrop_notebooklm_integration::error::{
+    exit_code, AppError, ConfigError, ContentError, ServiceError,
+};
+
+#[test]
+fn should_map_each_failure_class_to_its_documented_exit_code() {
+    let cases = [
+        (
+            AppError::Raindrop(ServiceError::Unauthorized { status: 401 }),
+            exit_code::RAINDROP_UNAUTHORIZED,
+        ),
+        (
+            AppError::NotebookLM(ServiceError::Status {
+                status: 500,
+                body: String::new(),
+            }),
+            exit_code::NOTEBOOKLM_API_ERROR,
+        ),
+        (
+            AppError::Content(ContentError::PartialFailure {
+                failed: 2,
+                total: 10,
+            }),
+            exit_code::CONTENT,
+        ),
+        (
+            AppError::Config(ConfigError::Invalid("bad".to_string())),
+            exit_code::CONFIG,
+        ),
+    ];
+
+    cases
+        .iter()
+        .for_each(|(error, code)| assert_eq!(error.exit_code(), *code, "{:?}", error));
+}
+
+#[test]
+fn should_include_every_cause_in_the_error_chain() {
+    let error = AppError::Raindrop(ServiceError::Unauthorized { status: 401 });
+
+    assert_eq!(
+        error.chain(),
+        "Raindrop API error: credentials were rejected (HTTP 401)"
+    );
+}
+
+#[test]
+fn should_exit_with_config_code_when_raindrop_token_is_missing() {
+    let workdir = std::env::temp_dir().join("raindrop-notebooklm-exit-code-test");
+    std::fs::create_dir_all(&workdir).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_raindrop-notebooklm-integration"))
+        .args(["sync", "--tag", "research"])
+        .current_dir(&workdir)
+        .env_clear()
+        .env("HOME", &workdir)
+        .status()
+        .unwrap();
+
+    assert_eq!(status.code(), Some(i32::from(exit_code::CONFIG)));
+}
@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+
+use chrono::{TimeZone, Utc};
+use raindrop_notebooklm_integration::content::PageContent;
+use raindrop_notebooklm_integration::export::{self, BundleWriter, ExportedPage};
+use raindrop_notebooklm_integration::raindrop::{Bookmark, BookmarkId, CollectionId};
+
+fn export_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-export-test-{}-{}",
+        name,
+        std::process::id()
+    ))
+}
+
+fn exported(id: u64, title: &str, words: usize) -> ExportedPage {
+    let markdown = vec!["word"; words].join(" ");
+    ExportedPage {
+        bookmark: Bookmark {
+            id: BookmarkId(id),
+            title: title.to_string(),
+            link: format!("https://example.com/{}", id),
+            excerpt: String::new(),
+            note: String::new(),
+            tags: vec!["research".to_string(), "ai".to_string()],
+            created: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+            last_update: Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap(),
+            collection_id: CollectionId(42),
+            domain: "example.com".to_string(),
+        },
+        page: PageContent {
+            url: format!("https://example.com/{}", id),
+            title: title.to_string(),
+            byline: Some("Ada Lovelace".to_string()),
+            published: None,
+            language: Some("en".to_string()),
+            text: markdown.clone(),
+            markdown,
+            word_count: words,
+        },
+        fetched_at: Utc.with_ymd_and_hms(2024, 3, 4, 5, 6, 7).unwrap(),
+    }
+}
+
+#[test]
+fn should_write_front_matter_with_source_url_fetch_time_and_tags() {
+    let front_matter = export::front_matter(&exported(7, "Rust: \"fearless\" concurrency", 3));
+
+    assert_eq!(
+        front_matter,
+        "---\n\
+         title: \"Rust: \\\"fearless\\\" concurrency\"\n\
+         source_url: \"https://example.com/7\"\n\
+         fetched_at: 2024-03-04T05:06:07Z\n\
+         tags: [\"research\", \"ai\"]\n\
+         raindrop_id: 7\n\
+         word_count: 3\n\
+         byline: \"Ada Lovelace\"\n\
+         language: \"en\"\n\
+         ---\n\n"
+    );
+}
+
+#[test]
+fn should_write_one_file_per_bookmark_digests_and_index() {
+    let dir = export_dir("bundle");
+    let pages = vec![
+        exported(1, "First post", 60),
+        exported(2, "Second post", 60),
+        exported(3, "", 10),
+    ];
+
+    let bundle = export::write_bundle(&dir, "#research", &pages, 100).unwrap();
+
+    let names = bundle
+        .pages
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            "001-first-post.md",
+            "002-second-post.md",
+            "003-bookmark-3.md"
+        ]
+    );
+    let first = std::fs::read_to_string(&bundle.pages[0]).unwrap();
+    assert!(first.starts_with("---\ntitle: \"First post\"\n"));
+
+    // 60 + 60 words do not fit in 100, so the second post starts a new part.
+    assert_eq!(bundle.digests.len(), 2);
+    let digest = std::fs::read_to_string(&bundle.digests[1]).unwrap();
+    assert!(digest.starts_with("# Raindrop digest: #research (2/2)\n\n## Second post\n"));
+    assert!(digest.contains("## \n\nSource: <https://example.com/3>"));
+
+    let index = std::fs::read_to_string(&bundle.index).unwrap();
+    assert!(index.contains("- [digest-01.md](digest-01.md) ("));
+    assert!(index.contains(
+        "| 2 | Second post | <https://example.com/2> | 60 | [bookmarks/002-second-post.md](bookmarks/002-second-post.md) |"
+    ));
+}
+
+#[test]
+fn should_number_pages_in_selection_order_whatever_order_they_arrive() {
+    let dir = export_dir("arrival");
+    let mut writer = BundleWriter::create(&dir).unwrap();
+    // The third bookmark of the selection could not be fetched.
+    writer.add(3, &exported(4, "Fourth", 5)).unwrap();
+    writer.add(0, &exported(1, "First", 5)).unwrap();
+    writer.add(1, &exported(2, "Second", 5)).unwrap();
+
+    let bundle = writer.finish("#research", 100).unwrap();
+
+    let names = bundle
+        .pages
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["001-first.md", "002-second.md", "003-fourth.md"]);
+    let page = std::fs::read_to_string(&bundle.pages[2]).unwrap();
+    assert!(page.contains("raindrop_id: 4\n"));
+    let digest = std::fs::read_to_string(&bundle.digests[0]).unwrap();
+    let first = digest.find("## First").unwrap();
+    let fourth = digest.find("## Fourth").unwrap();
+    assert!(first < fourth);
+    assert!(digest.ends_with("word word word word word\n"));
+    let files = std::fs::read_dir(&dir).unwrap().count();
+    assert_eq!(files, 3);
+}
+
+#[test]
+fn should_split_oversized_bookmark_between_paragraphs() {
+    let dir = export_dir("oversized");
+    let mut page = exported(1, "Long read", 0);
+    page.page.markdown = vec![vec!["word"; 40].join(" "); 5].join("\n\n");
+    page.page.word_count = 200;
+
+    let bundle = export::write_bundle(&dir, "#research", &[page], 100).unwrap();
+
+    assert_eq!(bundle.digests.len(), 3);
+    bundle.digests.iter().for_each(|path| {
+        let digest = std::fs::read_to_string(path).unwrap();
+        let body = digest.split_once("\n\n").unwrap().1;
+        assert!(body.split_whitespace().count() <= 100);
+    });
+}
+
+#[test]
+fn should_replace_previous_bundle() {
+    let dir = export_dir("replace");
+    export::write_bundle(&dir, "#research", &[exported(1, "Old", 5)], 100).unwrap();
+
+    let bundle = export::write_bundle(&dir, "#research", &[exported(2, "New", 5)], 100).unwrap();
+
+    let files = std::fs::read_dir(dir.join("bookmarks")).unwrap().count();
+    assert_eq!(files, 1);
+    assert!(bundle.pages[0].ends_with("001-new.md"));
+}
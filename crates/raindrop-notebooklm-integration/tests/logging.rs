@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+
+use chrono::{TimeZone, Utc};
+use log::{Level, LevelFilter, Metadata, Record};
+use raindrop_notebooklm_integration::logging::{self, LogFormat, RotatingFile};
+use serde_json::Value;
+
+fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-logging-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn metadata(level: Level, target: &str) -> Metadata<'_> {
+    Metadata::builder().level(level).target(target).build()
+}
+
+fn render(format: LogFormat, emoji: bool) -> String {
+    logging::render(
+        &Record::builder()
+            .level(Level::Warn)
+            .target("raindrop_notebooklm_integration::sync")
+            .args(format_args!("⚠️ 2 pages were skipped"))
+            .build(),
+        format,
+        emoji,
+        Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, 0).unwrap(),
+    )
+}
+
+#[test]
+fn should_let_rust_log_override_the_default_level() {
+    let default = logging::level_filter(LevelFilter::Warn, None);
+    let overridden = logging::level_filter(
+        LevelFilter::Warn,
+        Some("raindrop_notebooklm_integration::sync=debug"),
+    );
+
+    assert!(!default.enabled(&metadata(Level::Info, "raindrop_notebooklm_integration")));
+    assert!(default.enabled(&metadata(Level::Warn, "reqwest")));
+    assert!(overridden.enabled(&metadata(
+        Level::Debug,
+        "raindrop_notebooklm_integration::sync"
+    )));
+    assert!(!overridden.enabled(&metadata(Level::Warn, "reqwest")));
+}
+
+#[test]
+fn should_ignore_an_empty_rust_log() {
+    let filter = logging::level_filter(LevelFilter::Info, Some(" "));
+
+    assert!(filter.enabled(&metadata(Level::Info, "raindrop_notebooklm_integration")));
+}
+
+#[test]
+fn should_render_pretty_and_json_lines() {
+    assert_eq!(
+        render(LogFormat::Pretty, true),
+        "[2024-05-03T12:00:00Z WARN  raindrop_notebooklm_integration::sync] ⚠️ 2 pages were skipped"
+    );
+
+    let json = serde_json::from_str::<Value>(&render(LogFormat::Json, true)).unwrap();
+    assert_eq!(json["timestamp"], "2024-05-03T12:00:00Z");
+    assert_eq!(json["level"], "WARN");
+    assert_eq!(json["target"], "raindrop_notebooklm_integration::sync");
+    assert_eq!(json["message"], "⚠️ 2 pages were skipped");
+}
+
+#[test]
+fn should_leave_out_emoji_when_asked() {
+    assert!(render(LogFormat::Pretty, false).ends_with("] 2 pages were skipped"));
+    assert_eq!(
+        logging::strip_emoji("  ✓ Article (120 words)"),
+        "  Article (120 words)"
+    );
+    assert_eq!(
+        logging::strip_emoji("🗂️ 1 new, 0 changed"),
+        "1 new, 0 changed"
+    );
+    assert_eq!(
+        logging::strip_emoji("Raindrop → NotebookLM"),
+        "Raindrop → NotebookLM"
+    );
+}
+
+#[test]
+fn should_rotate_the_log_file_once_it_is_full() {
+    let dir = log_dir("rotate");
+    let path = dir.join("logs").join("sync.log");
+    let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+    ["first", "second", "third", "fourth"]
+        .iter()
+        .for_each(|line| file.write_line(line).unwrap());
+
+    let read = |name: &str| std::fs::read_to_string(dir.join("logs").join(name)).unwrap();
+    assert_eq!(read("sync.log"), "fourth\n");
+    assert_eq!(read("sync.log.1"), "third\n");
+    assert_eq!(read("sync.log.2"), "second\n");
+    assert!(!dir.join("logs").join("sync.log.3").exists());
+}
+
+#[test]
+fn should_append_to_an_existing_log_file() {
+    let dir = log_dir("append");
+    let path = dir.join("sync.log");
+
+    RotatingFile::open(&path, 100, 2)
+        .unwrap()
+        .write_line("first run")
+        .unwrap();
+    RotatingFile::open(&path, 100, 2)
+        .unwrap()
+        .write_line("second run")
+        .unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "first run\nsecond run\n"
+    );
+}
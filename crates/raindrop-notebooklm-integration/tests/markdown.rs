@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+
+use raindrop_notebooklm_integration::content;
+
+/// Golden files: every `tests/fixtures/markdown/<name>.html` must convert
+/// to exactly `<name>.md`.
+#[test]
+fn should_convert_every_fixture_to_its_golden_markdown() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/markdown");
+    let mut fixtures = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no fixtures in {}", dir.display());
+
+    for fixture in fixtures {
+        let html = fs::read_to_string(&fixture).unwrap();
+        let golden = fs::read_to_string(fixture.with_extension("md")).unwrap();
+
+        let markdown = content::html_to_markdown("https://example.com/docs/", &html);
+
+        assert_eq!(
+            markdown,
+            golden.trim_end(),
+            "{} does not match its golden file",
+            fixture.display()
+        );
+    }
+}
+
+#[test]
+fn should_render_the_extracted_article_as_markdown() {
+    let html = r#"<html><body>
+        <nav><a href="/">Home</a></nav>
+        <article>
+          <h2>Why it matters</h2>
+          <p>Structured text is easier to ground on, because headings, lists and tables survive.</p>
+          <ul><li>First reason</li><li>Second reason</li></ul>
+          <p>See the <a href="/spec">specification</a> for details, examples and caveats.</p>
+        </article>
+        <footer>All rights reserved</footer>
+    </body></html>"#;
+
+    let page = content::extract("https://example.com/blog/post", html);
+
+    assert_eq!(
+        page.markdown,
+        "## Why it matters\n\n\
+         Structured text is easier to ground on, because headings, lists and tables survive.\n\n\
+         - First reason\n- Second reason\n\n\
+         See the [specification](https://example.com/spec) for details, examples and caveats."
+    );
+}
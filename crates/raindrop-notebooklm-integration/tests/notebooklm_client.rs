@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use mockito::{Matcher, Server};
+use raindrop_notebooklm_integration::error::{exit_code, AppError, ServiceError};
+use raindrop_notebooklm_integration::notebooklm::{NotebookLMClient, SourceContent, SourceStatus};
+use serde_json::json;
+
+const NOTEBOOKS: &str = "/v1alpha/projects/123456/locations/global/notebooks";
+
+fn client_for(server: &Server) -> NotebookLMClient {
+    NotebookLMClient::new("notebooklm-token", "123456")
+        .with_endpoint(server.url())
+        .with_polling(Duration::from_millis(10), Duration::from_secs(5))
+}
+
+fn source(id: &str, title: &str, status: &str) -> serde_json::Value {
+    json!({
+        "name": format!("projects/123456/locations/global/notebooks/nb-1/sources/{}", id),
+        "sourceId": { "id": id },
+        "title": title,
+        "settings": { "status": status }
+    })
+}
+
+#[tokio::test]
+async fn should_create_notebook_with_bearer_token() {
+    let mut server = Server::new_async().await;
+    let create = server
+        .mock("POST", NOTEBOOKS)
+        .match_header("authorization", "Bearer notebooklm-token")
+        .match_body(Matcher::Json(json!({ "title": "Research" })))
+        .with_body(
+            json!({
+                "name": "projects/123456/locations/global/notebooks/nb-1",
+                "notebookId": "nb-1",
+                "title": "Research"
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let notebook = client_for(&server)
+        .create_notebook("Research")
+        .await
+        .unwrap();
+
+    create.assert_async().await;
+    assert_eq!(notebook.notebook_id, "nb-1");
+    assert_eq!(notebook.title, "Research");
+}
+
+#[tokio::test]
+async fn should_add_web_and_text_sources_in_one_batch() {
+    let mut server = Server::new_async().await;
+    let batch = server
+        .mock(
+            "POST",
+            format!("{}/nb-1/sources:batchCreate", NOTEBOOKS).as_str(),
+        )
+        .match_body(Matcher::Json(json!({
+            "userContents": [
+                { "webContent": { "url": "https://example.com/a", "sourceName": "A" } },
+                { "textContent": { "sourceName": "B", "content": "# B\n\nBody" } }
+            ]
+        })))
+        .with_body(
+            json!({
+                "sources": [
+                    source("src-a", "A", "SOURCE_STATUS_PENDING"),
+                    source("src-b", "B", "SOURCE_STATUS_COMPLETE")
+                ]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let sources = client_for(&server)
+        .add_sources(
+            "nb-1",
+            &[
+                SourceContent::web("https://example.com/a", "A"),
+                SourceContent::text("B", "# B\n\nBody"),
+            ],
+        )
+        .await
+        .unwrap();
+
+    batch.assert_async().await;
+    assert_eq!(sources[0].source_id.id, "src-a");
+    assert_eq!(sources[0].settings.status, SourceStatus::Pending);
+    assert_eq!(sources[1].settings.status, SourceStatus::Complete);
+}
+
+#[tokio::test]
+async fn should_poll_long_running_operation_until_done() {
+    let mut server = Server::new_async().await;
+    let operation = "projects/123456/locations/global/operations/op-7";
+    server
+        .mock(
+            "POST",
+            format!("{}/nb-1/sources:batchCreate", NOTEBOOKS).as_str(),
+        )
+        .with_body(json!({ "name": operation }).to_string())
+        .create_async()
+        .await;
+    let poll = server
+        .mock("GET", format!("/v1alpha/{}", operation).as_str())
+        .with_body(
+            json!({
+                "name": operation,
+                "done": true,
+                "response": { "sources": [source("src-b", "B", "SOURCE_STATUS_COMPLETE")] }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let source = client_for(&server)
+        .add_text_source("nb-1", "B", "Body")
+        .await
+        .unwrap();
+
+    poll.assert_async().await;
+    assert_eq!(source.source_id.id, "src-b");
+}
+
+#[tokio::test]
+async fn should_report_failed_operation() {
+    let mut server = Server::new_async().await;
+    let operation = "projects/123456/locations/global/operations/op-8";
+    server
+        .mock("POST", NOTEBOOKS)
+        .with_body(
+            json!({
+                "name": operation,
+                "done": true,
+                "error": { "code": 9, "message": "quota exceeded" }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let error = client_for(&server)
+        .create_notebook("Research")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        &error,
+        AppError::NotebookLM(ServiceError::OperationFailed { code: 9, .. })
+    ));
+    assert_eq!(error.exit_code(), exit_code::NOTEBOOKLM_API_ERROR);
+}
+
+#[tokio::test]
+async fn should_delete_sources_by_resource_name() {
+    let mut server = Server::new_async().await;
+    let delete = server
+        .mock(
+            "POST",
+            format!("{}/nb-1/sources:batchDelete", NOTEBOOKS).as_str(),
+        )
+        .match_body(Matcher::Json(json!({
+            "names": ["projects/123456/locations/global/notebooks/nb-1/sources/src-a"]
+        })))
+        .with_body("{}")
+        .create_async()
+        .await;
+
+    client_for(&server)
+        .delete_sources("nb-1", &["src-a".to_string()])
+        .await
+        .unwrap();
+
+    delete.assert_async().await;
+}
+
+#[tokio::test]
+async fn should_list_sources_of_notebook() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", format!("{}/nb-1", NOTEBOOKS).as_str())
+        .with_body(
+            json!({
+                "notebookId": "nb-1",
+                "title": "Research",
+                "sources": [source("src-a", "A", "SOURCE_STATUS_FAILED")]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let sources = client_for(&server).list_sources("nb-1").await.unwrap();
+
+    assert_eq!(sources.len(), 1);
+    assert_eq!(sources[0].settings.status, SourceStatus::Failed);
+}
+
+#[tokio::test]
+async fn should_map_rejected_token_to_unauthorized_exit_code() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("POST", NOTEBOOKS)
+        .with_status(401)
+        .create_async()
+        .await;
+
+    let error = client_for(&server)
+        .create_notebook("Research")
+        .await
+        .unwrap_err();
+
+    assert_eq!(error.exit_code(), exit_code::NOTEBOOKLM_UNAUTHORIZED);
+}
+
+/// A token command that prints `token-1`, `token-2`, ... on successive runs.
+#[cfg(unix)]
+fn counting_token_command(name: &str) -> String {
+    let counter = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-token-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&counter);
+    format!(
+        "n=$(($(cat '{0}' 2>/dev/null || echo 0) + 1)); echo $n > '{0}'; echo token-$n",
+        counter.display()
+    )
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_run_the_token_command_again_when_its_token_is_rejected() {
+    let mut server = Server::new_async().await;
+    let rejected = server
+        .mock("GET", format!("{}/nb-1", NOTEBOOKS).as_str())
+        .match_header("authorization", "Bearer token-1")
+        .with_status(401)
+        .expect(1)
+        .create_async()
+        .await;
+    let accepted = server
+        .mock("GET", format!("{}/nb-1", NOTEBOOKS).as_str())
+        .match_header("authorization", "Bearer token-2")
+        .with_body(json!({ "notebookId": "nb-1", "sources": [] }).to_string())
+        .expect(2)
+        .create_async()
+        .await;
+    let client = NotebookLMClient::new(String::new(), "123456")
+        .with_token_command(counting_token_command("renew"))
+        .with_endpoint(server.url());
+
+    client.list_sources("nb-1").await.unwrap();
+    client.list_sources("nb-1").await.unwrap();
+
+    rejected.assert_async().await;
+    accepted.assert_async().await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn should_treat_a_failing_token_command_as_rejected_credentials() {
+    let server = Server::new_async().await;
+    let client = NotebookLMClient::new(String::new(), "123456")
+        .with_token_command("echo 'not logged in' >&2; exit 1")
+        .with_endpoint(server.url());
+
+    let error = client.list_sources("nb-1").await.unwrap_err();
+
+    assert_eq!(error.exit_code(), exit_code::NOTEBOOKLM_UNAUTHORIZED);
+    assert!(error.chain().contains("not logged in"), "{}", error.chain());
+}
+
+#[test]
+fn should_link_to_notebooks_in_the_web_app() {
+    let client = NotebookLMClient::new("notebooklm-token", "123456").with_location("eu");
+
+    assert_eq!(
+        client.notebook_url("nb-1"),
+        "https://notebooklm.cloud.google.com/eu/notebook/nb-1?project=123456"
+    );
+}
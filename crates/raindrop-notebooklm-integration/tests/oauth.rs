@@ -0,0 +1,274 @@
+use std::time::Duration;
+
+use mockito::{Matcher, Server};
+use raindrop_notebooklm_integration::credentials::{CredentialStore, Secret};
+use raindrop_notebooklm_integration::error::exit_code;
+use raindrop_notebooklm_integration::oauth::{self, Loopback, OAuthClient, OAuthSession, Tokens};
+use raindrop_notebooklm_integration::raindrop::{CollectionId, RaindropClient, TagMatch};
+use raindrop_notebooklm_integration::redact::{self, REDACTED};
+use reqwest::Url;
+use serde_json::json;
+
+fn credentials(name: &str) -> CredentialStore {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-oauth-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    CredentialStore::file_only(dir.join("credentials.enc"))
+        .with_passphrase(Secret::from("correct horse"))
+}
+
+fn oauth_client(server: &Server) -> OAuthClient {
+    OAuthClient::new("app-id", "app-secret-0123").with_oauth_url(format!("{}/oauth", server.url()))
+}
+
+fn tokens(access: &str, refresh: &str) -> Tokens {
+    Tokens {
+        access_token: Secret::from(access),
+        refresh_token: Secret::from(refresh),
+        expires_at: None,
+    }
+}
+
+/// Follows `url` like a browser would, redirects included.
+fn browse(url: &str) {
+    let url = url.to_string();
+    tokio::spawn(async move {
+        let _ = reqwest::get(url).await;
+    });
+}
+
+#[tokio::test]
+async fn should_sign_in_through_the_loopback_redirect() {
+    let mut server = Server::new_async().await;
+    // The authorization server approves at once and sends the browser back
+    // with a code and the state it was given.
+    let authorize = server
+        .mock("GET", "/oauth/authorize")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("client_id".into(), "app-id".into()),
+            Matcher::UrlEncoded("response_type".into(), "code".into()),
+        ]))
+        .with_status(302)
+        .with_header_from_request("location", |request| {
+            let url = Url::parse(&format!("http://mock{}", request.path_and_query())).unwrap();
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap()
+            };
+            format!(
+                "{}?code=auth-code-1&state={}",
+                param("redirect_uri"),
+                param("state")
+            )
+        })
+        .create_async()
+        .await;
+    let exchange = server
+        .mock("POST", "/oauth/access_token")
+        .match_body(Matcher::PartialJson(json!({
+            "grant_type": "authorization_code",
+            "code": "auth-code-1",
+            "client_id": "app-id",
+            "client_secret": "app-secret-0123",
+        })))
+        .with_body(
+            json!({
+                "access_token": "access-1",
+                "refresh_token": "refresh-1",
+                "expires_in": 1209599,
+                "token_type": "Bearer"
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let loopback = Loopback::bind(0).await.unwrap();
+    let redirect_uri = loopback.redirect_uri().to_string();
+
+    let tokens = oauth::authorize(&oauth_client(&server), loopback, browse)
+        .await
+        .unwrap();
+
+    authorize.assert_async().await;
+    exchange.assert_async().await;
+    assert!(redirect_uri.starts_with("http://127.0.0.1:"));
+    assert_eq!(&*tokens.access_token, "access-1");
+    assert_eq!(&*tokens.refresh_token, "refresh-1");
+    assert!(tokens.expires_at.is_some());
+}
+
+#[tokio::test]
+async fn should_turn_away_redirects_that_do_not_belong_to_the_login_and_keep_waiting() {
+    let loopback = Loopback::bind(0).await.unwrap();
+    let redirect_uri = loopback.redirect_uri().to_string();
+    let mut waiting = tokio::spawn(async move { loopback.wait_for_code("expected-state").await });
+
+    for query in [
+        "code=auth-code-1&state=forged",
+        "code=auth-code-1",
+        "error=access_denied&state=forged",
+        "state=expected-state",
+    ] {
+        let response = reqwest::get(format!("{}?{}", redirect_uri, query))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400, "{}", query);
+    }
+    // None of those ended the login; the real redirect still can.
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), &mut waiting)
+            .await
+            .is_err()
+    );
+    browse(&format!(
+        "{}?code=auth-code-2&state=expected-state",
+        redirect_uri
+    ));
+
+    let code = tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .expect("the redirect was not served")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(&*code, "auth-code-2");
+}
+
+#[tokio::test]
+async fn should_report_a_refused_authorization() {
+    let loopback = Loopback::bind(0).await.unwrap();
+    browse(&format!(
+        "{}?error=access_denied&state=s",
+        loopback.redirect_uri()
+    ));
+
+    let error = loopback.wait_for_code("s").await.unwrap_err();
+
+    assert!(error.chain().contains("access_denied"));
+}
+
+#[tokio::test]
+async fn should_not_let_an_idle_browser_connection_hold_up_the_redirect() {
+    let loopback = Loopback::bind(0).await.unwrap();
+    let address = Url::parse(loopback.redirect_uri()).unwrap();
+    // Browsers open connections ahead of time and may never use them.
+    let _idle =
+        tokio::net::TcpStream::connect((address.host_str().unwrap(), address.port().unwrap()))
+            .await
+            .unwrap();
+    browse(&format!(
+        "{}?code=auth-code-1&state=s",
+        loopback.redirect_uri()
+    ));
+
+    let code = tokio::time::timeout(Duration::from_secs(5), loopback.wait_for_code("s"))
+        .await
+        .expect("the redirect was not served")
+        .unwrap();
+
+    assert_eq!(&*code, "auth-code-1");
+}
+
+#[tokio::test]
+async fn should_refresh_a_rejected_token_mid_sync_and_keep_the_new_one() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_body(json!({ "result": true, "user": { "_id": 1, "fullName": "Ada" } }).to_string())
+        .create_async()
+        .await;
+    // The listing turns the old token down, as once it has expired.
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(Matcher::Any)
+        .match_header("authorization", "Bearer access-old")
+        .with_status(401)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(Matcher::Any)
+        .match_header("authorization", "Bearer access-new")
+        .with_body(json!({ "result": true, "count": 0, "items": [] }).to_string())
+        .create_async()
+        .await;
+    let refresh = server
+        .mock("POST", "/oauth/access_token")
+        .match_body(Matcher::PartialJson(json!({
+            "grant_type": "refresh_token",
+            "refresh_token": "refresh-old",
+        })))
+        .with_body(
+            json!({
+                "access_token": "access-new",
+                "refresh_token": "refresh-new",
+                "expires_in": 1209599
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let store = credentials("refresh");
+    let session = OAuthSession::persisted(
+        oauth_client(&server),
+        tokens("access-old", "refresh-old"),
+        store.clone(),
+        "default",
+    );
+    let client = RaindropClient::with_oauth(session.clone()).with_base_url(server.url());
+
+    client.authenticate().await.unwrap();
+    let (found, mut received) = tokio::sync::mpsc::channel(8);
+    client
+        .send_bookmarks_by_tags(
+            CollectionId::ALL,
+            &["research".to_string()],
+            TagMatch::All,
+            &found,
+        )
+        .await
+        .unwrap();
+    drop(found);
+
+    assert!(received.recv().await.is_none());
+    refresh.assert_async().await;
+    assert_eq!(&*session.tokens().await.access_token, "access-new");
+    let stored = Tokens::load(&store, "default").unwrap().unwrap();
+    assert_eq!(&*stored.access_token, "access-new");
+    assert_eq!(&*stored.refresh_token, "refresh-new");
+    // Lines logged before the refresh may still quote the replaced tokens.
+    assert_eq!(
+        redact::redact("access-old refresh-old access-new refresh-new"),
+        [REDACTED; 4].join(" ")
+    );
+}
+
+#[tokio::test]
+async fn should_not_retry_when_the_refreshed_token_is_rejected_too() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_status(401)
+        .expect(2)
+        .create_async()
+        .await;
+    server
+        .mock("POST", "/oauth/access_token")
+        .with_body(json!({ "access_token": "access-new" }).to_string())
+        .create_async()
+        .await;
+    let session = OAuthSession::new(oauth_client(&server), tokens("access-old", "refresh-old"));
+    let client = RaindropClient::with_oauth(session.clone()).with_base_url(server.url());
+
+    let error = client.authenticate().await.unwrap_err();
+
+    assert_eq!(error.exit_code(), exit_code::RAINDROP_UNAUTHORIZED);
+    // Raindrop kept the refresh token valid by not sending a new one.
+    assert_eq!(&*session.tokens().await.refresh_token, "refresh-old");
+}
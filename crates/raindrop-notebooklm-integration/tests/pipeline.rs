@@ -0,0 +1,143 @@
+mod support;
+
+use std::time::{Duration, Instant};
+
+use raindrop_notebooklm_integration::content::ContentFetcher;
+use raindrop_notebooklm_integration::error::{AppError, ContentError, Result};
+use raindrop_notebooklm_integration::pipeline::{FetchLimits, Pipeline};
+use support::SlowServer;
+
+async fn fetch_all(limits: FetchLimits, urls: Vec<String>) -> Vec<(usize, Result<String>)> {
+    let mut results =
+        Pipeline::new(ContentFetcher::new(), limits).run(urls.into_iter().enumerate().collect());
+    let mut collected = Vec::new();
+    while let Some((index, result, _)) = results.recv().await {
+        collected.push((index, result.map(|page| page.title)));
+    }
+    collected.sort_by_key(|(index, _)| *index);
+    collected
+}
+
+fn limits(concurrency: usize, per_host: usize, host_delay: Duration) -> FetchLimits {
+    FetchLimits {
+        concurrency,
+        per_host,
+        host_delay,
+    }
+}
+
+#[tokio::test]
+async fn should_yield_every_result_with_its_key() {
+    let server = SlowServer::start(Duration::ZERO);
+    let urls = vec![server.url("/a"), server.url("/missing"), server.url("/b")];
+
+    let results = fetch_all(limits(4, 4, Duration::ZERO), urls).await;
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].1.as_deref().unwrap(), "/a");
+    assert!(matches!(
+        results[1].1,
+        Err(AppError::Content(ContentError::Fetch { .. }))
+    ));
+    assert_eq!(results[2].1.as_deref().unwrap(), "/b");
+}
+
+#[tokio::test]
+async fn should_not_exceed_the_global_concurrency_limit() {
+    let server = SlowServer::start(Duration::from_millis(100));
+    let urls = (0..12).map(|n| server.url(&format!("/{}", n))).collect();
+
+    let results = fetch_all(limits(3, 100, Duration::ZERO), urls).await;
+
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    assert_eq!(server.peak(), 3);
+}
+
+#[tokio::test]
+async fn should_not_exceed_the_per_host_limit() {
+    let server = SlowServer::start(Duration::from_millis(100));
+    let urls = (0..8).map(|n| server.url(&format!("/{}", n))).collect();
+
+    fetch_all(limits(8, 2, Duration::ZERO), urls).await;
+
+    assert_eq!(server.served(), 8);
+    assert_eq!(server.peak(), 2);
+}
+
+#[tokio::test]
+async fn should_space_requests_to_the_same_host() {
+    let server = SlowServer::start(Duration::ZERO);
+    let urls = (0..4).map(|n| server.url(&format!("/{}", n))).collect();
+    let started = Instant::now();
+
+    fetch_all(limits(4, 4, Duration::from_millis(100)), urls).await;
+
+    // The first request goes out at once, each later one 100ms after the
+    // one before.
+    assert!(started.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn should_time_each_download_without_the_wait_for_its_host() {
+    let server = SlowServer::start(Duration::from_millis(100));
+    let urls = vec![server.url("/a"), server.url("/b")];
+
+    let mut results = Pipeline::new(
+        ContentFetcher::new(),
+        limits(2, 1, Duration::from_millis(300)),
+    )
+    .run(urls.into_iter().enumerate().collect());
+
+    while let Some((_, result, transfer)) = results.recv().await {
+        assert!(result.is_ok());
+        assert!(transfer.elapsed >= Duration::from_millis(100));
+        assert!(transfer.elapsed < Duration::from_millis(300));
+        assert!(transfer.bytes > 0);
+    }
+}
+
+#[tokio::test]
+async fn should_not_let_urls_waiting_for_their_host_hold_up_other_hosts() {
+    let server = SlowServer::start(Duration::ZERO);
+    // The same server under a second host name.
+    let other_host = server.url.replace("127.0.0.1", "localhost");
+    let urls = vec![
+        (0, server.url("/a")),
+        (1, server.url("/b")),
+        (2, server.url("/c")),
+        (3, format!("{}/d", other_host)),
+    ];
+    let started = Instant::now();
+
+    let mut results = Pipeline::new(
+        ContentFetcher::new(),
+        limits(2, 1, Duration::from_millis(300)),
+    )
+    .run(urls);
+
+    let mut finished = Vec::new();
+    while let Some((index, result, _)) = results.recv().await {
+        assert!(result.is_ok());
+        finished.push((index, started.elapsed()));
+    }
+    let (_, other_finished) = finished.iter().find(|(index, _)| *index == 3).unwrap();
+    assert!(*other_finished < Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn should_fetch_urls_queued_while_others_are_downloading() {
+    let server = SlowServer::start(Duration::from_millis(50));
+    let (queue, queued) = tokio::sync::mpsc::channel(1);
+    let mut results =
+        Pipeline::new(ContentFetcher::new(), limits(2, 2, Duration::ZERO)).run_queued(queued);
+
+    queue.send((0, server.url("/a"))).await.unwrap();
+    let (first, result, _) = results.recv().await.unwrap();
+    assert_eq!((first, result.unwrap().title), (0, "/a".to_string()));
+    queue.send((1, server.url("/b"))).await.unwrap();
+    drop(queue);
+
+    let (second, result, _) = results.recv().await.unwrap();
+    assert_eq!((second, result.unwrap().title), (1, "/b".to_string()));
+    assert!(results.recv().await.is_none());
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use raindrop_notebooklm_integration::events::{Event, EventKind, Stage};
+use raindrop_notebooklm_integration::ledger::FailureClass;
+use raindrop_notebooklm_integration::progress::{ProgressTracker, StageProgress};
+use raindrop_notebooklm_integration::raindrop::BookmarkId;
+
+fn event(kind: EventKind) -> Event {
+    Event {
+        at: Utc::now(),
+        profile: "default".to_string(),
+        kind,
+    }
+}
+
+fn stage_started(stage: Stage, total: Option<usize>) -> Event {
+    event(EventKind::StageStarted { stage, total })
+}
+
+fn listing_page(page: usize, items: usize) -> Event {
+    event(EventKind::ListingPage {
+        search: "#research".to_string(),
+        page,
+        items,
+        total: 52,
+    })
+}
+
+#[test]
+fn should_work_out_rate_and_eta_from_the_stage_so_far() {
+    let started = Instant::now();
+    let mut progress = StageProgress::new(Stage::Fetching, Some(10), started);
+    progress.done = 4;
+    progress.bytes = 2048;
+    progress.current = Some("https://example.com/4".to_string());
+    let now = started + Duration::from_secs(2);
+
+    assert_eq!(progress.per_second(now), 2.0);
+    assert_eq!(progress.eta(now), Some(Duration::from_secs(3)));
+    assert_eq!(progress.count(), "4/10");
+    let line = progress.describe(now);
+    assert!(line.starts_with("Fetching 4/10 (2.0/s, 2.00 KiB, ETA "));
+    assert!(line.ends_with(") https://example.com/4"));
+}
+
+#[test]
+fn should_leave_out_the_eta_until_the_total_is_known() {
+    let started = Instant::now();
+    let mut progress = StageProgress::new(Stage::Listing, None, started);
+    progress.done = 50;
+
+    let now = started + Duration::from_secs(5);
+
+    assert_eq!(progress.eta(now), None);
+    assert_eq!(progress.count(), "50");
+    assert_eq!(progress.rates(now), "10.0/s");
+    assert_eq!(progress.rates(started), "0.0/s");
+}
+
+#[test]
+fn should_follow_each_stage_through_the_events_of_a_run() {
+    let mut tracker = ProgressTracker::log_lines();
+
+    tracker.update(&stage_started(Stage::Listing, None));
+    tracker.update(&listing_page(0, 50));
+    tracker.update(&listing_page(1, 2));
+    let listing = tracker.stages().next().unwrap().clone();
+    assert_eq!((listing.done, listing.total), (52, Some(52)));
+    assert_eq!(listing.current.as_deref(), Some("#research, page 2"));
+
+    // Fetching starts while the listing goes on, and learns its total
+    // once the listing is done.
+    tracker.update(&stage_started(Stage::Fetching, None));
+    assert_eq!(tracker.stages().count(), 2);
+    tracker.update(&event(EventKind::StageFinished {
+        stage: Stage::Listing,
+    }));
+    tracker.update(&event(EventKind::StageTotal {
+        stage: Stage::Fetching,
+        total: 2,
+    }));
+    tracker.update(&event(EventKind::FetchSucceeded {
+        bookmark_id: BookmarkId(1),
+        link: "https://example.com/1".to_string(),
+        title: "One".to_string(),
+        word_count: 10,
+        fetch_ms: 5,
+        bytes: 300,
+    }));
+    tracker.update(&event(EventKind::FetchFailed {
+        bookmark_id: BookmarkId(2),
+        link: "https://example.com/2".to_string(),
+        class: FailureClass::ClientError,
+        http_status: Some(404),
+        error: "HTTP 404".to_string(),
+        fetch_ms: 5,
+    }));
+    let stages = tracker.stages().cloned().collect::<Vec<_>>();
+    assert_eq!(stages.len(), 1);
+    assert_eq!(stages[0].stage, Stage::Fetching);
+    assert_eq!((stages[0].done, stages[0].bytes), (2, 300));
+    assert_eq!(stages[0].total, Some(2));
+    assert_eq!(stages[0].current.as_deref(), Some("https://example.com/2"));
+
+    tracker.update(&event(EventKind::RunFinished {
+        exit_code: 0,
+        error: None,
+    }));
+    assert_eq!(tracker.stages().count(), 0);
+}
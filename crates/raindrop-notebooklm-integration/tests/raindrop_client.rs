@@ -0,0 +1,249 @@
+use mockito::{Matcher, Server};
+use raindrop_notebooklm_integration::events::{EventKind, Events};
+use raindrop_notebooklm_integration::raindrop::{
+    Bookmark, BookmarkId, CollectionId, RaindropClient, TagMatch,
+};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+fn raindrop_item(id: u64) -> serde_json::Value {
+    json!({
+        "_id": id,
+        "title": format!("Bookmark {}", id),
+        "link": format!("https://example.com/{}", id),
+        "excerpt": "An excerpt",
+        "note": "A note",
+        "tags": ["research"],
+        "created": "2024-01-02T03:04:05.000Z",
+        "lastUpdate": "2024-02-03T04:05:06.000Z",
+        "collection": { "$id": 42 },
+        "domain": "example.com"
+    })
+}
+
+fn page_query(page: usize) -> Matcher {
+    Matcher::AllOf(vec![
+        Matcher::UrlEncoded("search".into(), "#research".into()),
+        Matcher::UrlEncoded("page".into(), page.to_string()),
+        Matcher::UrlEncoded("perpage".into(), "50".into()),
+    ])
+}
+
+/// Everything `client` sends for `tags`, in the order it was sent.
+async fn list(
+    client: &RaindropClient,
+    collection: CollectionId,
+    tags: &[&str],
+    tag_match: TagMatch,
+) -> Vec<Bookmark> {
+    let tags = tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+    let (found, mut received) = mpsc::channel(100);
+    client
+        .send_bookmarks_by_tags(collection, &tags, tag_match, &found)
+        .await
+        .unwrap();
+    drop(found);
+    let mut bookmarks = Vec::new();
+    while let Some(bookmark) = received.recv().await {
+        bookmarks.push(bookmark);
+    }
+    bookmarks
+}
+
+#[tokio::test]
+async fn should_follow_pagination_until_all_bookmarks_are_fetched() {
+    let mut server = Server::new_async().await;
+    let first_page: Vec<_> = (0..50).map(raindrop_item).collect();
+    let first = server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(page_query(0))
+        .with_body(json!({ "result": true, "items": first_page, "count": 52 }).to_string())
+        .create_async()
+        .await;
+    let second = server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(page_query(1))
+        .with_body(
+            json!({ "result": true, "items": [raindrop_item(50), raindrop_item(51)], "count": 52 })
+                .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let client = RaindropClient::new("test-token").with_base_url(server.url());
+    let bookmarks = list(&client, CollectionId::ALL, &["research"], TagMatch::All).await;
+
+    first.assert_async().await;
+    second.assert_async().await;
+    assert_eq!(bookmarks.len(), 52);
+    assert_eq!(bookmarks.last().unwrap().id, BookmarkId(51));
+}
+
+#[tokio::test]
+async fn should_page_until_a_short_page_whatever_the_count_says() {
+    let mut server = Server::new_async().await;
+    let first_page: Vec<_> = (0..50).map(raindrop_item).collect();
+    // A count that stopped at the first page, then none at all.
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(page_query(0))
+        .with_body(json!({ "result": true, "items": first_page, "count": 50 }).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(page_query(1))
+        .with_body(json!({ "result": true, "items": [raindrop_item(50)] }).to_string())
+        .create_async()
+        .await;
+    let beyond = server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(page_query(2))
+        .expect(0)
+        .create_async()
+        .await;
+
+    let client = RaindropClient::new("test-token").with_base_url(server.url());
+    let bookmarks = list(&client, CollectionId::ALL, &["research"], TagMatch::All).await;
+
+    beyond.assert_async().await;
+    assert_eq!(bookmarks.len(), 51);
+    assert_eq!(bookmarks.last().unwrap().id, BookmarkId(50));
+}
+
+#[tokio::test]
+async fn should_report_each_page_of_a_search_as_it_arrives() {
+    let mut server = Server::new_async().await;
+    let first_page: Vec<_> = (0..50).map(raindrop_item).collect();
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(page_query(0))
+        .with_body(json!({ "result": true, "items": first_page, "count": 52 }).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(page_query(1))
+        .with_body(
+            json!({ "result": true, "items": [raindrop_item(50), raindrop_item(51)], "count": 52 })
+                .to_string(),
+        )
+        .create_async()
+        .await;
+    let (events, mut receiver) = Events::channel();
+
+    let client = RaindropClient::new("test-token")
+        .with_base_url(server.url())
+        .with_events(events);
+    list(&client, CollectionId::ALL, &["research"], TagMatch::All).await;
+    drop(client);
+
+    let mut pages = Vec::new();
+    while let Some(event) = receiver.recv().await {
+        pages.push(event.kind);
+    }
+    let listing_page = |page, items| EventKind::ListingPage {
+        search: "#research".to_string(),
+        page,
+        items,
+        total: 52,
+    };
+    assert_eq!(pages, [listing_page(0, 50), listing_page(1, 2)]);
+}
+
+#[tokio::test]
+async fn should_deserialize_bookmark_fields_and_send_bearer_token() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/rest/v1/raindrops/42")
+        .match_header("authorization", "Bearer test-token")
+        .match_query(page_query(0))
+        .with_body(json!({ "result": true, "items": [raindrop_item(7)], "count": 1 }).to_string())
+        .create_async()
+        .await;
+
+    let client = RaindropClient::new("test-token").with_base_url(server.url());
+    let bookmarks = list(&client, CollectionId(42), &["research"], TagMatch::All).await;
+
+    mock.assert_async().await;
+    let bookmark = &bookmarks[0];
+    assert_eq!(bookmark.id, BookmarkId(7));
+    assert_eq!(bookmark.title, "Bookmark 7");
+    assert_eq!(bookmark.link, "https://example.com/7");
+    assert_eq!(bookmark.tags, vec!["research".to_string()]);
+    assert_eq!(bookmark.collection_id, CollectionId(42));
+    assert_eq!(bookmark.domain, "example.com");
+    assert_eq!(
+        bookmark.last_update.to_rfc3339(),
+        "2024-02-03T04:05:06+00:00"
+    );
+}
+
+#[tokio::test]
+async fn should_reject_invalid_token_on_authenticate() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_status(401)
+        .create_async()
+        .await;
+
+    let client = RaindropClient::new("bad-token").with_base_url(server.url());
+
+    assert!(client.authenticate().await.is_err());
+}
+
+#[tokio::test]
+async fn should_combine_tags_into_a_single_search_when_all_must_match() {
+    let mut server = Server::new_async().await;
+    let mock = server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(Matcher::UrlEncoded(
+            "search".into(),
+            "#research #\"machine learning\"".into(),
+        ))
+        .with_body(json!({ "result": true, "items": [raindrop_item(1)], "count": 1 }).to_string())
+        .create_async()
+        .await;
+
+    let client = RaindropClient::new("test-token").with_base_url(server.url());
+    let tags = ["research", "machine learning"];
+    let bookmarks = list(&client, CollectionId::ALL, &tags, TagMatch::All).await;
+
+    mock.assert_async().await;
+    assert_eq!(bookmarks.len(), 1);
+}
+
+#[tokio::test]
+async fn should_send_the_bookmarks_of_any_tag_newest_first() {
+    let mut server = Server::new_async().await;
+    let created = |id: u64, day: u32| {
+        let mut item = raindrop_item(id);
+        item["created"] = json!(format!("2024-01-{:02}T00:00:00.000Z", day));
+        item
+    };
+    for (tag, items) in [
+        ("#research", [created(3, 5), created(1, 1)]),
+        ("#ai", [created(4, 4), created(2, 2)]),
+    ] {
+        server
+            .mock("GET", "/rest/v1/raindrops/0")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("search".into(), tag.into()),
+                Matcher::UrlEncoded("sort".into(), "-created".into()),
+            ]))
+            .with_body(json!({ "result": true, "items": items, "count": 2 }).to_string())
+            .create_async()
+            .await;
+    }
+
+    let client = RaindropClient::new("test-token").with_base_url(server.url());
+    let tags = ["research", "ai"];
+    let bookmarks = list(&client, CollectionId::ALL, &tags, TagMatch::Any).await;
+
+    let ids = bookmarks
+        .iter()
+        .map(|bookmark| bookmark.id.0)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [3, 4, 2, 1]);
+}
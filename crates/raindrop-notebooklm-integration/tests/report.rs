@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, TimeZone, Utc};
+use raindrop_notebooklm_integration::content::PageContent;
+use raindrop_notebooklm_integration::ledger::FailureClass;
+use raindrop_notebooklm_integration::plan::SkipReason;
+use raindrop_notebooklm_integration::raindrop::{Bookmark, BookmarkId, CollectionId};
+use raindrop_notebooklm_integration::report::{self, NotebookLink};
+use raindrop_notebooklm_integration::snapshot::{
+    Outcome, RunInfo, Snapshot, SnapshotItem, SNAPSHOT_VERSION,
+};
+use raindrop_notebooklm_integration::state::{self, BookmarkRecord};
+
+fn report_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-report-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn at(seconds: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, 3, 12, 0, seconds).unwrap()
+}
+
+fn item(
+    id: u64,
+    domain: &str,
+    words: Option<usize>,
+    fetch_ms: u64,
+    outcome: Outcome,
+) -> SnapshotItem {
+    let link = format!("https://{}/{}", domain, id);
+    SnapshotItem {
+        bookmark_id: BookmarkId(id),
+        link: link.clone(),
+        title: format!("Page {}", id),
+        bookmark: Some(Bookmark {
+            id: BookmarkId(id),
+            title: format!("Page {}", id),
+            link: link.clone(),
+            excerpt: String::new(),
+            note: String::new(),
+            tags: vec!["research".to_string()],
+            created: at(0),
+            last_update: at(0),
+            collection_id: CollectionId(42),
+            domain: domain.to_string(),
+        }),
+        content: words.map(|word_count| PageContent {
+            url: link,
+            title: format!("Page {}", id),
+            byline: None,
+            published: None,
+            language: None,
+            text: String::new(),
+            markdown: String::new(),
+            word_count,
+        }),
+        fetch_ms: Some(fetch_ms),
+        source_id: None,
+        outcome,
+    }
+}
+
+fn fetch_failed(class: FailureClass, error: &str) -> Outcome {
+    Outcome::Skipped {
+        reason: SkipReason::FetchFailed {
+            class,
+            http_status: None,
+            error: error.to_string(),
+        },
+    }
+}
+
+fn snapshot(items: Vec<SnapshotItem>) -> Snapshot {
+    Snapshot {
+        version: SNAPSHOT_VERSION,
+        run: RunInfo {
+            tool_version: "0.1.0".to_string(),
+            profile: "default".to_string(),
+            scope: "collection=0;match=all;tags=research".to_string(),
+            selection: "#research".to_string(),
+            dry_run: false,
+            started_at: at(0),
+            finished_at: at(3),
+            error: None,
+        },
+        config: BTreeMap::new(),
+        items,
+    }
+}
+
+fn record(id: u64, shard: usize, source_id: &str) -> BookmarkRecord {
+    BookmarkRecord {
+        id: BookmarkId(id),
+        title: format!("Page [{}]", id),
+        link: format!("https://example.com/{}", id),
+        last_update: at(0),
+        content_hash: state::content_hash("content"),
+        source_id: Some(source_id.to_string()),
+        shard,
+        word_count: 10,
+        synced_at: at(3),
+    }
+}
+
+#[test]
+fn should_count_bookmarks_by_outcome_and_domain() {
+    let items = vec![
+        item(1, "example.com", Some(100), 200, Outcome::Added),
+        item(2, "example.com", Some(50), 100, Outcome::Replaced),
+        item(3, "blog.test", None, 0, Outcome::Unchanged),
+        item(
+            4,
+            "example.com",
+            None,
+            900,
+            fetch_failed(FailureClass::Timeout, "timed out"),
+        ),
+    ];
+
+    let markdown = report::render(&snapshot(items), &[], &[]);
+
+    assert!(markdown.starts_with("# Sync summary: #research\n"));
+    assert!(markdown.contains("- Started: 2024-05-03 12:00:00 UTC, took 3.0 s"));
+    assert!(markdown.contains("| Found | 4 |"));
+    assert!(markdown.contains("| Fetched | 2 |"));
+    assert!(markdown.contains("| Skipped | 1 |"));
+    assert!(markdown.contains("| Uploaded | 2 |"));
+    assert!(markdown.contains("| Unchanged | 1 |"));
+    let example = markdown.find("| example.com | 3 | 2 | 1 | 150 |").unwrap();
+    let blog = markdown.find("| blog.test | 1 | 0 | 0 | 0 |").unwrap();
+    assert!(example < blog);
+}
+
+#[test]
+fn should_list_the_slowest_and_largest_pages_first() {
+    let items = (1..=7)
+        .map(|id| {
+            item(
+                id,
+                "example.com",
+                Some(id as usize * 10),
+                id * 100,
+                Outcome::Added,
+            )
+        })
+        .collect();
+
+    let markdown = report::render(&snapshot(items), &[], &[]);
+
+    let slowest = markdown.split("## Slowest pages").nth(1).unwrap();
+    let slowest = slowest.split("## Largest pages").next().unwrap();
+    assert!(slowest.find("| Page 7 |").unwrap() < slowest.find("| Page 6 |").unwrap());
+    assert!(slowest.contains("| Page 7 | <https://example.com/7> | 0.7 s |"));
+    assert!(!slowest.contains("| Page 2 |"));
+    let largest = markdown.split("## Largest pages").nth(1).unwrap();
+    assert!(largest.contains("| Page 7 | <https://example.com/7> | 70 |"));
+    assert!(!largest.contains("| Page 1 |"));
+}
+
+#[test]
+fn should_group_failures_by_cause() {
+    let items = vec![
+        item(
+            1,
+            "example.com",
+            None,
+            10,
+            fetch_failed(FailureClass::ClientError, "HTTP 404"),
+        ),
+        item(
+            2,
+            "example.com",
+            None,
+            10,
+            fetch_failed(FailureClass::ClientError, "HTTP 410"),
+        ),
+        item(
+            3,
+            "example.com",
+            None,
+            10,
+            fetch_failed(FailureClass::Timeout, "timed out"),
+        ),
+        item(
+            4,
+            "example.com",
+            None,
+            10,
+            Outcome::Skipped {
+                reason: SkipReason::Duplicate,
+            },
+        ),
+    ];
+
+    let markdown = report::render(&snapshot(items), &[], &[]);
+
+    let client = markdown
+        .find("### Could not be fetched: client_error (2)")
+        .unwrap();
+    let timeout = markdown
+        .find("### Could not be fetched: timeout (1)")
+        .unwrap();
+    assert!(client < timeout);
+    assert!(markdown.contains("- [Page 1](<https://example.com/1>): HTTP 404"));
+    assert!(markdown.contains("### Same URL as another bookmark (1)"));
+}
+
+#[test]
+fn should_link_notebooks_and_their_sources() {
+    let notebooks = vec![
+        NotebookLink {
+            shard: 1,
+            title: "Research (2/2)".to_string(),
+            notebook_id: "nb-2".to_string(),
+            url: None,
+        },
+        NotebookLink {
+            shard: 0,
+            title: "Research (1/2)".to_string(),
+            notebook_id: "nb-1".to_string(),
+            url: Some("https://notebooklm.cloud.google.com/global/notebook/nb-1".to_string()),
+        },
+    ];
+    let records = vec![record(1, 0, "src-1"), record(2, 1, "src-2")];
+
+    let markdown = report::render(&snapshot(Vec::new()), &notebooks, &records);
+
+    let first = markdown
+        .find("### [Research (1/2)](<https://notebooklm.cloud.google.com/global/notebook/nb-1>)")
+        .unwrap();
+    let second = markdown.find("### Research (2/2) (`nb-2`)").unwrap();
+    assert!(first < second);
+    assert!(markdown.contains("- [Page \\[1\\]](<https://example.com/1>): source `src-1`"));
+    assert!(markdown.contains("## Failures\n\nNone."));
+}
+
+#[test]
+fn should_write_the_summary_without_overwriting_an_earlier_one() {
+    let dir = report_dir("write");
+    let snapshot = snapshot(Vec::new());
+
+    let first = report::write(&dir, &snapshot, "first").unwrap();
+    let second = report::write(&dir, &snapshot, "second").unwrap();
+
+    assert_eq!(first, dir.join("summary-default.md"));
+    assert_eq!(second, dir.join("summary-default-20240503T120003Z.md"));
+    assert_eq!(std::fs::read_to_string(first).unwrap(), "first");
+}
@@ -0,0 +1,293 @@
+use std::time::{Duration, Instant};
+
+use chrono::{TimeZone, Utc};
+use mockito::Server;
+use raindrop_notebooklm_integration::content::ContentFetcher;
+use raindrop_notebooklm_integration::error::{AppError, ContentError, ServiceError};
+use raindrop_notebooklm_integration::notebooklm::NotebookLMClient;
+use raindrop_notebooklm_integration::raindrop::RaindropClient;
+use raindrop_notebooklm_integration::retry::{self, RetryLimits, RetryPolicy};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::json;
+
+const USER: &str = r#"{"result":true,"user":{"_id":1,"fullName":"Ada"}}"#;
+
+fn fast_retries(max_retries: u32, budget: u32) -> RetryPolicy {
+    RetryPolicy::new(RetryLimits {
+        max_retries,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_secs(2),
+        budget,
+    })
+}
+
+fn raindrop(server: &Server, retry: &RetryPolicy) -> RaindropClient {
+    RaindropClient::new("token")
+        .with_base_url(server.url())
+        .with_retry(retry.clone())
+}
+
+fn fetch_status(error: AppError) -> Option<u16> {
+    match error {
+        AppError::Content(ContentError::Fetch { source, .. }) => source.http_status(),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn should_retry_server_errors_until_the_request_succeeds() {
+    let mut server = Server::new_async().await;
+    let failing = server
+        .mock("GET", "/rest/v1/user")
+        .with_status(503)
+        .expect(2)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_body(USER)
+        .create_async()
+        .await;
+    let retry = fast_retries(3, 10);
+
+    let session = raindrop(&server, &retry).authenticate().await.unwrap();
+
+    assert_eq!(session.user.full_name, "Ada");
+    failing.assert_async().await;
+    assert_eq!(retry.remaining(), 8);
+}
+
+#[tokio::test]
+async fn should_give_up_after_the_maximum_number_of_retries() {
+    let mut server = Server::new_async().await;
+    let failing = server
+        .mock("GET", "/rest/v1/user")
+        .with_status(500)
+        .expect(3)
+        .create_async()
+        .await;
+
+    let error = raindrop(&server, &fast_retries(2, 10))
+        .authenticate()
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        error,
+        AppError::Raindrop(ServiceError::Status { status: 500, .. })
+    ));
+    failing.assert_async().await;
+}
+
+#[tokio::test]
+async fn should_not_retry_client_errors() {
+    let mut server = Server::new_async().await;
+    let missing = server
+        .mock("GET", "/gone")
+        .with_status(404)
+        .expect(1)
+        .create_async()
+        .await;
+    let fetcher = ContentFetcher::new().with_retry(fast_retries(3, 10));
+
+    let error = fetcher
+        .fetch_content(&format!("{}/gone", server.url()))
+        .await
+        .unwrap_err();
+
+    assert_eq!(fetch_status(error), Some(404));
+    missing.assert_async().await;
+}
+
+#[tokio::test]
+async fn should_wait_as_long_as_retry_after_asks() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/article")
+        .with_status(429)
+        .with_header("retry-after", "1")
+        .expect(1)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/article")
+        .with_header("content-type", "text/html")
+        .with_body("<html><head><title>Article</title></head><body><p>Text</p></body></html>")
+        .create_async()
+        .await;
+    let fetcher = ContentFetcher::new().with_retry(fast_retries(3, 10));
+    let started = Instant::now();
+
+    let page = fetcher
+        .fetch_content(&format!("{}/article", server.url()))
+        .await
+        .unwrap();
+
+    assert_eq!(page.title, "Article");
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn should_not_wait_when_the_server_asks_for_more_than_the_maximum_delay() {
+    let mut server = Server::new_async().await;
+    let limited = server
+        .mock("GET", "/rest/v1/user")
+        .with_status(429)
+        .with_header("retry-after", "3600")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let error = raindrop(&server, &fast_retries(3, 10))
+        .authenticate()
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        match error {
+            AppError::Raindrop(e) => e.http_status(),
+            _ => None,
+        },
+        Some(429)
+    );
+    limited.assert_async().await;
+}
+
+#[tokio::test]
+async fn should_share_the_retry_budget_between_clients() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_status(502)
+        .expect(1)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_body(USER)
+        .create_async()
+        .await;
+    let page = server
+        .mock("GET", "/article")
+        .with_status(502)
+        .expect(1)
+        .create_async()
+        .await;
+    let retry = fast_retries(3, 1);
+
+    raindrop(&server, &retry).authenticate().await.unwrap();
+    let error = ContentFetcher::new()
+        .with_retry(retry.clone())
+        .fetch_content(&format!("{}/article", server.url()))
+        .await
+        .unwrap_err();
+
+    // The Raindrop retry used up the budget, so the page is tried once.
+    assert_eq!(fetch_status(error), Some(502));
+    page.assert_async().await;
+    assert_eq!(retry.remaining(), 0);
+}
+
+#[tokio::test]
+async fn should_retry_notebooklm_requests() {
+    let mut server = Server::new_async().await;
+    let path = "/v1alpha/projects/123456/locations/global/notebooks";
+    let unavailable = server
+        .mock("POST", path)
+        .with_status(503)
+        .expect(1)
+        .create_async()
+        .await;
+    server
+        .mock("POST", path)
+        .with_body(json!({ "notebookId": "nb-1", "title": "Research" }).to_string())
+        .create_async()
+        .await;
+    let client = NotebookLMClient::new("token", "123456")
+        .with_endpoint(server.url())
+        .with_retry(fast_retries(3, 10));
+
+    let notebook = client.create_notebook("Research").await.unwrap();
+
+    assert_eq!(notebook.notebook_id, "nb-1");
+    unavailable.assert_async().await;
+}
+
+#[tokio::test]
+async fn should_not_repeat_a_notebooklm_request_the_server_may_have_processed() {
+    let mut server = Server::new_async().await;
+    let path = "/v1alpha/projects/123456/locations/global/notebooks";
+    let failing = server
+        .mock("POST", path)
+        .with_status(502)
+        .expect(1)
+        .create_async()
+        .await;
+    let client = NotebookLMClient::new("token", "123456")
+        .with_endpoint(server.url())
+        .with_retry(fast_retries(3, 10));
+
+    let error = client.create_notebook("Research").await.unwrap_err();
+
+    assert!(matches!(
+        error,
+        AppError::NotebookLM(ServiceError::Status { status: 502, .. })
+    ));
+    failing.assert_async().await;
+}
+
+#[test]
+fn should_read_requested_waits_from_rate_limit_headers() {
+    let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+    let headers = |pairs: &[(&'static str, &str)]| {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    };
+
+    assert_eq!(
+        retry::requested_wait(&headers(&[("retry-after", "7")]), now),
+        Some(Duration::from_secs(7))
+    );
+    assert_eq!(
+        retry::requested_wait(
+            &headers(&[("retry-after", "Wed, 01 May 2024 12:00:30 GMT")]),
+            now
+        ),
+        Some(Duration::from_secs(30))
+    );
+    let reset = (now.timestamp() + 45).to_string();
+    assert_eq!(
+        retry::requested_wait(&headers(&[("x-ratelimit-reset", &reset)]), now),
+        Some(Duration::from_secs(45))
+    );
+    assert_eq!(
+        retry::requested_wait(&headers(&[("ratelimit-reset", "12")]), now),
+        Some(Duration::from_secs(12))
+    );
+    assert_eq!(retry::requested_wait(&headers(&[]), now), None);
+}
+
+#[test]
+fn should_back_off_exponentially_within_the_limits() {
+    let retry = RetryPolicy::new(RetryLimits {
+        max_retries: 4,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(300),
+        budget: 10,
+    });
+
+    let delays = (0..4)
+        .map(|retries| retry.next_delay(retries, None).unwrap())
+        .collect::<Vec<_>>();
+
+    // Half of each wait is fixed and half is jitter.
+    assert!(delays[0] >= Duration::from_millis(50) && delays[0] <= Duration::from_millis(100));
+    assert!(delays[1] >= Duration::from_millis(100) && delays[1] <= Duration::from_millis(200));
+    assert!(delays[2] >= Duration::from_millis(150) && delays[2] <= Duration::from_millis(300));
+    assert!(delays[3] <= Duration::from_millis(300));
+    assert_eq!(retry.next_delay(4, None), None);
+    assert_eq!(RetryPolicy::none().next_delay(0, None), None);
+}
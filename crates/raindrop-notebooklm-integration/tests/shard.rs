@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+
+use chrono::{TimeZone, Utc};
+use raindrop_notebooklm_integration::raindrop::BookmarkId;
+use raindrop_notebooklm_integration::shard::{self, ShardLimits, Shards};
+use raindrop_notebooklm_integration::state::{BookmarkRecord, NotebookShard, StateStore};
+
+const LIMITS: ShardLimits = ShardLimits {
+    max_sources: 3,
+    max_words: 1_000,
+};
+
+/// The shard each of `words` lands in, placed one after the other.
+fn place(placed: &[(usize, usize)], words: &[usize]) -> Vec<usize> {
+    let mut shards = Shards::new(placed, LIMITS);
+    words.iter().map(|&words| shards.place(words)).collect()
+}
+
+#[test]
+fn should_keep_everything_in_one_notebook_below_the_limits() {
+    assert_eq!(place(&[], &[100; 3]), vec![0, 0, 0]);
+}
+
+#[test]
+fn should_open_a_new_shard_when_source_limit_is_reached() {
+    assert_eq!(place(&[], &[100; 7]), vec![0, 0, 0, 1, 1, 1, 2]);
+}
+
+#[test]
+fn should_open_a_new_shard_when_word_limit_is_reached() {
+    let placed = place(&[], &[600, 600, 300, 5_000]);
+
+    // The third still fits next to the first; the last is too large for any
+    // notebook, so it gets one of its own rather than being dropped.
+    assert_eq!(placed, vec![0, 1, 0, 2]);
+}
+
+#[test]
+fn should_never_move_placed_bookmarks_and_fill_freed_space_first() {
+    // Shard 0 lost a bookmark since the last run; shard 1 is full.
+    let placed = [(0, 100), (0, 100), (1, 100), (1, 100), (1, 100)];
+
+    assert_eq!(place(&placed, &[100, 100]), vec![0, 2]);
+}
+
+#[test]
+fn should_count_a_placed_bookmark_at_its_new_size() {
+    let mut shards = Shards::new(&[(0, 100), (0, 800)], LIMITS);
+
+    assert_eq!(shards.place(200), 1);
+    // The large page shrank and leaves room in shard 0.
+    shards.resize(0, 800, 300);
+    assert_eq!(shards.place(500), 0);
+}
+
+#[test]
+fn should_number_titles_only_when_split() {
+    assert_eq!(shard::title("Research", 0, 1), "Research");
+    assert_eq!(shard::title("Research", 1, 3), "Research (2/3)");
+}
+
+#[test]
+fn should_list_every_bookmark_under_its_notebook_in_the_manifest() {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-shard-test-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = StateStore::open(&dir).unwrap();
+    let record = |id: u64, shard: usize| BookmarkRecord {
+        id: BookmarkId(id),
+        link: format!("https://example.com/{}", id),
+        title: format!("Bookmark {}", id),
+        last_update: Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap(),
+        content_hash: "hash".to_string(),
+        source_id: Some(format!("src-{}", id)),
+        shard,
+        word_count: 100,
+        synced_at: Utc.with_ymd_and_hms(2024, 1, 5, 0, 0, 0).unwrap(),
+    };
+    store.save("s", &record(1, 0)).unwrap();
+    store.save("s", &record(2, 1)).unwrap();
+    store.save("s", &record(3, 1)).unwrap();
+    store
+        .save_notebook(
+            "s",
+            &NotebookShard {
+                shard: 0,
+                notebook_id: "nb-1".to_string(),
+                title: "Research (1/2)".to_string(),
+            },
+        )
+        .unwrap();
+
+    let path: PathBuf = shard::write_manifest(&store, &dir).unwrap();
+    let manifest: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+
+    let notebooks = &manifest["scopes"][0]["notebooks"];
+    assert_eq!(notebooks[0]["notebook_id"], "nb-1");
+    assert_eq!(notebooks[0]["bookmarks"][0]["id"], 1);
+    assert_eq!(notebooks[1]["shard"], 2);
+    assert_eq!(notebooks[1]["notebook_id"], serde_json::Value::Null);
+    assert_eq!(notebooks[1]["words"], 200);
+}
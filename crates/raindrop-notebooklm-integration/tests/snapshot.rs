@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use jsonschema::JSONSchema;
+use raindrop_notebooklm_integration::content::PageContent;
+use raindrop_notebooklm_integration::ledger::FailureClass;
+use raindrop_notebooklm_integration::plan::{
+    Change, ChangeKind, ExecutionPlan, SkipReason, Skipped, PLAN_VERSION,
+};
+use raindrop_notebooklm_integration::raindrop::{Bookmark, BookmarkId, CollectionId};
+use raindrop_notebooklm_integration::snapshot::{
+    self, Outcome, RunInfo, Snapshot, SNAPSHOT_VERSION,
+};
+use raindrop_notebooklm_integration::state::{self, BookmarkRecord};
+use serde_json::{json, Value};
+
+fn snapshot_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-snapshot-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn at(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 5, day, 12, 0, 0).unwrap()
+}
+
+fn bookmark(id: u64) -> Bookmark {
+    Bookmark {
+        id: BookmarkId(id),
+        title: format!("Bookmark {}", id),
+        link: format!("https://example.com/{}", id),
+        excerpt: String::new(),
+        note: String::new(),
+        tags: vec!["research".to_string()],
+        created: at(1),
+        last_update: at(2),
+        collection_id: CollectionId(42),
+        domain: "example.com".to_string(),
+    }
+}
+
+fn record(id: u64, source_id: &str) -> BookmarkRecord {
+    BookmarkRecord {
+        id: BookmarkId(id),
+        title: format!("Bookmark {}", id),
+        link: format!("https://example.com/{}", id),
+        last_update: at(2),
+        content_hash: state::content_hash("content"),
+        source_id: Some(source_id.to_string()),
+        shard: 0,
+        word_count: 3,
+        synced_at: at(3),
+    }
+}
+
+fn change(kind: ChangeKind, id: u64) -> Change {
+    Change {
+        kind,
+        bookmark_id: BookmarkId(id),
+        link: format!("https://example.com/{}", id),
+        title: format!("Bookmark {}", id),
+        last_update: at(2),
+        shard: 0,
+        word_count: 3,
+        content_hash: state::content_hash("content"),
+        source_id: None,
+        content: None,
+    }
+}
+
+fn skipped(id: u64, reason: SkipReason) -> Skipped {
+    Skipped {
+        bookmark_id: BookmarkId(id),
+        link: format!("https://example.com/{}", id),
+        title: format!("Bookmark {}", id),
+        last_update: at(2),
+        reason,
+    }
+}
+
+/// Bookmark 1 was added, 2 unchanged, 3 a duplicate, 4 could not be
+/// fetched, 5 was removed and 6 no longer matches but stays.
+fn snapshot() -> Snapshot {
+    let plan = ExecutionPlan {
+        version: PLAN_VERSION,
+        created_at: at(3),
+        profile: "default".to_string(),
+        scope: "collection=0;match=all;tags=research".to_string(),
+        selection: "#research".to_string(),
+        state_fingerprint: String::new(),
+        checked_sources: true,
+        notebooks: Vec::new(),
+        changes: vec![change(ChangeKind::Add, 1), change(ChangeKind::Remove, 5)],
+        unchanged: 1,
+        stale: 1,
+        skipped: vec![
+            skipped(3, SkipReason::Duplicate),
+            skipped(
+                4,
+                SkipReason::FetchFailed {
+                    class: FailureClass::ClientError,
+                    http_status: Some(404),
+                    error: "HTTP 404".to_string(),
+                },
+            ),
+        ],
+    };
+    let pages = HashMap::from([(
+        BookmarkId(1),
+        PageContent {
+            url: "https://example.com/1".to_string(),
+            title: "Bookmark 1".to_string(),
+            byline: None,
+            published: Some(at(1)),
+            language: Some("en".to_string()),
+            text: "Three words here".to_string(),
+            markdown: "Three words here".to_string(),
+            word_count: 3,
+        },
+    )]);
+    let config = BTreeMap::from([
+        ("profile".to_string(), Some("default".to_string())),
+        ("raindrop_api_key".to_string(), Some("********".to_string())),
+        ("notebooklm_project".to_string(), None),
+    ]);
+    Snapshot::new(
+        RunInfo {
+            tool_version: "0.1.0".to_string(),
+            profile: "default".to_string(),
+            scope: plan.scope.clone(),
+            selection: plan.selection.clone(),
+            dry_run: false,
+            started_at: at(3),
+            finished_at: at(3),
+            error: None,
+        },
+        config,
+        &plan,
+        &[
+            bookmark(1),
+            bookmark(2),
+            bookmark(3),
+            bookmark(4),
+            bookmark(2),
+        ],
+        &pages,
+        &HashMap::from([
+            (BookmarkId(1), Duration::from_millis(120)),
+            (BookmarkId(4), Duration::from_millis(30)),
+        ]),
+        &[record(1, "src-1"), record(2, "src-2"), record(6, "src-6")],
+    )
+}
+
+fn schema_errors(instance: &Value) -> Vec<String> {
+    let schema = serde_json::from_str::<Value>(snapshot::SCHEMA).unwrap();
+    let compiled = JSONSchema::compile(&schema).expect("the published schema is valid");
+    let result = compiled
+        .validate(instance)
+        .map_err(|errors| errors.map(|e| e.to_string()).collect::<Vec<_>>());
+    result.err().unwrap_or_default()
+}
+
+#[test]
+fn should_record_the_outcome_of_every_bookmark() {
+    let snapshot = snapshot();
+
+    let outcomes = snapshot
+        .items
+        .iter()
+        .map(|item| (item.bookmark_id.0, item.outcome.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(outcomes[0], (1, Outcome::Added));
+    assert_eq!(outcomes[1], (2, Outcome::Unchanged));
+    assert_eq!(
+        outcomes[2],
+        (
+            3,
+            Outcome::Skipped {
+                reason: SkipReason::Duplicate
+            }
+        )
+    );
+    assert!(matches!(outcomes[3], (4, Outcome::Skipped { .. })));
+    assert_eq!(outcomes[4], (5, Outcome::Removed));
+    assert_eq!(outcomes[5], (6, Outcome::Stale));
+    assert_eq!(outcomes.len(), 6);
+
+    let added = &snapshot.items[0];
+    assert_eq!(added.source_id.as_deref(), Some("src-1"));
+    assert_eq!(added.content.as_ref().unwrap().word_count, 3);
+    assert_eq!(added.fetch_ms, Some(120));
+    assert!(snapshot.items[1].content.is_none());
+    assert!(snapshot.items[4].bookmark.is_none());
+}
+
+#[test]
+fn should_match_the_published_schema() {
+    let json = serde_json::to_value(snapshot()).unwrap();
+
+    assert_eq!(schema_errors(&json), Vec::<String>::new());
+    assert_eq!(json["items"][3]["reason"]["kind"], "fetch_failed");
+    assert_eq!(json["items"][3]["reason"]["http_status"], 404);
+}
+
+#[test]
+fn should_publish_the_current_format_version_in_the_schema() {
+    let schema = serde_json::from_str::<Value>(snapshot::SCHEMA).unwrap();
+
+    assert_eq!(
+        schema["properties"]["version"]["const"],
+        json!(SNAPSHOT_VERSION)
+    );
+}
+
+#[test]
+fn should_reject_snapshots_that_break_the_schema() {
+    let mut missing_content = serde_json::to_value(snapshot()).unwrap();
+    missing_content["items"][0]
+        .as_object_mut()
+        .unwrap()
+        .remove("content");
+    let mut unexplained_skip = serde_json::to_value(snapshot()).unwrap();
+    unexplained_skip["items"][2]
+        .as_object_mut()
+        .unwrap()
+        .remove("reason");
+    let mut other_version = serde_json::to_value(snapshot()).unwrap();
+    other_version["version"] = json!(SNAPSHOT_VERSION + 1);
+
+    assert!(!schema_errors(&missing_content).is_empty());
+    assert!(!schema_errors(&unexplained_skip).is_empty());
+    assert!(!schema_errors(&other_version).is_empty());
+}
+
+#[test]
+fn should_read_back_a_saved_snapshot() {
+    let dir = snapshot_dir("roundtrip");
+    let snapshot = snapshot();
+
+    let path = snapshot.save(&dir).unwrap();
+
+    assert_eq!(path, dir.join("sync-default.json"));
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(serde_json::from_str::<Snapshot>(&text).unwrap(), snapshot);
+}
+
+#[test]
+fn should_add_a_timestamp_instead_of_overwriting_an_earlier_snapshot() {
+    let dir = snapshot_dir("unique");
+    let snapshot = snapshot();
+
+    let paths = (0..3)
+        .map(|_| snapshot.save(&dir).unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        paths,
+        vec![
+            dir.join("sync-default.json"),
+            dir.join("sync-default-20240503T120000Z.json"),
+            dir.join("sync-default-20240503T120000Z-2.json"),
+        ]
+    );
+}
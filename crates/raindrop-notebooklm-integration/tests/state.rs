@@ -0,0 +1,228 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, TimeZone, Utc};
+use raindrop_notebooklm_integration::ledger::{FailedFetch, FailureClass};
+use raindrop_notebooklm_integration::raindrop::{Bookmark, BookmarkId, CollectionId, TagMatch};
+use raindrop_notebooklm_integration::state::{self, BookmarkRecord, NotebookShard, StateStore};
+
+fn state_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-state-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn at(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap()
+}
+
+fn bookmark(id: u64, last_update: DateTime<Utc>) -> Bookmark {
+    Bookmark {
+        id: BookmarkId(id),
+        title: format!("Bookmark {}", id),
+        link: format!("https://example.com/{}", id),
+        excerpt: String::new(),
+        note: String::new(),
+        tags: vec!["research".to_string()],
+        created: at(1),
+        last_update,
+        collection_id: CollectionId(42),
+        domain: "example.com".to_string(),
+    }
+}
+
+fn record(id: u64, last_update: DateTime<Utc>) -> BookmarkRecord {
+    BookmarkRecord {
+        id: BookmarkId(id),
+        title: format!("Bookmark {}", id),
+        link: format!("https://example.com/{}", id),
+        last_update,
+        content_hash: state::content_hash("content"),
+        source_id: Some(format!("source-{}", id)),
+        shard: 0,
+        word_count: 100,
+        synced_at: at(5),
+    }
+}
+
+#[test]
+fn should_persist_records_per_scope_across_reopening() {
+    let dir = state_dir("persist");
+    let research = state::scope(CollectionId::ALL, &["research".to_string()], TagMatch::All);
+    let other = state::scope(CollectionId::ALL, &["other".to_string()], TagMatch::All);
+
+    {
+        let store = StateStore::open(&dir).unwrap();
+        store.save(&research, &record(1, at(2))).unwrap();
+        store.save(&research, &record(2, at(2))).unwrap();
+        store.save(&other, &record(3, at(2))).unwrap();
+        store.save(&research, &record(2, at(3))).unwrap();
+        store.remove(&research, BookmarkId(1)).unwrap();
+    }
+
+    let store = StateStore::open(&dir).unwrap();
+    assert_eq!(store.records(&research).unwrap(), vec![record(2, at(3))]);
+    assert_eq!(store.records(&other).unwrap(), vec![record(3, at(2))]);
+}
+
+#[test]
+fn should_name_the_same_scope_regardless_of_tag_order() {
+    let tags = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        state::scope(CollectionId(7), &tags(&["b", "a", "b"]), TagMatch::Any),
+        "collection=7;match=any;tags=a,b"
+    );
+    assert_eq!(
+        state::scope(CollectionId(7), &tags(&["a", "b"]), TagMatch::Any),
+        state::scope(CollectionId(7), &tags(&["b", "a"]), TagMatch::Any)
+    );
+}
+
+#[test]
+fn should_treat_bookmarks_edited_since_the_last_sync_as_outdated() {
+    let synced = record(1, at(2));
+    let mut moved = bookmark(1, at(2));
+    moved.link = "https://example.com/moved".to_string();
+
+    assert!(!synced.is_outdated(&bookmark(1, at(2))));
+    assert!(synced.is_outdated(&bookmark(1, at(4))));
+    assert!(synced.is_outdated(&moved));
+}
+
+#[test]
+fn should_remember_notebook_shards_per_scope() {
+    let dir = state_dir("notebook");
+    let research = state::scope(CollectionId::ALL, &["research".to_string()], TagMatch::All);
+    let shard = |shard: usize, id: &str| NotebookShard {
+        shard,
+        notebook_id: id.to_string(),
+        title: format!("Research ({}/2)", shard + 1),
+    };
+    let store = StateStore::open(&dir).unwrap();
+
+    assert_eq!(store.notebooks(&research).unwrap(), vec![]);
+    store.save_notebook(&research, &shard(1, "nb-2")).unwrap();
+    store.save_notebook(&research, &shard(0, "nb-1")).unwrap();
+    drop(store);
+
+    let store = StateStore::open(&dir).unwrap();
+    assert_eq!(
+        store.notebooks(&research).unwrap(),
+        vec![shard(0, "nb-1"), shard(1, "nb-2")]
+    );
+}
+
+#[test]
+fn should_count_attempts_for_failures_recorded_again_until_cleared() {
+    let dir = state_dir("failures");
+    let research = state::scope(CollectionId::ALL, &["research".to_string()], TagMatch::All);
+    let failure = |class, http_status, day| FailedFetch {
+        bookmark_id: BookmarkId(1),
+        link: "https://example.com/1".to_string(),
+        title: "Bookmark 1".to_string(),
+        last_update: at(1),
+        selection: "#research".to_string(),
+        class,
+        http_status,
+        error: "the page could not be fetched".to_string(),
+        attempts: 1,
+        first_failed_at: at(day),
+        last_failed_at: at(day),
+    };
+    let store = StateStore::open(&dir).unwrap();
+
+    store
+        .record_failure(&research, &failure(FailureClass::ServerError, Some(503), 2))
+        .unwrap();
+    store
+        .record_failure(&research, &failure(FailureClass::Timeout, None, 3))
+        .unwrap();
+    drop(store);
+
+    let store = StateStore::open(&dir).unwrap();
+    let failures = store.failures().unwrap();
+    assert_eq!(
+        failures,
+        vec![(
+            research.clone(),
+            FailedFetch {
+                attempts: 2,
+                first_failed_at: at(2),
+                ..failure(FailureClass::Timeout, None, 3)
+            }
+        )]
+    );
+    store.clear_failure(&research, BookmarkId(1)).unwrap();
+    assert_eq!(store.failures().unwrap(), vec![]);
+}
+
+#[test]
+fn should_upgrade_database_created_before_sharding() {
+    let dir = state_dir("migrate");
+    let old = rusqlite::Connection::open(dir.join(state::STATE_FILE)).unwrap();
+    old.execute_batch(
+        "CREATE TABLE bookmarks (
+             scope TEXT NOT NULL, bookmark_id INTEGER NOT NULL, link TEXT NOT NULL,
+             title TEXT NOT NULL, last_update TEXT NOT NULL, content_hash TEXT NOT NULL,
+             source_id TEXT, synced_at TEXT NOT NULL, PRIMARY KEY (scope, bookmark_id));
+         CREATE TABLE notebooks (
+             scope TEXT NOT NULL PRIMARY KEY, notebook_id TEXT NOT NULL, title TEXT NOT NULL);
+         INSERT INTO bookmarks VALUES
+             ('s', 1, 'https://example.com/1', 'Bookmark 1', '2024-01-02T00:00:00Z', 'h', 'src-1',
+              '2024-01-05T00:00:00Z');
+         INSERT INTO notebooks VALUES ('s', 'nb-1', 'Research');
+         PRAGMA user_version = 1;",
+    )
+    .unwrap();
+    drop(old);
+
+    let store = StateStore::open(&dir).unwrap();
+
+    let records = store.records("s").unwrap();
+    assert_eq!((records[0].shard, records[0].word_count), (0, 0));
+    assert_eq!(
+        store.notebooks("s").unwrap(),
+        vec![NotebookShard {
+            shard: 0,
+            notebook_id: "nb-1".to_string(),
+            title: "Research".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn should_reopen_a_current_database_without_recreating_dropped_tables() {
+    let dir = state_dir("reopen");
+    StateStore::open(&dir)
+        .unwrap()
+        .save("s", &record(1, at(2)))
+        .unwrap();
+    let tables = || {
+        let connection = rusqlite::Connection::open(dir.join(state::STATE_FILE)).unwrap();
+        let mut statement = connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let names = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap();
+        names.collect::<rusqlite::Result<Vec<_>>>().unwrap()
+    };
+    let expected = ["bookmarks", "failed_fetches", "notebook_shards"];
+    assert_eq!(tables(), expected);
+
+    let store = StateStore::open(&dir).unwrap();
+    drop(StateStore::open(&dir).unwrap());
+
+    assert_eq!(store.records("s").unwrap(), vec![record(1, at(2))]);
+    assert_eq!(tables(), expected);
+}
@@ -0,0 +1,139 @@
+use std::process::Command;
+
+use mockito::{Matcher, Server};
+use raindrop_notebooklm_integration::config::{Config, ConfigBuilder, DEFAULT_PROFILE};
+use raindrop_notebooklm_integration::error::exit_code;
+use raindrop_notebooklm_integration::status::{self, CheckOutcome};
+use serde_json::json;
+
+fn config_for(server_url: &str) -> Config {
+    let env = [
+        ("RAINDROP_TOKEN", "raindrop-token"),
+        ("RAINDROP_API_URL", server_url),
+        ("NOTEBOOKLM_ACCESS_TOKEN", "notebooklm-token"),
+        ("NOTEBOOKLM_ENDPOINT", server_url),
+        ("NOTEBOOKLM_PROJECT", "123456"),
+    ]
+    .map(|(key, value)| (key.to_string(), value.to_string()));
+    ConfigBuilder::new()
+        .with_env(env)
+        .unwrap()
+        .build(DEFAULT_PROFILE)
+        .unwrap()
+}
+
+async fn mock_raindrop_user(server: &mut Server) {
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_header("x-ratelimit-limit", "120")
+        .with_header("ratelimit-remaining", "117")
+        .with_body(json!({ "result": true, "user": { "_id": 1, "fullName": "Ada" } }).to_string())
+        .create_async()
+        .await;
+}
+
+#[tokio::test]
+async fn should_report_account_and_rate_limit_when_both_services_are_healthy() {
+    let mut server = Server::new_async().await;
+    mock_raindrop_user(&mut server).await;
+    server
+        .mock(
+            "GET",
+            "/v1alpha/projects/123456/locations/global/notebooks:listRecentlyViewed",
+        )
+        .match_query(Matcher::UrlEncoded("pageSize".into(), "1".into()))
+        .with_body(json!({ "notebooks": [] }).to_string())
+        .create_async()
+        .await;
+
+    let report = status::check(&config_for(&server.url())).await;
+
+    assert_eq!(report.exit_code(), exit_code::OK);
+    match &report.services[0].outcome {
+        CheckOutcome::Ok {
+            account,
+            rate_limit,
+        } => {
+            assert_eq!(account, "Ada");
+            let rate_limit = rate_limit.as_ref().unwrap();
+            assert_eq!(
+                (rate_limit.remaining, rate_limit.limit),
+                (Some(117), Some(120))
+            );
+        }
+        other => panic!("unexpected outcome {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn should_exit_with_notebooklm_auth_code_when_credentials_are_rejected() {
+    let mut server = Server::new_async().await;
+    mock_raindrop_user(&mut server).await;
+    server
+        .mock(
+            "GET",
+            "/v1alpha/projects/123456/locations/global/notebooks:listRecentlyViewed",
+        )
+        .match_query(Matcher::Any)
+        .with_status(401)
+        .create_async()
+        .await;
+
+    let report = status::check(&config_for(&server.url())).await;
+
+    assert!(report.services[1].reachable());
+    assert!(!report.services[1].authenticated());
+    assert_eq!(report.exit_code(), exit_code::NOTEBOOKLM_UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn should_exit_with_raindrop_unreachable_code_when_nothing_listens() {
+    let config = config_for("http://127.0.0.1:9");
+
+    let report = status::check(&config).await;
+
+    assert!(!report.services[0].reachable());
+    assert_eq!(report.exit_code(), exit_code::RAINDROP_UNREACHABLE);
+}
+
+#[test]
+fn should_stream_one_event_per_line_with_ndjson_output() {
+    let workdir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-status-ndjson-test-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&workdir).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_raindrop-notebooklm-integration"))
+        .args(["status", "--output", "ndjson"])
+        .current_dir(&workdir)
+        .env_clear()
+        .env("HOME", &workdir)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(i32::from(exit_code::CONFIG)));
+    let events = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let names = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "run_started",
+            "service_checked",
+            "service_checked",
+            "run_finished"
+        ]
+    );
+    assert_eq!(events[0]["command"], "status");
+    assert_eq!(events[1]["service"], "raindrop");
+    assert_eq!(events[1]["state"], "not_configured");
+    assert_eq!(events[3]["exit_code"], exit_code::CONFIG);
+    assert!(events.iter().all(|event| event["profile"] == "default"));
+}
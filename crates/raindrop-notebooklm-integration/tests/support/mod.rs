@@ -0,0 +1,117 @@
+//! A local HTTP server that answers every request after a fixed delay and
+//! records how many requests it was serving at once. Shared by the pipeline
+//! tests and benchmark.
+
+// Each of them uses only part of it.
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub struct SlowServer {
+    pub url: String,
+    in_flight: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+    served: Arc<AtomicUsize>,
+}
+
+impl SlowServer {
+    /// `/missing` answers 404; every other path is a small article. Paths
+    /// under `/slow/` take ten times as long.
+    pub fn start(latency: Duration) -> SlowServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = SlowServer {
+            url,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            peak: Arc::new(AtomicUsize::new(0)),
+            served: Arc::new(AtomicUsize::new(0)),
+        };
+        let counters = (
+            server.in_flight.clone(),
+            server.peak.clone(),
+            server.served.clone(),
+        );
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (in_flight, peak, served) = counters.clone();
+                thread::spawn(move || {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    let path = request_path(&stream);
+                    if path.starts_with("/slow/") {
+                        thread::sleep(latency * 10);
+                    } else {
+                        thread::sleep(latency);
+                    }
+                    // Counted out before answering, so the client can never
+                    // start its next request while this one still counts.
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    served.fetch_add(1, Ordering::SeqCst);
+                    respond(stream, &path);
+                });
+            }
+        });
+        server
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    /// The most requests served at the same time so far.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    pub fn served(&self) -> usize {
+        self.served.load(Ordering::SeqCst)
+    }
+
+    pub fn reset(&self) {
+        self.peak.store(0, Ordering::SeqCst);
+        self.served.store(0, Ordering::SeqCst);
+    }
+}
+
+fn request_path(stream: &TcpStream) -> String {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap_or_default();
+    let mut header = String::new();
+    while reader.read_line(&mut header).unwrap_or_default() > 2 {
+        header.clear();
+    }
+    request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("/")
+        .to_string()
+}
+
+fn respond(mut stream: TcpStream, path: &str) {
+    let (status, body) = if path == "/missing" {
+        ("404 Not Found", String::new())
+    } else {
+        (
+            "200 OK",
+            format!(
+                "<html><head><title>{0}</title></head><body>\
+                 <p>The article at {0} is long enough to count as a body.</p>\
+                 </body></html>",
+                path
+            ),
+        )
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
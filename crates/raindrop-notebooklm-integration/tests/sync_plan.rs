@@ -0,0 +1,863 @@
+mod support;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use mockito::{Matcher, Server};
+use raindrop_notebooklm_integration::content::ContentFetcher;
+use raindrop_notebooklm_integration::error::exit_code;
+use raindrop_notebooklm_integration::events::{EventKind, Events, Stage};
+use raindrop_notebooklm_integration::ledger::{FailedFetch, FailureClass};
+use raindrop_notebooklm_integration::notebooklm::{self, NotebookLMClient};
+use raindrop_notebooklm_integration::pipeline::FetchLimits;
+use raindrop_notebooklm_integration::plan::{ChangeKind, ExecutionPlan, SkipReason};
+use raindrop_notebooklm_integration::raindrop::{
+    BookmarkId, CollectionId, RaindropClient, TagMatch,
+};
+use raindrop_notebooklm_integration::shard::ShardLimits;
+use raindrop_notebooklm_integration::snapshot::{Outcome, Snapshot};
+use raindrop_notebooklm_integration::state::{self, BookmarkRecord, NotebookShard, StateStore};
+use raindrop_notebooklm_integration::sync::{self, ApplyOptions, RetryOptions, SyncOptions};
+use serde_json::json;
+use support::SlowServer;
+
+const NOTEBOOKS: &str = "/v1alpha/projects/123456/locations/global/notebooks";
+
+fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "raindrop-notebooklm-sync-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn options(dir: &Path, dry_run: bool) -> SyncOptions {
+    SyncOptions {
+        profile: "default".to_string(),
+        tags: vec!["research".to_string()],
+        tag_match: TagMatch::All,
+        collection: CollectionId::ALL,
+        max_urls: None,
+        output_dir: dir.to_path_buf(),
+        state_dir: dir.join("state"),
+        dry_run,
+        prune: false,
+        notebook_title: Some("Research".to_string()),
+        shard_limits: ShardLimits {
+            max_sources: notebooklm::DEFAULT_MAX_SOURCES,
+            max_words: notebooklm::DEFAULT_MAX_WORDS,
+        },
+        fetch_limits: FetchLimits {
+            host_delay: Duration::ZERO,
+            ..FetchLimits::default()
+        },
+        config: BTreeMap::from([("profile".to_string(), Some("default".to_string()))]),
+        events: Events::default(),
+    }
+}
+
+/// Applies plans made with `options`.
+fn apply_options(options: &SyncOptions) -> ApplyOptions {
+    ApplyOptions {
+        output_dir: options.output_dir.clone(),
+        state_dir: options.state_dir.clone(),
+        config: options.config.clone(),
+        events: Events::default(),
+    }
+}
+
+fn raindrop_item(id: u64, link: String, created: &str) -> serde_json::Value {
+    json!({
+        "_id": id,
+        "title": format!("Bookmark {}", id),
+        "link": link,
+        "excerpt": "",
+        "note": "",
+        "tags": ["research"],
+        "created": created,
+        "lastUpdate": created,
+        "collection": { "$id": 42 },
+        "domain": "127.0.0.1"
+    })
+}
+
+/// Raindrop lists three bookmarks: an article, the same article saved
+/// again, and a page that is gone.
+async fn mock_raindrop(server: &mut Server) {
+    mock_listing(server).await;
+    server
+        .mock("GET", "/gone")
+        .with_status(404)
+        .create_async()
+        .await;
+}
+
+/// The bookmarks and the article, leaving `/gone` to the test.
+async fn mock_listing(server: &mut Server) {
+    let article = format!("{}/article", server.url());
+    let gone = format!("{}/gone", server.url());
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_body(json!({ "result": true, "user": { "_id": 1, "fullName": "Ada" } }).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(Matcher::Any)
+        .with_body(
+            json!({
+                "result": true,
+                "count": 3,
+                "items": [
+                    raindrop_item(1, article.clone(), "2024-01-03T00:00:00.000Z"),
+                    raindrop_item(2, article, "2024-01-01T00:00:00.000Z"),
+                    raindrop_item(3, gone, "2024-01-02T00:00:00.000Z")
+                ]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/article")
+        .with_header("content-type", "text/html")
+        .with_body(
+            "<html><head><title>Article</title></head><body>\
+             <p>This paragraph is long enough to count as the article body.</p>\
+             </body></html>",
+        )
+        .create_async()
+        .await;
+}
+
+fn clients(server: &Server) -> (RaindropClient, NotebookLMClient) {
+    (
+        RaindropClient::new("raindrop-token").with_base_url(server.url()),
+        NotebookLMClient::new("notebooklm-token", "123456")
+            .with_endpoint(server.url())
+            .with_polling(Duration::from_millis(10), Duration::from_secs(5)),
+    )
+}
+
+#[tokio::test]
+async fn should_plan_additions_and_list_skipped_bookmarks_with_reasons() {
+    let mut server = Server::new_async().await;
+    mock_raindrop(&mut server).await;
+    let (raindrop, _) = clients(&server);
+    let dir = output_dir("dry-run");
+
+    let plan = sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        None,
+        &options(&dir, true),
+    )
+    .await
+    .unwrap()
+    .plan;
+
+    assert!(!plan.checked_sources);
+    assert_eq!(plan.changes.len(), 1);
+    assert_eq!(plan.changes[0].kind, ChangeKind::Add);
+    assert_eq!(plan.changes[0].title, "Article");
+    assert!(plan.changes[0].content.is_some());
+    assert_eq!(plan.notebooks.len(), 1);
+    assert_eq!(plan.notebooks[0].title, "Research");
+    assert_eq!(plan.notebooks[0].notebook_id, None);
+    let reasons = plan
+        .skipped
+        .iter()
+        .map(|skipped| (skipped.bookmark_id.0, &skipped.reason))
+        .collect::<Vec<_>>();
+    assert_eq!(reasons[0], (2, &SkipReason::Duplicate));
+    assert!(matches!(reasons[1], (3, SkipReason::FetchFailed { .. })));
+    assert!(plan.check_fetched().is_err());
+
+    let rendered = plan.to_string();
+    assert!(rendered.contains("+ \"Research\": 1 sources"));
+    assert!(rendered.contains("+ add     Article <"));
+    assert!(rendered.contains("Plan: 1 to add, 0 to replace, 0 to refresh, 0 to remove"));
+    // A dry run leaves no trace in the sync state.
+    let state = StateStore::open(&dir.join("state")).unwrap();
+    assert!(state.records(&plan.scope).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn should_leave_a_snapshot_of_the_collected_data() {
+    let mut server = Server::new_async().await;
+    mock_raindrop(&mut server).await;
+    let (raindrop, _) = clients(&server);
+    let dir = output_dir("snapshot");
+
+    sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        None,
+        &options(&dir, true),
+    )
+    .await
+    .unwrap();
+
+    let text = std::fs::read_to_string(dir.join("sync-default.json")).unwrap();
+    let snapshot = serde_json::from_str::<Snapshot>(&text).unwrap();
+    assert!(snapshot.run.dry_run);
+    assert_eq!(snapshot.run.selection, "#research");
+    assert_eq!(snapshot.config["profile"].as_deref(), Some("default"));
+    let outcomes = snapshot
+        .items
+        .iter()
+        .map(|item| (item.bookmark_id.0, &item.outcome))
+        .collect::<Vec<_>>();
+    assert_eq!(outcomes[0], (1, &Outcome::Added));
+    assert!(matches!(outcomes[1], (2, Outcome::Skipped { .. })));
+    assert!(matches!(outcomes[2], (3, Outcome::Skipped { .. })));
+    let article = snapshot.items[0].content.as_ref().unwrap();
+    assert_eq!(article.title, "Article");
+    // The text was set aside while the run went on and is back in place.
+    assert!(article.markdown.contains("long enough to count"));
+    assert!(article.text.contains("long enough to count"));
+    let spooled = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().ends_with(".pages")
+        })
+        .count();
+    assert_eq!(spooled, 0);
+    assert_eq!(
+        snapshot.items[0].bookmark.as_ref().unwrap().tags,
+        ["research"]
+    );
+}
+
+#[tokio::test]
+async fn should_report_listed_fetched_and_uploaded_bookmarks_as_events() {
+    let mut server = Server::new_async().await;
+    mock_raindrop(&mut server).await;
+    server
+        .mock("POST", NOTEBOOKS)
+        .with_body(json!({ "notebookId": "nb-1", "title": "Research" }).to_string())
+        .create_async()
+        .await;
+    server
+        .mock(
+            "POST",
+            format!("{}/nb-1/sources:batchCreate", NOTEBOOKS).as_str(),
+        )
+        .with_body(
+            json!({ "sources": [{ "sourceId": { "id": "src-1" }, "title": "Article" }] })
+                .to_string(),
+        )
+        .create_async()
+        .await;
+    let (raindrop, notebooklm) = clients(&server);
+    let dir = output_dir("events");
+    let (events, mut receiver) = Events::channel();
+    let options = SyncOptions {
+        events: events.with_profile("default"),
+        ..options(&dir, false)
+    };
+
+    sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        Some(&notebooklm),
+        &options,
+    )
+    .await
+    .unwrap();
+    drop(options);
+
+    let mut received = Vec::new();
+    while let Some(event) = receiver.recv().await {
+        received.push(event);
+    }
+    assert!(received.iter().all(|event| event.profile == "default"));
+    let kinds = received
+        .into_iter()
+        .map(|event| event.kind)
+        .collect::<Vec<_>>();
+    let listed = kinds
+        .iter()
+        .filter_map(|kind| match kind {
+            EventKind::BookmarkListed { bookmark_id, .. } => Some(bookmark_id.0),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(listed, [1, 2, 3]);
+    // Pages are reported as their downloads complete, in any order.
+    let mut fetched = kinds
+        .iter()
+        .filter_map(|kind| match kind {
+            EventKind::FetchSucceeded { bookmark_id, .. } => Some((bookmark_id.0, None)),
+            EventKind::FetchFailed {
+                bookmark_id, class, ..
+            } => Some((bookmark_id.0, Some(*class))),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    fetched.sort();
+    assert_eq!(fetched, [(1, None), (3, Some(FailureClass::ClientError))]);
+    let uploaded = kinds
+        .iter()
+        .filter_map(|kind| match kind {
+            EventKind::SourceUploaded {
+                bookmark_id,
+                notebook_id,
+                source_id,
+                bytes,
+                ..
+            } => Some((
+                *bookmark_id,
+                notebook_id.as_str(),
+                source_id.as_str(),
+                *bytes,
+            )),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(uploaded.len(), 1);
+    assert_eq!(uploaded[0].0, BookmarkId(1));
+    assert_eq!((uploaded[0].1, uploaded[0].2), ("nb-1", "src-1"));
+    assert!(uploaded[0].3 > 0);
+    // The stages overlap, so none of them knows its total when it starts.
+    let stages = kinds
+        .iter()
+        .filter_map(|kind| match kind {
+            EventKind::StageStarted { stage, total } => Some((*stage, *total)),
+            EventKind::StageTotal { stage, total } => Some((*stage, Some(*total))),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        stages,
+        [
+            (Stage::Listing, None),
+            (Stage::Fetching, None),
+            (Stage::Uploading, None),
+            (Stage::Fetching, Some(2)),
+            (Stage::Uploading, Some(1))
+        ]
+    );
+    let finished = kinds
+        .iter()
+        .filter_map(|kind| match kind {
+            EventKind::StageFinished { stage } => Some(*stage),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        finished,
+        [Stage::Listing, Stage::Fetching, Stage::Uploading]
+    );
+}
+
+#[tokio::test]
+async fn should_apply_saved_plan_exactly_once() {
+    let mut server = Server::new_async().await;
+    mock_raindrop(&mut server).await;
+    let create = server
+        .mock("POST", NOTEBOOKS)
+        .match_body(Matcher::Json(json!({ "title": "Research" })))
+        .with_body(json!({ "notebookId": "nb-1", "title": "Research" }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let upload = server
+        .mock(
+            "POST",
+            format!("{}/nb-1/sources:batchCreate", NOTEBOOKS).as_str(),
+        )
+        .match_body(Matcher::Regex(
+            r#""textContent":\{"sourceName":"Article""#.to_string(),
+        ))
+        .with_body(
+            json!({ "sources": [{ "sourceId": { "id": "src-1" }, "title": "Article" }] })
+                .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let (raindrop, notebooklm) = clients(&server);
+    let dir = output_dir("apply");
+    let options = options(&dir, true);
+
+    let plan = sync::run(&raindrop, &ContentFetcher::new(), None, &options)
+        .await
+        .unwrap()
+        .plan;
+    let saved = dir.join("plan.json");
+    plan.save(&saved).unwrap();
+    let plan = ExecutionPlan::load(&saved).unwrap();
+    let (events, mut receiver) = Events::channel();
+    let apply = ApplyOptions {
+        events,
+        ..apply_options(&options)
+    };
+    let run = sync::apply_plan(plan.clone(), &notebooklm, &apply)
+        .await
+        .unwrap();
+
+    create.assert_async().await;
+    upload.assert_async().await;
+    let manifest = std::fs::read_to_string(options.state_dir.join("manifest.json")).unwrap();
+    assert!(manifest.contains("\"src-1\""));
+    // The run is reported like any other sync.
+    let text = std::fs::read_to_string(&run.snapshot).unwrap();
+    let snapshot = serde_json::from_str::<Snapshot>(&text).unwrap();
+    assert!(!snapshot.run.dry_run);
+    let outcomes = snapshot
+        .items
+        .iter()
+        .map(|item| (item.bookmark_id.0, &item.outcome, item.source_id.as_deref()))
+        .collect::<Vec<_>>();
+    assert_eq!(outcomes[0], (1, &Outcome::Added, Some("src-1")));
+    assert!(matches!(outcomes[1], (2, Outcome::Skipped { .. }, None)));
+    assert!(run.summary.contains("src-1"));
+    assert!(run.summary_path.exists());
+    drop(apply);
+    let mut uploaded = Vec::new();
+    while let Some(event) = receiver.recv().await {
+        if let EventKind::SourceUploaded { bookmark_id, .. } = event.kind {
+            uploaded.push(bookmark_id.0);
+        }
+    }
+    assert_eq!(uploaded, [1]);
+
+    // The state moved on, so the same plan must not run a second time.
+    let error = sync::apply_plan(plan, &notebooklm, &apply_options(&options))
+        .await
+        .unwrap_err();
+    assert!(error.chain().contains("create a new plan"));
+}
+
+#[tokio::test]
+async fn should_record_failed_fetches_and_upload_them_when_retried() {
+    let mut server = Server::new_async().await;
+    mock_listing(&mut server).await;
+    server
+        .mock("GET", "/gone")
+        .with_status(404)
+        .expect(1)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/gone")
+        .with_header("content-type", "text/html")
+        .with_body(
+            "<html><head><title>Back again</title></head><body>\
+             <p>The page came back after a while and can be fetched now.</p>\
+             </body></html>",
+        )
+        .create_async()
+        .await;
+    server
+        .mock("POST", NOTEBOOKS)
+        .with_body(json!({ "notebookId": "nb-1", "title": "Research" }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let mut upload = |title: &str, source: &str| {
+        server
+            .mock(
+                "POST",
+                format!("{}/nb-1/sources:batchCreate", NOTEBOOKS).as_str(),
+            )
+            .match_body(Matcher::Regex(format!(
+                r#""textContent":\{{"sourceName":"{}""#,
+                title
+            )))
+            .with_body(
+                json!({ "sources": [{ "sourceId": { "id": source }, "title": title }] })
+                    .to_string(),
+            )
+            .expect(1)
+            .create_async()
+    };
+    let article = upload("Article", "src-1").await;
+    let retried = upload("Back again", "src-2").await;
+    let (raindrop, notebooklm) = clients(&server);
+    let dir = output_dir("retry-failed");
+    let options = options(&dir, false);
+
+    let plan = sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        Some(&notebooklm),
+        &options,
+    )
+    .await
+    .unwrap()
+    .plan;
+
+    assert!(plan.check_fetched().is_err());
+    let store = StateStore::open(&options.state_dir).unwrap();
+    let failures = store.failures().unwrap();
+    assert_eq!(failures.len(), 1);
+    let (scope, failure) = &failures[0];
+    assert_eq!(scope, &plan.scope);
+    assert_eq!(failure.bookmark_id.0, 3);
+    assert_eq!(failure.class, FailureClass::ClientError);
+    assert_eq!(failure.http_status, Some(404));
+    assert_eq!(failure.attempts, 1);
+    assert_eq!(failure.selection, "#research");
+
+    let mut retry = RetryOptions {
+        profile: options.profile.clone(),
+        classes: vec![FailureClass::ServerError],
+        state_dir: options.state_dir.clone(),
+        dry_run: false,
+        notebook_title: options.notebook_title.clone(),
+        shard_limits: options.shard_limits,
+        fetch_limits: options.fetch_limits,
+    };
+    let plans = sync::retry_failed(&ContentFetcher::new(), Some(&notebooklm), &retry)
+        .await
+        .unwrap();
+    assert!(plans.is_empty());
+
+    retry.classes = vec![FailureClass::ClientError];
+    let plans = sync::retry_failed(&ContentFetcher::new(), Some(&notebooklm), &retry)
+        .await
+        .unwrap();
+
+    assert_eq!(plans.len(), 1);
+    assert_eq!(plans[0].changes.len(), 1);
+    assert_eq!(plans[0].changes[0].kind, ChangeKind::Add);
+    assert_eq!(plans[0].changes[0].bookmark_id.0, 3);
+    plans[0].check_fetched().unwrap();
+    article.assert_async().await;
+    retried.assert_async().await;
+    assert!(store.failures().unwrap().is_empty());
+    let records = store.records(&plan.scope).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1].source_id.as_deref(), Some("src-2"));
+}
+
+#[tokio::test]
+async fn should_record_a_failed_upload_and_carry_on() {
+    let mut server = Server::new_async().await;
+    mock_raindrop(&mut server).await;
+    server
+        .mock("POST", NOTEBOOKS)
+        .with_body(json!({ "notebookId": "nb-1", "title": "Research" }).to_string())
+        .create_async()
+        .await;
+    server
+        .mock(
+            "POST",
+            format!("{}/nb-1/sources:batchCreate", NOTEBOOKS).as_str(),
+        )
+        .with_status(500)
+        .create_async()
+        .await;
+    let (raindrop, notebooklm) = clients(&server);
+    let dir = output_dir("upload-failed");
+    let options = options(&dir, false);
+
+    let plan = sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        Some(&notebooklm),
+        &options,
+    )
+    .await
+    .unwrap()
+    .plan;
+
+    assert!(plan.changes.is_empty());
+    let reasons = plan
+        .skipped
+        .iter()
+        .map(|skipped| (skipped.bookmark_id.0, &skipped.reason))
+        .collect::<Vec<_>>();
+    assert!(matches!(
+        reasons[2],
+        (
+            1,
+            SkipReason::UploadFailed {
+                class: FailureClass::ServerError,
+                http_status: Some(500),
+                ..
+            }
+        )
+    ));
+    assert!(plan.check_fetched().is_err());
+    let store = StateStore::open(&options.state_dir).unwrap();
+    let failed = store
+        .failures()
+        .unwrap()
+        .into_iter()
+        .map(|(_, failure)| (failure.bookmark_id.0, failure.class))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        failed,
+        [
+            (1, FailureClass::ServerError),
+            (3, FailureClass::ClientError)
+        ]
+    );
+    assert!(store.records(&plan.scope).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn should_record_failed_fetches_even_if_uploading_stops() {
+    let mut server = Server::new_async().await;
+    mock_raindrop(&mut server).await;
+    server
+        .mock("POST", NOTEBOOKS)
+        .with_status(401)
+        .create_async()
+        .await;
+    let (raindrop, notebooklm) = clients(&server);
+    let dir = output_dir("upload-stopped");
+    let options = options(&dir, false);
+
+    let error = sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        Some(&notebooklm),
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(error.exit_code(), exit_code::NOTEBOOKLM_UNAUTHORIZED);
+    let store = StateStore::open(&options.state_dir).unwrap();
+    let failures = store.failures().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].1.bookmark_id.0, 3);
+}
+
+#[tokio::test]
+async fn should_prune_synced_bookmarks_and_forget_their_failures_once_none_of_them_match() {
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_body(json!({ "result": true, "user": { "_id": 1, "fullName": "Ada" } }).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(Matcher::Any)
+        .with_body(json!({ "result": true, "count": 0, "items": [] }).to_string())
+        .create_async()
+        .await;
+    server
+        .mock("GET", format!("{}/nb-1", NOTEBOOKS).as_str())
+        .with_body(
+            json!({
+                "notebookId": "nb-1",
+                "title": "Research",
+                "sources": [{ "sourceId": { "id": "src-1" }, "title": "Article" }]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let delete = server
+        .mock(
+            "POST",
+            format!("{}/nb-1/sources:batchDelete", NOTEBOOKS).as_str(),
+        )
+        .with_body("{}")
+        .expect(1)
+        .create_async()
+        .await;
+    let (raindrop, notebooklm) = clients(&server);
+    let dir = output_dir("prune-all");
+    let options = SyncOptions {
+        prune: true,
+        ..options(&dir, false)
+    };
+    std::fs::create_dir_all(&options.state_dir).unwrap();
+    let store = StateStore::open(&options.state_dir).unwrap();
+    let scope = state::scope(CollectionId::ALL, &options.tags, options.tag_match);
+    store
+        .save(
+            &scope,
+            &BookmarkRecord {
+                id: BookmarkId(1),
+                link: format!("{}/article", server.url()),
+                title: "Article".to_string(),
+                last_update: Utc::now(),
+                content_hash: "hash".to_string(),
+                source_id: Some("src-1".to_string()),
+                shard: 0,
+                word_count: 11,
+                synced_at: Utc::now(),
+            },
+        )
+        .unwrap();
+    store
+        .save_notebook(
+            &scope,
+            &NotebookShard {
+                shard: 0,
+                notebook_id: "nb-1".to_string(),
+                title: "Research".to_string(),
+            },
+        )
+        .unwrap();
+    // The synced bookmark failed to update once, and another one never
+    // made it in before it lost its tag.
+    for id in [1, 7] {
+        store
+            .record_failure(
+                &scope,
+                &FailedFetch {
+                    bookmark_id: BookmarkId(id),
+                    link: format!("{}/gone", server.url()),
+                    title: format!("Bookmark {}", id),
+                    last_update: Utc::now(),
+                    selection: "#research".to_string(),
+                    class: FailureClass::ServerError,
+                    http_status: Some(503),
+                    error: "server error".to_string(),
+                    attempts: 1,
+                    first_failed_at: Utc::now(),
+                    last_failed_at: Utc::now(),
+                },
+            )
+            .unwrap();
+    }
+
+    let plan = sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        Some(&notebooklm),
+        &options,
+    )
+    .await
+    .unwrap()
+    .plan;
+
+    assert_eq!(plan.changes.len(), 1);
+    assert_eq!(plan.changes[0].kind, ChangeKind::Remove);
+    delete.assert_async().await;
+    assert!(store.records(&scope).unwrap().is_empty());
+    // Neither is retried into the notebook.
+    assert!(store.failures().unwrap().is_empty());
+
+    // Without anything left to remove, an empty selection is an error again.
+    let error = sync::run(
+        &raindrop,
+        &ContentFetcher::new(),
+        Some(&notebooklm),
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(error.chain().contains("no bookmarks found"));
+}
+
+#[tokio::test]
+async fn should_not_place_new_bookmarks_in_shards_filled_by_bookmarks_beyond_max_urls() {
+    let mut server = Server::new_async().await;
+    mock_raindrop(&mut server).await;
+    let (raindrop, _) = clients(&server);
+    let dir = output_dir("placed-beyond-limit");
+    let options = SyncOptions {
+        max_urls: Some(1),
+        shard_limits: ShardLimits {
+            max_sources: 1,
+            max_words: notebooklm::DEFAULT_MAX_WORDS,
+        },
+        ..options(&dir, true)
+    };
+    std::fs::create_dir_all(&options.state_dir).unwrap();
+    let store = StateStore::open(&options.state_dir).unwrap();
+    let scope = state::scope(CollectionId::ALL, &options.tags, options.tag_match);
+    // The older bookmark was synced before and now falls past `max_urls`.
+    store
+        .save(
+            &scope,
+            &BookmarkRecord {
+                id: BookmarkId(3),
+                link: format!("{}/gone", server.url()),
+                title: "Bookmark 3".to_string(),
+                last_update: Utc::now(),
+                content_hash: "hash".to_string(),
+                source_id: Some("src-3".to_string()),
+                shard: 0,
+                word_count: 11,
+                synced_at: Utc::now(),
+            },
+        )
+        .unwrap();
+
+    let plan = sync::run(&raindrop, &ContentFetcher::new(), None, &options)
+        .await
+        .unwrap()
+        .plan;
+
+    assert_eq!(plan.changes.len(), 1);
+    assert_eq!(plan.changes[0].bookmark_id, BookmarkId(1));
+    assert_eq!(plan.changes[0].shard, 1);
+}
+
+#[tokio::test]
+async fn should_place_new_bookmarks_in_listing_order_however_the_downloads_complete() {
+    let pages = SlowServer::start(Duration::from_millis(20));
+    let mut server = Server::new_async().await;
+    server
+        .mock("GET", "/rest/v1/user")
+        .with_body(json!({ "result": true, "user": { "_id": 1, "fullName": "Ada" } }).to_string())
+        .create_async()
+        .await;
+    // The newer bookmark is listed first but its page comes in last.
+    server
+        .mock("GET", "/rest/v1/raindrops/0")
+        .match_query(Matcher::Any)
+        .with_body(
+            json!({
+                "result": true,
+                "count": 2,
+                "items": [
+                    raindrop_item(1, pages.url("/slow/first"), "2024-01-02T00:00:00.000Z"),
+                    raindrop_item(2, pages.url("/second"), "2024-01-01T00:00:00.000Z")
+                ]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let (raindrop, _) = clients(&server);
+    let dir = output_dir("listing-order");
+    let options = SyncOptions {
+        shard_limits: ShardLimits {
+            max_sources: 1,
+            max_words: notebooklm::DEFAULT_MAX_WORDS,
+        },
+        ..options(&dir, true)
+    };
+
+    let plan = sync::run(&raindrop, &ContentFetcher::new(), None, &options)
+        .await
+        .unwrap()
+        .plan;
+
+    let placed = plan
+        .changes
+        .iter()
+        .map(|change| (change.bookmark_id.0, change.shard))
+        .collect::<Vec<_>>();
+    assert_eq!(placed, vec![(1, 0), (2, 1)]);
+}
+
+#[test]
+fn should_reject_plan_files_of_another_format_version() {
+    let dir = output_dir("version");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("plan.json");
+    std::fs::write(&path, json!({ "version": 99 }).to_string()).unwrap();
+
+    let error = ExecutionPlan::load(&path).unwrap_err();
+    assert!(error
+        .chain()
+        .contains("plan format version 99 is not supported"));
+}